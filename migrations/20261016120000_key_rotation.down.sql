@@ -0,0 +1,2 @@
+ALTER TABLE keys DROP COLUMN retire_at;
+ALTER TABLE keys DROP COLUMN nbf;
@@ -0,0 +1,6 @@
+-- Signing window for each key. A key signs new tokens while nbf <= now < retire_at,
+-- and its public half stays published until exp.
+ALTER TABLE keys ADD COLUMN nbf INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE keys ADD COLUMN retire_at INTEGER NOT NULL DEFAULT 0;
+
+UPDATE keys SET retire_at = exp;
@@ -0,0 +1,10 @@
+-- Lifecycle state of each key: pending, active, retiring, expired or revoked.
+ALTER TABLE keys ADD COLUMN state TEXT NOT NULL DEFAULT 'active'
+    CHECK (state IN ('pending', 'active', 'retiring', 'expired', 'revoked'));
+
+UPDATE keys SET state = CASE
+    WHEN exp <= CAST(strftime('%s', 'now') AS INTEGER) THEN 'expired'
+    WHEN retire_at <= CAST(strftime('%s', 'now') AS INTEGER) THEN 'retiring'
+    WHEN nbf <= CAST(strftime('%s', 'now') AS INTEGER) THEN 'active'
+    ELSE 'pending'
+END;
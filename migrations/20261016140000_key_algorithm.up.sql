@@ -0,0 +1,2 @@
+-- JWS algorithm each key signs with. Keys created before this column are RSA keys.
+ALTER TABLE keys ADD COLUMN alg TEXT NOT NULL DEFAULT 'RS256';
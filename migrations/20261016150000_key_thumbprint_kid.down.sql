@@ -0,0 +1,16 @@
+CREATE TABLE keys_old (
+    kid INTEGER PRIMARY KEY AUTOINCREMENT,
+    key BLOB NOT NULL,
+    exp INTEGER NOT NULL,
+    nbf INTEGER NOT NULL DEFAULT 0,
+    retire_at INTEGER NOT NULL DEFAULT 0,
+    state TEXT NOT NULL DEFAULT 'active'
+        CHECK (state IN ('pending', 'active', 'retiring', 'expired', 'revoked')),
+    alg TEXT NOT NULL DEFAULT 'RS256'
+);
+
+INSERT INTO keys_old (key, exp, nbf, retire_at, state, alg)
+    SELECT key, exp, nbf, retire_at, state, alg FROM keys;
+
+DROP TABLE keys;
+ALTER TABLE keys_old RENAME TO keys;
@@ -0,0 +1,19 @@
+-- Key IDs become RFC 7638 thumbprints instead of autoincrement integers. Existing
+-- integer kids are copied as text; the server relabels them with their thumbprint
+-- on startup, since the thumbprint needs the decrypted key.
+CREATE TABLE keys_new (
+    kid TEXT PRIMARY KEY NOT NULL,
+    key BLOB NOT NULL,
+    exp INTEGER NOT NULL,
+    nbf INTEGER NOT NULL DEFAULT 0,
+    retire_at INTEGER NOT NULL DEFAULT 0,
+    state TEXT NOT NULL DEFAULT 'active'
+        CHECK (state IN ('pending', 'active', 'retiring', 'expired', 'revoked')),
+    alg TEXT NOT NULL DEFAULT 'RS256'
+);
+
+INSERT INTO keys_new (kid, key, exp, nbf, retire_at, state, alg)
+    SELECT CAST(kid AS TEXT), key, exp, nbf, retire_at, state, alg FROM keys;
+
+DROP TABLE keys;
+ALTER TABLE keys_new RENAME TO keys;
@@ -0,0 +1,7 @@
+-- OAuth clients, such as resource servers calling /introspect. Secrets are stored as
+-- bcrypt hashes, like user passwords.
+CREATE TABLE IF NOT EXISTS clients (
+    client_id TEXT PRIMARY KEY NOT NULL,
+    secret_hash TEXT NOT NULL,
+    date_registered TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,14 @@
+-- Revoked tokens, by jti. Each row is only needed until the token would have expired
+-- anyway, so rows are deleted once exp has passed.
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    jti TEXT PRIMARY KEY NOT NULL,
+    exp INTEGER NOT NULL,
+    revoked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Subjects whose tokens were all revoked: every token for sub issued at or before
+-- revoked_before is rejected.
+CREATE TABLE IF NOT EXISTS revoked_subjects (
+    sub TEXT PRIMARY KEY NOT NULL,
+    revoked_before INTEGER NOT NULL
+);
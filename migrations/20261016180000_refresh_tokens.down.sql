@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS refresh_tokens_family_id;
+DROP TABLE IF EXISTS refresh_tokens;
@@ -0,0 +1,15 @@
+-- Refresh tokens, stored as SHA-256 hashes. Every rotation adds a row to the same
+-- family; presenting a token that was already used revokes the whole family.
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    token_hash TEXT PRIMARY KEY NOT NULL,
+    family_id TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    client_id TEXT,
+    expires_at INTEGER NOT NULL,
+    used_at INTEGER,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_id ON refresh_tokens (family_id);
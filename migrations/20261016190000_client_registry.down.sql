@@ -0,0 +1,4 @@
+ALTER TABLE auth_logs DROP COLUMN client_id;
+ALTER TABLE clients DROP COLUMN token_ttl;
+ALTER TABLE clients DROP COLUMN audiences;
+ALTER TABLE clients DROP COLUMN scopes;
@@ -0,0 +1,8 @@
+-- What each client may ask for in the client_credentials grant: space separated
+-- scopes and audiences, and an access token lifetime overriding TOKEN_TTL_SECS.
+ALTER TABLE clients ADD COLUMN scopes TEXT NOT NULL DEFAULT '';
+ALTER TABLE clients ADD COLUMN audiences TEXT NOT NULL DEFAULT '';
+ALTER TABLE clients ADD COLUMN token_ttl INTEGER;
+
+-- Tokens issued to clients rather than users are logged with the client instead.
+ALTER TABLE auth_logs ADD COLUMN client_id TEXT;
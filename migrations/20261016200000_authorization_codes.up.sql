@@ -0,0 +1,20 @@
+-- Space separated redirect URIs a client may use with /authorize. Clients registered
+-- without a secret have an empty secret_hash and are public clients.
+ALTER TABLE clients ADD COLUMN redirect_uris TEXT NOT NULL DEFAULT '';
+
+-- Refresh tokens keep the scope of the login they descend from.
+ALTER TABLE refresh_tokens ADD COLUMN scope TEXT;
+
+-- Authorization codes, stored as SHA-256 hashes. Codes are single use and short lived,
+-- and can only be redeemed with the PKCE verifier matching code_challenge.
+CREATE TABLE IF NOT EXISTS authorization_codes (
+    code_hash TEXT PRIMARY KEY NOT NULL,
+    client_id TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    redirect_uri TEXT NOT NULL,
+    scope TEXT,
+    code_challenge TEXT NOT NULL,
+    expires_at INTEGER NOT NULL,
+    FOREIGN KEY(client_id) REFERENCES clients(client_id),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
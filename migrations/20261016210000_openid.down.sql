@@ -0,0 +1,3 @@
+ALTER TABLE refresh_tokens DROP COLUMN auth_time;
+ALTER TABLE authorization_codes DROP COLUMN auth_time;
+ALTER TABLE authorization_codes DROP COLUMN nonce;
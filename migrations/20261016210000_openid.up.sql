@@ -0,0 +1,5 @@
+-- OpenID Connect ID tokens carry the nonce from the authorization request and the
+-- time the user logged in, which later refreshes of the same login keep.
+ALTER TABLE authorization_codes ADD COLUMN nonce TEXT;
+ALTER TABLE authorization_codes ADD COLUMN auth_time INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE refresh_tokens ADD COLUMN auth_time INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Profile metadata for the OpenID Connect UserInfo endpoint. updated_at is a Unix
+-- timestamp; existing users are taken to have last changed when they registered.
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN updated_at INTEGER;
+
+UPDATE users
+SET updated_at = CAST(strftime('%s', COALESCE(date_registered, CURRENT_TIMESTAMP)) AS INTEGER);
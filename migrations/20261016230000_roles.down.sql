@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS user_roles;
+DROP TABLE IF EXISTS role_scopes;
+DROP TABLE IF EXISTS roles;
+DROP TABLE IF EXISTS scopes;
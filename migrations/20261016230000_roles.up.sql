@@ -0,0 +1,45 @@
+-- Scopes that users can be granted, and roles that grant them. A user may request the
+-- scopes of every role they hold, plus those of the default roles, which every user
+-- holds implicitly.
+CREATE TABLE IF NOT EXISTS scopes (
+    name TEXT PRIMARY KEY NOT NULL,
+    description TEXT NOT NULL DEFAULT ''
+);
+
+CREATE TABLE IF NOT EXISTS roles (
+    name TEXT PRIMARY KEY NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    is_default BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE IF NOT EXISTS role_scopes (
+    role TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    PRIMARY KEY (role, scope),
+    FOREIGN KEY(role) REFERENCES roles(name) ON DELETE CASCADE,
+    FOREIGN KEY(scope) REFERENCES scopes(name) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS user_roles (
+    user_id INTEGER NOT NULL,
+    role TEXT NOT NULL,
+    PRIMARY KEY (user_id, role),
+    FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY(role) REFERENCES roles(name) ON DELETE CASCADE
+);
+
+INSERT INTO scopes (name, description) VALUES
+    ('openid', 'Sign in with OpenID Connect'),
+    ('profile', 'Read your username'),
+    ('email', 'Read your email address'),
+    ('admin', 'Administer users, clients and signing keys');
+
+INSERT INTO roles (name, description, is_default) VALUES
+    ('user', 'Every registered user', TRUE),
+    ('admin', 'Administrators of this server', FALSE);
+
+INSERT INTO role_scopes (role, scope) VALUES
+    ('user', 'openid'),
+    ('user', 'profile'),
+    ('user', 'email'),
+    ('admin', 'admin');
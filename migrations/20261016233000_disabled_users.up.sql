@@ -0,0 +1,2 @@
+-- Disabled users cannot log in, and their tokens are revoked when they are disabled.
+ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,14 @@
+-- Rate limit counters shared by every server using the same database, when
+-- RATE_LIMIT_STORE=sqlite. Each row is a sliding window counter: the requests counted in the
+-- current fixed window of the policy, which started at window_start (in milliseconds since the
+-- Unix epoch), and in the window before it.
+CREATE TABLE IF NOT EXISTS rate_limit_windows (
+    policy TEXT NOT NULL,
+    key TEXT NOT NULL,
+    window_start INTEGER NOT NULL,
+    count INTEGER NOT NULL,
+    previous_count INTEGER NOT NULL,
+    -- Whether the last request counted was allowed.
+    allowed BOOLEAN NOT NULL,
+    PRIMARY KEY (policy, key)
+);
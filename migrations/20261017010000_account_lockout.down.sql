@@ -0,0 +1,5 @@
+ALTER TABLE auth_logs DROP COLUMN failure_reason;
+ALTER TABLE auth_logs DROP COLUMN username;
+ALTER TABLE auth_logs DROP COLUMN success;
+ALTER TABLE users DROP COLUMN locked_until;
+ALTER TABLE users DROP COLUMN failed_logins;
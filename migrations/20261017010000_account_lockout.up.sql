@@ -0,0 +1,9 @@
+-- Consecutive failed logins of each user, and until when (as a Unix timestamp) the account
+-- refuses logins after the last of them.
+ALTER TABLE users ADD COLUMN failed_logins INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN locked_until INTEGER;
+
+-- Failed logins are logged too, with the username tried and why the login failed.
+ALTER TABLE auth_logs ADD COLUMN success BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE auth_logs ADD COLUMN username TEXT;
+ALTER TABLE auth_logs ADD COLUMN failure_reason TEXT;
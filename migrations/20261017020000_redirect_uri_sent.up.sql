@@ -0,0 +1,3 @@
+-- Whether the authorization request sent its redirect_uri, in which case the token
+-- request must send the same one (RFC 6749 section 4.1.3).
+ALTER TABLE authorization_codes ADD COLUMN redirect_uri_sent BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,100 @@
+use super::bearer::{AdminScope, Scoped};
+use super::BearerError;
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::State;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// The API key that grants access to the `/admin` endpoints.
+///
+/// Only the SHA-256 digest of the key is kept in memory, and presented keys are
+/// compared against it in constant time.
+#[derive(Debug, Default)]
+pub struct AdminApiKey {
+    digest: Option<[u8; 32]>,
+}
+
+impl AdminApiKey {
+    /// Creates an `AdminApiKey` that accepts `key`.
+    pub fn new(key: &str) -> Self {
+        Self {
+            digest: Some(Sha256::digest(key.as_bytes()).into()),
+        }
+    }
+
+    /// Reads the key from the `ADMIN_API_KEY` environment variable.
+    ///
+    /// If the variable is unset or empty, every admin request is refused.
+    pub fn from_env() -> Self {
+        match dotenv::var("ADMIN_API_KEY") {
+            Ok(key) if !key.is_empty() => Self::new(&key),
+            _ => Self::default(),
+        }
+    }
+
+    /// Checks a presented key against the configured one.
+    pub fn verify(&self, key: &str) -> bool {
+        let Some(digest) = &self.digest else {
+            return false;
+        };
+        let presented = Sha256::digest(key.as_bytes());
+
+        digest.as_slice().ct_eq(presented.as_slice()).into()
+    }
+}
+
+/// A request guard for administrative endpoints.
+///
+/// The caller either sends the `ADMIN_API_KEY` as a bearer token, as in
+/// `Authorization: Bearer <key>`, or an access token granted the `admin` scope. The
+/// API key is meant for bootstrapping, before any user holds the `admin` role.
+/// Requests without either fail with `401 Unauthorized`, or `403 Forbidden` for a
+/// valid access token without the scope.
+#[derive(Debug)]
+pub struct Admin {
+    /// Who is acting, for the logs: `api-key`, or `user <sub>`.
+    pub actor: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = BearerError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let admin_key = request.guard::<&State<AdminApiKey>>().await.unwrap();
+        let presented = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+
+        if presented.is_some_and(|key| admin_key.verify(key.trim())) {
+            return Outcome::Success(Admin {
+                actor: "api-key".to_string(),
+            });
+        }
+
+        request
+            .guard::<Scoped<AdminScope>>()
+            .await
+            .map(|scoped| Admin {
+                actor: format!("user {}", scoped.claims.sub),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_api_key() {
+        let admin_key = AdminApiKey::new("letmein");
+        assert!(admin_key.verify("letmein"));
+        assert!(!admin_key.verify("letmeout"));
+
+        assert!(
+            !AdminApiKey::default().verify(""),
+            "Without ADMIN_API_KEY nobody is an admin."
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use super::{has_scope, BearerError};
+use crate::crypto::{Claims, KeyCipher, TokenError, TokenPolicy};
+use crate::revocation::validate_token;
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::State;
+use sqlx::SqlitePool;
+use std::marker::PhantomData;
+
+/// The access token from an `Authorization: Bearer` header, as described in RFC 6750
+/// section 2.1, if the request has one.
+///
+/// The guard never fails, so that routes can answer a missing token with a
+/// `WWW-Authenticate` challenge; see [`BearerToken::validate`].
+#[derive(Debug)]
+pub struct BearerToken(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty());
+
+        Outcome::Success(BearerToken(token))
+    }
+}
+
+impl BearerToken {
+    /// Validates the access token as one this server issued, and checks that it has
+    /// been granted `scope`.
+    ///
+    /// # Arguments
+    ///
+    /// * `db_pool` - A connection pool to the SQLite database.
+    /// * `key_cipher` - The cipher used to open the stored keys.
+    /// * `token_policy` - The issuer and clock leeway to validate against.
+    /// * `scope` - The scope the token must carry.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BearerError::MissingToken` without a token, `BearerError::InvalidToken`
+    /// if it does not validate or has been revoked, `BearerError::InsufficientScope` if
+    /// it lacks `scope`, and `BearerError::ServerError` if the keys cannot be loaded.
+    pub async fn validate(
+        &self,
+        db_pool: &SqlitePool,
+        key_cipher: &KeyCipher,
+        token_policy: &TokenPolicy,
+        scope: &str,
+    ) -> Result<Claims, BearerError> {
+        let token = self.0.as_deref().ok_or(BearerError::MissingToken)?;
+
+        let claims = match validate_token(db_pool, key_cipher, token_policy, token, None).await {
+            Ok(claims) => claims,
+            Err(TokenError::KeyError(err)) => return Err(err.into()),
+            Err(_) => return Err(BearerError::InvalidToken),
+        };
+
+        let scopes = claims.extra.get("scope").and_then(|scope| scope.as_str());
+        if !has_scope(scopes, scope) {
+            return Err(BearerError::InsufficientScope(scope.to_string()));
+        }
+
+        Ok(claims)
+    }
+}
+
+/// A scope that a route can require with [`Scoped`].
+pub trait RequiredScope {
+    /// The name of the scope, as it appears in the `scope` claim.
+    const SCOPE: &'static str;
+}
+
+/// The `openid` scope, which OpenID Connect clients request.
+#[derive(Debug)]
+pub struct OpenId;
+
+impl RequiredScope for OpenId {
+    const SCOPE: &'static str = "openid";
+}
+
+/// The `admin` scope, which grants access to the `/admin` endpoints.
+#[derive(Debug)]
+pub struct AdminScope;
+
+impl RequiredScope for AdminScope {
+    const SCOPE: &'static str = "admin";
+}
+
+/// A request guard for routes that require an access token granted the scope `S`.
+///
+/// The token is taken from the `Authorization: Bearer` header and validated as for
+/// [`BearerToken::validate`]. A route that takes `Scoped<S>` is only called with a
+/// valid token; one that takes `Result<Scoped<S>, BearerError>` can respond with the
+/// error, which carries the `WWW-Authenticate` challenge RFC 6750 asks for.
+///
+/// # Examples
+///
+/// ```
+/// #[get("/profile")]
+/// fn profile(scoped: Scoped<OpenId>) -> String {
+///     scoped.claims.sub
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Scoped<S: RequiredScope> {
+    /// The claims of the validated access token.
+    pub claims: Claims,
+    scope: PhantomData<S>,
+}
+
+#[rocket::async_trait]
+impl<'r, S: RequiredScope + Send> FromRequest<'r> for Scoped<S> {
+    type Error = BearerError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let db_pool = request.guard::<&State<SqlitePool>>().await.unwrap();
+        let key_cipher = request.guard::<&State<KeyCipher>>().await.unwrap();
+        let token_policy = request.guard::<&State<TokenPolicy>>().await.unwrap();
+        let bearer = request.guard::<BearerToken>().await.unwrap();
+
+        match bearer
+            .validate(db_pool, key_cipher, token_policy, S::SCOPE)
+            .await
+        {
+            Ok(claims) => Outcome::Success(Scoped {
+                claims,
+                scope: PhantomData,
+            }),
+            Err(err) => Outcome::Error((err.status(), err)),
+        }
+    }
+}
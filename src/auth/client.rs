@@ -0,0 +1,598 @@
+use super::{hash_password, verify_password, AuthError, OAuthError};
+use base64::engine::general_purpose;
+use base64::Engine;
+use rocket::http::{RawStr, Status};
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::State;
+use serde::Deserialize;
+use sqlx::{FromRow, SqlitePool};
+
+/// Represents an OAuth client registered in the `clients` table.
+#[derive(FromRow, Debug, Deserialize)]
+pub struct Client {
+    /// The unique identifier of the client.
+    pub client_id: String,
+    /// The bcrypt hash of the client's secret, or empty for a public client.
+    pub secret_hash: String,
+    /// The space separated scopes the client may request.
+    pub scopes: String,
+    /// The space separated audiences the client may request tokens for.
+    pub audiences: String,
+    /// How long, in seconds, the client's tokens are valid for, if not `TOKEN_TTL_SECS`.
+    pub token_ttl: Option<i64>,
+    /// The space separated redirect URIs the client may use with `/authorize`.
+    pub redirect_uris: String,
+}
+
+impl Client {
+    /// Whether the client is a public client, such as a browser or mobile app, that
+    /// has no secret to authenticate with.
+    pub fn is_public(&self) -> bool {
+        self.secret_hash.is_empty()
+    }
+
+    /// Works out the redirect URI for an authorization request.
+    ///
+    /// A requested URI must exactly match one of the client's redirect URIs. Without
+    /// one, the client's only redirect URI is used.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the requested URI is not registered, or if none was requested and
+    /// the client does not have exactly one.
+    pub fn redirect_uri(&self, requested: Option<&str>) -> Option<String> {
+        let mut allowed = self.redirect_uris.split_whitespace();
+
+        match requested {
+            Some(uri) => allowed
+                .any(|allowed| allowed == uri)
+                .then(|| uri.to_string()),
+            None => match (allowed.next(), allowed.next()) {
+                (Some(uri), None) => Some(uri.to_string()),
+                _ => None,
+            },
+        }
+    }
+
+    /// Works out the scopes to grant for a `scope` parameter.
+    ///
+    /// Without a `scope` parameter, every scope the client may request is granted.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OAuthError::InvalidScope` if any requested scope is not allowed.
+    pub fn grant_scopes(&self, requested: Option<&str>) -> Result<String, OAuthError> {
+        let Some(requested) = requested else {
+            return Ok(self.scopes.clone());
+        };
+        let allowed: Vec<&str> = self.scopes.split_whitespace().collect();
+
+        if requested
+            .split_whitespace()
+            .all(|scope| allowed.contains(&scope))
+        {
+            Ok(requested.split_whitespace().collect::<Vec<_>>().join(" "))
+        } else {
+            Err(OAuthError::InvalidScope)
+        }
+    }
+
+    /// Works out the audience to issue a token for, given an `audience` parameter.
+    ///
+    /// Without an `audience` parameter, the client's first audience is used, if it
+    /// has any.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OAuthError::InvalidTarget` if the requested audience is not allowed.
+    pub fn grant_audience(&self, requested: Option<&str>) -> Result<Option<String>, OAuthError> {
+        let mut allowed = self.audiences.split_whitespace();
+
+        match requested {
+            None => Ok(allowed.next().map(str::to_string)),
+            Some(audience) if allowed.any(|allowed| allowed == audience) => {
+                Ok(Some(audience.to_string()))
+            }
+            Some(_) => Err(OAuthError::InvalidTarget),
+        }
+    }
+}
+
+/// Whether `client_id` can be registered.
+///
+/// Tokens issued to a client have its id as their `sub`, as tokens issued to a user
+/// have the user's id, so a client id must not be empty or a number.
+pub fn is_valid_client_id(client_id: &str) -> bool {
+    !client_id.is_empty() && !client_id.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// The details needed to register a client.
+#[derive(Debug, Default)]
+pub struct ClientRegistration {
+    /// The unique identifier of the client.
+    pub client_id: String,
+    /// The plain text secret the client will authenticate with, or empty for a
+    /// public client.
+    pub secret: String,
+    /// The scopes the client may request.
+    pub scopes: Vec<String>,
+    /// The audiences the client may request tokens for.
+    pub audiences: Vec<String>,
+    /// How long, in seconds, the client's tokens are valid for, if not `TOKEN_TTL_SECS`.
+    pub token_ttl: Option<u64>,
+    /// The redirect URIs the client may use with `/authorize`.
+    pub redirect_uris: Vec<String>,
+}
+
+impl ClientRegistration {
+    /// Creates a registration for a client with no scopes or audiences.
+    pub fn new(client_id: &str, secret: &str) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            secret: secret.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Parses an entry of `OAUTH_CLIENTS`, which has the form
+    /// `client_id:secret;scope=read write;audience=https://api.example;ttl=300;redirect_uri=https://app.example/callback`.
+    /// Everything after the secret is optional, lists are separated by spaces, and
+    /// an empty secret registers a public client.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the entry has no secret, has an unknown attribute, or the lifetime is
+    /// not a number, or if the client id is not valid; see [`is_valid_client_id`].
+    pub fn parse(entry: &str) -> Option<Self> {
+        let mut attributes = entry.trim().split(';');
+        let (client_id, secret) = attributes.next()?.split_once(':')?;
+        if !is_valid_client_id(client_id) {
+            return None;
+        }
+
+        let mut registration = Self::new(client_id, secret);
+        for attribute in attributes {
+            let list = |value: &str| value.split_whitespace().map(str::to_string).collect();
+            match attribute.split_once('=')? {
+                ("scope", scopes) => registration.scopes = list(scopes),
+                ("audience", audiences) => registration.audiences = list(audiences),
+                ("ttl", ttl) => registration.token_ttl = Some(ttl.trim().parse().ok()?),
+                ("redirect_uri", uris) => registration.redirect_uris = list(uris),
+                _ => return None,
+            }
+        }
+
+        Some(registration)
+    }
+}
+
+/// Registers a client, replacing its secret and settings if the client already exists.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `registration` - The client to register.
+///
+/// # Errors
+///
+/// Returns `AuthError::DatabaseError` if the secret cannot be hashed or stored.
+pub async fn create_client(
+    db_pool: &SqlitePool,
+    registration: &ClientRegistration,
+) -> Result<Client, AuthError> {
+    let secret_hash = if registration.secret.is_empty() {
+        String::new()
+    } else {
+        hash_password(&registration.secret)
+            .map_err(|err| AuthError::DatabaseError(sqlx::Error::Protocol(err.to_string())))?
+    };
+    let scopes = registration.scopes.join(" ");
+    let audiences = registration.audiences.join(" ");
+    let token_ttl = registration.token_ttl.map(|ttl| ttl as i64);
+    let redirect_uris = registration.redirect_uris.join(" ");
+
+    sqlx::query!(
+        "INSERT INTO clients (client_id, secret_hash, scopes, audiences, token_ttl, redirect_uris)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT (client_id) DO UPDATE SET
+             secret_hash = excluded.secret_hash,
+             scopes = excluded.scopes,
+             audiences = excluded.audiences,
+             token_ttl = excluded.token_ttl,
+             redirect_uris = excluded.redirect_uris",
+        registration.client_id,
+        secret_hash,
+        scopes,
+        audiences,
+        token_ttl,
+        redirect_uris
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(Client {
+        client_id: registration.client_id.clone(),
+        secret_hash,
+        scopes,
+        audiences,
+        token_ttl,
+        redirect_uris,
+    })
+}
+
+/// Registers the clients listed in the `OAUTH_CLIENTS` environment variable.
+///
+/// The variable is a comma separated list of entries of the form
+/// `client_id:secret;scope=...;audience=...;ttl=...;redirect_uri=...`; see
+/// [`ClientRegistration::parse`]. Listed clients are created, or updated, on every
+/// startup; clients that are not listed are left alone. Malformed entries are skipped
+/// with a warning.
+///
+/// # Returns
+///
+/// The number of clients registered.
+pub async fn register_clients_from_env(db_pool: &SqlitePool) -> Result<usize, AuthError> {
+    let Ok(clients) = dotenv::var("OAUTH_CLIENTS") else {
+        return Ok(0);
+    };
+
+    let mut registered = 0;
+    for entry in clients.split(',').filter(|entry| !entry.trim().is_empty()) {
+        let Some(registration) = ClientRegistration::parse(entry) else {
+            warn!("Skipping malformed OAUTH_CLIENTS entry");
+            continue;
+        };
+        create_client(db_pool, &registration).await?;
+        registered += 1;
+    }
+
+    Ok(registered)
+}
+
+/// Looks up a registered client.
+pub async fn find_client(
+    db_pool: &SqlitePool,
+    client_id: &str,
+) -> Result<Option<Client>, sqlx::Error> {
+    sqlx::query_as!(
+        Client,
+        "SELECT client_id, secret_hash, scopes, audiences, token_ttl, redirect_uris
+         FROM clients WHERE client_id = ?",
+        client_id
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+/// Lists registered clients in order of their id, a page at a time.
+pub async fn list_clients(
+    db_pool: &SqlitePool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Client>, sqlx::Error> {
+    sqlx::query_as!(
+        Client,
+        "SELECT client_id, secret_hash, scopes, audiences, token_ttl, redirect_uris
+         FROM clients ORDER BY client_id LIMIT ? OFFSET ?",
+        limit,
+        offset
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+/// Counts the registered clients.
+pub async fn count_clients(db_pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!("SELECT COUNT(*) FROM clients")
+        .fetch_one(db_pool)
+        .await
+        .map(i64::from)
+}
+
+/// Deletes a registered client, with its pending authorization codes, and revokes
+/// the refresh tokens issued to it.
+///
+/// # Returns
+///
+/// `false` if there is no client with that id.
+pub async fn delete_client(db_pool: &SqlitePool, client_id: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    sqlx::query!(
+        "DELETE FROM authorization_codes WHERE client_id = ?",
+        client_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked = TRUE WHERE client_id = ?",
+        client_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let deleted = sqlx::query!("DELETE FROM clients WHERE client_id = ?", client_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
+
+    Ok(deleted > 0)
+}
+
+/// Lists every scope that some registered client may request, sorted and without
+/// duplicates.
+pub async fn registered_scopes(db_pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query_scalar!("SELECT scopes FROM clients")
+        .fetch_all(db_pool)
+        .await?;
+
+    let mut scopes: Vec<String> = rows
+        .iter()
+        .flat_map(|scopes| scopes.split_whitespace())
+        .map(str::to_string)
+        .collect();
+    scopes.sort();
+    scopes.dedup();
+    Ok(scopes)
+}
+
+/// Verifies a client's identifier and secret against the `clients` table.
+///
+/// # Errors
+///
+/// Returns `AuthError::InvalidClient` if the client does not exist or the secret does
+/// not match, or `AuthError::DatabaseError` if the lookup fails.
+pub async fn authenticate_client(
+    db_pool: &SqlitePool,
+    client_id: &str,
+    secret: &str,
+) -> Result<Client, AuthError> {
+    let client = find_client(db_pool, client_id)
+        .await?
+        .ok_or(AuthError::InvalidClient)?;
+
+    if verify_password(secret, &client.secret_hash) {
+        Ok(client)
+    } else {
+        Err(AuthError::InvalidClient)
+    }
+}
+
+/// Splits an HTTP Basic `Authorization` header into a client identifier and secret.
+///
+/// Both parts are percent-decoded, as RFC 6749 section 2.3.1 requires.
+pub fn parse_basic_credentials(header: &str) -> Option<(String, String)> {
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (client_id, secret) = decoded.split_once(':')?;
+
+    Some((
+        RawStr::new(client_id).percent_decode_lossy().into_owned(),
+        RawStr::new(secret).percent_decode_lossy().into_owned(),
+    ))
+}
+
+/// The client making a request to an endpoint that clients may, but need not,
+/// identify themselves to.
+#[derive(Debug)]
+pub enum RequestingClient {
+    /// The request did not name a client.
+    Anonymous,
+    /// A client without a secret to authenticate with, named in the `client_id`
+    /// parameter.
+    Public(String),
+    /// A registered client that authenticated with its secret.
+    Confidential(Client),
+}
+
+impl RequestingClient {
+    /// The identifier of the client, if the request named one.
+    pub fn client_id(&self) -> Option<&str> {
+        match self {
+            RequestingClient::Anonymous => None,
+            RequestingClient::Public(client_id) => Some(client_id),
+            RequestingClient::Confidential(client) => Some(&client.client_id),
+        }
+    }
+}
+
+/// Works out which client is making a request to the token endpoint.
+///
+/// Registered clients with a secret must authenticate, either with HTTP Basic or by
+/// sending `client_id` and `client_secret` in the request body
+/// (`client_secret_post`), but not both. Public clients, whether registered without
+/// a secret or not registered at all, are taken at their word when they send a
+/// `client_id` parameter.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `basic` - The credentials from the `Authorization` header, if any.
+/// * `client_id` - The `client_id` parameter of the request, if any.
+/// * `client_secret` - The `client_secret` parameter of the request, if any.
+///
+/// # Errors
+///
+/// Returns `OAuthError::InvalidRequest` if the request uses both authentication
+/// methods, or `OAuthError::InvalidClient` if the credentials are wrong, if they are
+/// for a different client than `client_id`, or if `client_id` names a registered
+/// client with a secret without authenticating as it.
+pub async fn identify_client(
+    db_pool: &SqlitePool,
+    basic: &BasicCredentials,
+    client_id: Option<&str>,
+    client_secret: Option<&str>,
+) -> Result<RequestingClient, OAuthError> {
+    let credentials = match (&basic.0, client_id, client_secret) {
+        (Some(_), _, Some(_)) => {
+            return Err(OAuthError::InvalidRequest(
+                "use only one client authentication method".to_string(),
+            ))
+        }
+        (Some((basic_id, _)), Some(client_id), None) if client_id != basic_id => {
+            return Err(OAuthError::InvalidClient)
+        }
+        (Some((basic_id, secret)), _, None) => Some((basic_id.as_str(), secret.as_str())),
+        (None, Some(client_id), Some(secret)) => Some((client_id, secret)),
+        (None, None, Some(_)) => return Err(OAuthError::InvalidClient),
+        (None, _, None) => None,
+    };
+
+    if let Some((client_id, secret)) = credentials {
+        let client = authenticate_client(db_pool, client_id, secret).await?;
+        return Ok(RequestingClient::Confidential(client));
+    }
+
+    let Some(client_id) = client_id else {
+        return Ok(RequestingClient::Anonymous);
+    };
+    match find_client(db_pool, client_id).await? {
+        Some(client) if !client.is_public() => Err(OAuthError::InvalidClient),
+        _ => Ok(RequestingClient::Public(client_id.to_string())),
+    }
+}
+
+/// A request guard for the HTTP Basic client credentials of a request, if it has any.
+///
+/// Unlike [`ClientCredentials`], the credentials are not checked, so that endpoints
+/// can also serve public clients; see [`identify_client`]. Requests with an
+/// `Authorization` header that is not valid HTTP Basic fail with `401 Unauthorized`.
+#[derive(Debug)]
+pub struct BasicCredentials(pub Option<(String, String)>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BasicCredentials {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request.headers().get_one("Authorization") {
+            None => Outcome::Success(BasicCredentials(None)),
+            Some(header) => match parse_basic_credentials(header) {
+                Some(credentials) => Outcome::Success(BasicCredentials(Some(credentials))),
+                None => Outcome::Error((Status::Unauthorized, ())),
+            },
+        }
+    }
+}
+
+/// A request guard for endpoints that only registered clients may call.
+///
+/// The client authenticates with HTTP Basic, using its `client_id` as the username
+/// and its secret as the password. Requests without valid credentials fail with
+/// `401 Unauthorized`.
+#[derive(Debug)]
+pub struct ClientCredentials(pub Client);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientCredentials {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let Some((client_id, secret)) = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(parse_basic_credentials)
+        else {
+            return Outcome::Error((Status::Unauthorized, ()));
+        };
+
+        let db_pool = request.guard::<&State<SqlitePool>>().await.unwrap();
+        match authenticate_client(db_pool, &client_id, &secret).await {
+            Ok(client) => Outcome::Success(ClientCredentials(client)),
+            Err(AuthError::InvalidClient) => Outcome::Error((Status::Unauthorized, ())),
+            Err(_) => Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_authenticate_client() {
+        let db_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+
+        create_client(
+            &db_pool,
+            &ClientRegistration::new("resource-server", "s3cret"),
+        )
+        .await
+        .unwrap();
+
+        let header = format!(
+            "Basic {}",
+            general_purpose::STANDARD.encode("resource-server:s3cret")
+        );
+        let (client_id, secret) = parse_basic_credentials(&header).unwrap();
+        assert!(authenticate_client(&db_pool, &client_id, &secret)
+            .await
+            .is_ok());
+
+        assert!(matches!(
+            authenticate_client(&db_pool, "resource-server", "wrong").await,
+            Err(AuthError::InvalidClient)
+        ));
+        assert!(matches!(
+            authenticate_client(&db_pool, "nobody", "s3cret").await,
+            Err(AuthError::InvalidClient)
+        ));
+    }
+
+    #[test]
+    fn test_client_grants() {
+        let registration = ClientRegistration::parse(
+            "svc:s3cret;scope=read write;audience=https://api.test https://other.test;ttl=300",
+        )
+        .unwrap();
+        assert_eq!(registration.scopes, ["read", "write"]);
+        assert_eq!(registration.token_ttl, Some(300));
+        assert!(ClientRegistration::parse("svc").is_none());
+        assert!(ClientRegistration::parse("svc:s3cret;ttl=soon").is_none());
+        assert!(
+            ClientRegistration::parse("42:s3cret").is_none(),
+            "A client id that is a number would be mistaken for a user's id."
+        );
+
+        let client = Client {
+            client_id: registration.client_id,
+            secret_hash: String::new(),
+            scopes: registration.scopes.join(" "),
+            audiences: registration.audiences.join(" "),
+            token_ttl: None,
+            redirect_uris: "https://app.test/callback".to_string(),
+        };
+        assert_eq!(
+            client.redirect_uri(None).as_deref(),
+            Some("https://app.test/callback")
+        );
+        assert!(client
+            .redirect_uri(Some("https://app.test/other"))
+            .is_none());
+
+        assert_eq!(client.grant_scopes(None).unwrap(), "read write");
+        assert_eq!(client.grant_scopes(Some("read")).unwrap(), "read");
+        assert!(matches!(
+            client.grant_scopes(Some("read admin")),
+            Err(OAuthError::InvalidScope)
+        ));
+
+        assert_eq!(
+            client.grant_audience(None).unwrap().as_deref(),
+            Some("https://api.test")
+        );
+        assert_eq!(
+            client
+                .grant_audience(Some("https://other.test"))
+                .unwrap()
+                .as_deref(),
+            Some("https://other.test")
+        );
+        assert!(matches!(
+            client.grant_audience(Some("https://evil.test")),
+            Err(OAuthError::InvalidTarget)
+        ));
+    }
+}
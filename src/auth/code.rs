@@ -0,0 +1,204 @@
+use super::{generate_token, hash_token, OAuthError};
+use base64::engine::general_purpose;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, SqlitePool};
+use subtle::ConstantTimeEq;
+
+/// How long, in seconds, an authorization code can be redeemed for.
+pub const AUTHORIZATION_CODE_TTL: u64 = 60;
+
+/// Represents an authorization code stored in the `authorization_codes` table.
+///
+/// The code itself is never stored, only its SHA-256 hash.
+#[derive(FromRow, Debug)]
+pub struct AuthorizationCode {
+    /// The client the code was issued to.
+    pub client_id: String,
+    /// The user who logged in.
+    pub user_id: i64,
+    /// The redirect URI the code was sent to.
+    pub redirect_uri: String,
+    /// Whether the authorization request sent `redirect_uri`, rather than leaving it
+    /// to the client's only registered one. If it did, the token request must send
+    /// it too.
+    pub redirect_uri_sent: bool,
+    /// The space separated scopes the user granted, if any.
+    pub scope: Option<String>,
+    /// The PKCE `code_challenge`, which is the S256 hash of the client's verifier.
+    pub code_challenge: String,
+    /// The OpenID Connect `nonce` from the authorization request, if any.
+    pub nonce: Option<String>,
+    /// When the user logged in, as a timestamp.
+    pub auth_time: i64,
+    /// The expiration time of the code as a timestamp.
+    pub expires_at: i64,
+}
+
+impl AuthorizationCode {
+    /// Creates a code for a user who logged in at `now`, which expires
+    /// `AUTHORIZATION_CODE_TTL` seconds later.
+    ///
+    /// The code is for a request that did not send `redirect_uri`; set
+    /// `redirect_uri_sent` for one that did.
+    pub fn new(
+        client_id: &str,
+        user_id: i64,
+        redirect_uri: &str,
+        scope: Option<&str>,
+        code_challenge: &str,
+        nonce: Option<&str>,
+        now: u64,
+    ) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            user_id,
+            redirect_uri: redirect_uri.to_string(),
+            redirect_uri_sent: false,
+            scope: scope.map(str::to_string),
+            code_challenge: code_challenge.to_string(),
+            nonce: nonce.map(str::to_string),
+            auth_time: now as i64,
+            expires_at: now.saturating_add(AUTHORIZATION_CODE_TTL) as i64,
+        }
+    }
+}
+
+/// Checks a PKCE `code_verifier` against an S256 `code_challenge`, as described in
+/// RFC 7636 section 4.6.
+///
+/// Verifiers must be between 43 and 128 characters long.
+pub fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    if !(43..=128).contains(&code_verifier.len()) {
+        return false;
+    }
+    let expected = general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier));
+
+    expected.as_bytes().ct_eq(code_challenge.as_bytes()).into()
+}
+
+/// Stores an authorization code and returns the code to send to the client.
+///
+/// Expired codes are deleted at the same time.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `code` - The details of the code.
+/// * `now` - The current time as a timestamp.
+pub async fn issue_authorization_code(
+    db_pool: &SqlitePool,
+    code: &AuthorizationCode,
+    now: u64,
+) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+    let code_hash = hash_token(&token);
+    let now = now as i64;
+
+    sqlx::query!("DELETE FROM authorization_codes WHERE expires_at < ?", now)
+        .execute(db_pool)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO authorization_codes
+             (code_hash, client_id, user_id, redirect_uri, redirect_uri_sent, scope,
+              code_challenge, nonce, auth_time, expires_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        code_hash,
+        code.client_id,
+        code.user_id,
+        code.redirect_uri,
+        code.redirect_uri_sent,
+        code.scope,
+        code.code_challenge,
+        code.nonce,
+        code.auth_time,
+        code.expires_at
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// Redeems an authorization code.
+///
+/// The code is deleted as it is looked up, so it can only be redeemed once, even if
+/// the request then fails.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `code` - The code presented by the client.
+/// * `client_id` - The client presenting the code.
+/// * `redirect_uri` - The `redirect_uri` parameter of the request, if any. It must be
+///   sent if the authorization request sent one.
+/// * `code_verifier` - The PKCE verifier presented by the client.
+/// * `now` - The current time as a timestamp.
+///
+/// # Errors
+///
+/// Returns `OAuthError::InvalidGrant` if the code is unknown, used, expired, issued
+/// to another client or redirect URI, or if the verifier does not match.
+pub async fn redeem_authorization_code(
+    db_pool: &SqlitePool,
+    code: &str,
+    client_id: &str,
+    redirect_uri: Option<&str>,
+    code_verifier: &str,
+    now: u64,
+) -> Result<AuthorizationCode, OAuthError> {
+    let code_hash = hash_token(code);
+    let mut tx = db_pool.begin().await?;
+
+    let record = sqlx::query_as!(
+        AuthorizationCode,
+        r#"SELECT client_id, user_id, redirect_uri, redirect_uri_sent AS "redirect_uri_sent: bool",
+                  scope, code_challenge, nonce, auth_time, expires_at
+           FROM authorization_codes WHERE code_hash = ?"#,
+        code_hash
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(OAuthError::InvalidGrant)?;
+    let deleted = sqlx::query!(
+        "DELETE FROM authorization_codes WHERE code_hash = ?",
+        code_hash
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    tx.commit().await?;
+    if deleted == 0 {
+        return Err(OAuthError::InvalidGrant);
+    }
+
+    let valid = record.expires_at >= now as i64
+        && record.client_id == client_id
+        && match redirect_uri {
+            Some(uri) => uri == record.redirect_uri,
+            None => !record.redirect_uri_sent,
+        }
+        && verify_pkce(code_verifier, &record.code_challenge);
+
+    if valid {
+        Ok(record)
+    } else {
+        Err(OAuthError::InvalidGrant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_pkce() {
+        // The example from RFC 7636 appendix B.
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+        assert!(verify_pkce(verifier, challenge));
+        assert!(!verify_pkce(&verifier.replace('d', "e"), challenge));
+        assert!(!verify_pkce("too-short", challenge));
+    }
+}
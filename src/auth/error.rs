@@ -0,0 +1,302 @@
+use crate::crypto::CryptoError;
+use crate::rate_limit::TooManyRequests;
+use rocket::serde::json::{json, Json};
+use rocket::{
+    http::Status,
+    response::{self, Responder, Response},
+    Request,
+};
+
+/// Represents errors that can occur while authenticating a user.
+#[derive(Debug)]
+pub enum AuthError {
+    /// The supplied username and password did not match a registered user.
+    ///
+    /// This variant is deliberately vague so that responses do not reveal
+    /// whether the username or the password was wrong.
+    InvalidCredentials,
+
+    /// The client identifier and secret did not match a registered client.
+    InvalidClient,
+
+    /// A rate limit refused the request.
+    TooManyRequests,
+
+    /// An error arising from a database operation.
+    ///
+    /// This variant wraps errors from `sqlx` encountered while looking up
+    /// users, writing audit logs, or loading keys.
+    DatabaseError(sqlx::Error),
+
+    /// An error arising from a cryptographic operation such as signing a JWT.
+    CryptoError(CryptoError),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => write!(f, "invalid username or password"),
+            AuthError::InvalidClient => write!(f, "invalid client credentials"),
+            AuthError::TooManyRequests => write!(f, "too many requests"),
+            AuthError::DatabaseError(err) => write!(f, "database error: {}", err),
+            AuthError::CryptoError(err) => write!(f, "crypto error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Allows conversion from `sqlx::Error` to `AuthError`.
+impl From<sqlx::Error> for AuthError {
+    fn from(err: sqlx::Error) -> AuthError {
+        AuthError::DatabaseError(err)
+    }
+}
+
+/// Allows conversion from `CryptoError` to `AuthError`.
+impl From<CryptoError> for AuthError {
+    fn from(err: CryptoError) -> AuthError {
+        AuthError::CryptoError(err)
+    }
+}
+
+/// Allows conversion from `TooManyRequests` to `AuthError`.
+impl From<TooManyRequests> for AuthError {
+    fn from(_: TooManyRequests) -> AuthError {
+        AuthError::TooManyRequests
+    }
+}
+
+/// Implementation of the `Responder` trait for `AuthError`.
+/// This allows `AuthError` instances to be directly used in Rocket handler responses.
+impl<'r> Responder<'r, 'static> for AuthError {
+    /// Converts an `AuthError` into a Rocket response.
+    ///
+    /// # Returns
+    ///
+    /// A Rocket response with `401 Unauthorized` for bad credentials, `429 Too Many
+    /// Requests` when rate limited, or the status of the underlying error otherwise.
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            AuthError::InvalidCredentials | AuthError::InvalidClient => {
+                Response::build().status(Status::Unauthorized).ok()
+            }
+            AuthError::TooManyRequests => Response::build().status(Status::TooManyRequests).ok(),
+            AuthError::DatabaseError(_) => {
+                Response::build().status(Status::InternalServerError).ok()
+            }
+            AuthError::CryptoError(err) => err.respond_to(request),
+        }
+    }
+}
+
+/// Represents the errors of the OAuth 2.0 token endpoint, as listed in RFC 6749
+/// section 5.2.
+#[derive(Debug)]
+pub enum OAuthError {
+    /// The request is missing a parameter or is otherwise malformed.
+    InvalidRequest(String),
+
+    /// The client could not be authenticated.
+    InvalidClient,
+
+    /// The grant, such as a refresh token, is invalid, expired, revoked or was
+    /// issued to another client.
+    InvalidGrant,
+
+    /// The client is not allowed to use the requested grant.
+    UnauthorizedClient,
+
+    /// The `grant_type` is not one this server supports.
+    UnsupportedGrantType,
+
+    /// The requested scope is not one the client may request.
+    InvalidScope,
+
+    /// The requested audience is not one the client may request tokens for, as
+    /// described in RFC 8707.
+    InvalidTarget,
+
+    /// An error that is the server's fault rather than the client's.
+    ServerError(AuthError),
+}
+
+impl OAuthError {
+    /// The `error` code sent to the client.
+    pub fn code(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::InvalidGrant => "invalid_grant",
+            OAuthError::UnauthorizedClient => "unauthorized_client",
+            OAuthError::UnsupportedGrantType => "unsupported_grant_type",
+            OAuthError::InvalidScope => "invalid_scope",
+            OAuthError::InvalidTarget => "invalid_target",
+            OAuthError::ServerError(_) => "server_error",
+        }
+    }
+}
+
+impl std::fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OAuthError::InvalidRequest(description) => {
+                write!(f, "{}: {}", self.code(), description)
+            }
+            OAuthError::ServerError(err) => write!(f, "{}: {}", self.code(), err),
+            _ => write!(f, "{}", self.code()),
+        }
+    }
+}
+
+impl std::error::Error for OAuthError {}
+
+/// Allows conversion from `AuthError` to `OAuthError`.
+impl From<AuthError> for OAuthError {
+    fn from(err: AuthError) -> OAuthError {
+        match err {
+            AuthError::InvalidClient => OAuthError::InvalidClient,
+            AuthError::InvalidCredentials => OAuthError::InvalidGrant,
+            err => OAuthError::ServerError(err),
+        }
+    }
+}
+
+/// Allows conversion from `TooManyRequests` to `OAuthError`.
+impl From<TooManyRequests> for OAuthError {
+    fn from(err: TooManyRequests) -> OAuthError {
+        OAuthError::ServerError(err.into())
+    }
+}
+
+/// Allows conversion from `sqlx::Error` to `OAuthError`.
+impl From<sqlx::Error> for OAuthError {
+    fn from(err: sqlx::Error) -> OAuthError {
+        OAuthError::ServerError(AuthError::DatabaseError(err))
+    }
+}
+
+/// Allows conversion from `CryptoError` to `OAuthError`.
+impl From<CryptoError> for OAuthError {
+    fn from(err: CryptoError) -> OAuthError {
+        OAuthError::ServerError(AuthError::CryptoError(err))
+    }
+}
+
+/// Implementation of the `Responder` trait for `OAuthError`.
+impl<'r> Responder<'r, 'static> for OAuthError {
+    /// Converts an `OAuthError` into a Rocket response.
+    ///
+    /// # Returns
+    ///
+    /// A JSON body such as `{"error": "invalid_grant"}` with `400 Bad Request`, or
+    /// `401 Unauthorized` and a `WWW-Authenticate` challenge for `invalid_client`.
+    /// Server errors respond with the status of the underlying error.
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let body = match &self {
+            OAuthError::InvalidRequest(description) => {
+                json!({ "error": self.code(), "error_description": description })
+            }
+            _ => json!({ "error": self.code() }),
+        };
+
+        match self {
+            OAuthError::ServerError(err) => err.respond_to(request),
+            OAuthError::InvalidClient => Response::build_from(Json(body).respond_to(request)?)
+                .status(Status::Unauthorized)
+                .raw_header("WWW-Authenticate", "Basic realm=\"jwks_server\"")
+                .ok(),
+            _ => Response::build_from(Json(body).respond_to(request)?)
+                .status(Status::BadRequest)
+                .ok(),
+        }
+    }
+}
+
+/// Represents the errors of resources protected by a bearer access token, as listed in
+/// RFC 6750 section 3.1.
+#[derive(Debug)]
+pub enum BearerError {
+    /// The request carries no access token.
+    MissingToken,
+
+    /// The access token is malformed, expired, revoked or not for this resource.
+    InvalidToken,
+
+    /// The access token lacks the scope named here.
+    InsufficientScope(String),
+
+    /// An error that is the server's fault rather than the client's.
+    ServerError(AuthError),
+}
+
+impl BearerError {
+    /// The status to respond with.
+    pub fn status(&self) -> Status {
+        match self {
+            BearerError::MissingToken | BearerError::InvalidToken => Status::Unauthorized,
+            BearerError::InsufficientScope(_) => Status::Forbidden,
+            BearerError::ServerError(_) => Status::InternalServerError,
+        }
+    }
+}
+
+impl std::fmt::Display for BearerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BearerError::MissingToken => write!(f, "missing access token"),
+            BearerError::InvalidToken => write!(f, "invalid_token"),
+            BearerError::InsufficientScope(scope) => {
+                write!(f, "insufficient_scope: {} is required", scope)
+            }
+            BearerError::ServerError(err) => write!(f, "server_error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BearerError {}
+
+/// Allows conversion from `sqlx::Error` to `BearerError`.
+impl From<sqlx::Error> for BearerError {
+    fn from(err: sqlx::Error) -> BearerError {
+        BearerError::ServerError(AuthError::DatabaseError(err))
+    }
+}
+
+/// Allows conversion from `CryptoError` to `BearerError`.
+impl From<CryptoError> for BearerError {
+    fn from(err: CryptoError) -> BearerError {
+        BearerError::ServerError(AuthError::CryptoError(err))
+    }
+}
+
+/// Implementation of the `Responder` trait for `BearerError`.
+impl<'r> Responder<'r, 'static> for BearerError {
+    /// Converts a `BearerError` into a Rocket response.
+    ///
+    /// # Returns
+    ///
+    /// `401 Unauthorized` for a missing or invalid token and `403 Forbidden` for
+    /// insufficient scope, each with a `WWW-Authenticate: Bearer` challenge as RFC 6750
+    /// section 3 describes. Server errors respond with the status of the underlying
+    /// error.
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        let challenge = match self {
+            BearerError::MissingToken => "Bearer realm=\"jwks_server\"".to_string(),
+            BearerError::InvalidToken => {
+                "Bearer realm=\"jwks_server\", error=\"invalid_token\"".to_string()
+            }
+            BearerError::InsufficientScope(scope) => format!(
+                "Bearer realm=\"jwks_server\", error=\"insufficient_scope\", scope=\"{}\"",
+                scope
+            ),
+            BearerError::ServerError(err) => return err.respond_to(request),
+        };
+
+        Response::build()
+            .status(status)
+            .raw_header("WWW-Authenticate", challenge)
+            .ok()
+    }
+}
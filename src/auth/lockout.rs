@@ -0,0 +1,180 @@
+use sqlx::SqlitePool;
+use std::num::ParseIntError;
+
+/// Why a login failed, as recorded in `auth_logs.failure_reason`.
+///
+/// The client is never told; every failure is answered as `invalid username or
+/// password`, so that responses do not reveal whether a username exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginFailure {
+    /// No user has the username.
+    UnknownUser,
+    /// The password did not match.
+    WrongPassword,
+    /// An administrator has disabled the user.
+    Disabled,
+    /// The account is locked after earlier failures.
+    Locked,
+}
+
+impl LoginFailure {
+    /// The name recorded in `auth_logs.failure_reason`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginFailure::UnknownUser => "unknown_user",
+            LoginFailure::WrongPassword => "wrong_password",
+            LoginFailure::Disabled => "disabled",
+            LoginFailure::Locked => "locked",
+        }
+    }
+}
+
+/// How an account slows down, and then locks, after consecutive failed logins.
+///
+/// After each wrong password the account refuses logins for `backoff` seconds,
+/// doubling with every further failure, until `threshold` failures lock it for
+/// `lockout` seconds. Logins refused while the account is locked are not counted,
+/// and a successful login or an administrator unlocking the account starts over.
+#[derive(Debug, Clone)]
+pub struct LockoutPolicy {
+    /// How many consecutive failures lock the account.
+    pub threshold: u32,
+    /// How long, in seconds, the account is locked for once it reaches `threshold`.
+    pub lockout: u64,
+    /// How long, in seconds, the account refuses logins after its first failure.
+    pub backoff: u64,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            lockout: 900,
+            backoff: 1,
+        }
+    }
+}
+
+impl LockoutPolicy {
+    /// Builds a `LockoutPolicy` from the `LOCKOUT_THRESHOLD`, `LOCKOUT_SECS` and
+    /// `LOGIN_BACKOFF_SECS` environment variables, falling back to the defaults for
+    /// any that are not set.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ParseIntError` if a variable is set but is not a number.
+    pub fn from_env() -> Result<Self, ParseIntError> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            threshold: match dotenv::var("LOCKOUT_THRESHOLD") {
+                Ok(threshold) => threshold.parse()?,
+                Err(_) => defaults.threshold,
+            },
+            lockout: match dotenv::var("LOCKOUT_SECS") {
+                Ok(lockout) => lockout.parse()?,
+                Err(_) => defaults.lockout,
+            },
+            backoff: match dotenv::var("LOGIN_BACKOFF_SECS") {
+                Ok(backoff) => backoff.parse()?,
+                Err(_) => defaults.backoff,
+            },
+        })
+    }
+
+    /// How long, in seconds, an account refuses logins after `failures` consecutive
+    /// failures.
+    pub fn delay(&self, failures: u32) -> u64 {
+        if failures >= self.threshold.max(1) {
+            return self.lockout;
+        }
+        let doublings = failures.saturating_sub(1).min(63);
+
+        self.backoff
+            .saturating_mul(1 << doublings)
+            .min(self.lockout)
+    }
+}
+
+/// Counts a wrong password for a user, and refuses their logins for as long as
+/// `policy` says.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `policy` - The lockout policy.
+/// * `user_id` - The user whose password was wrong.
+/// * `now` - The current time, as a Unix timestamp.
+///
+/// # Returns
+///
+/// The number of consecutive failures so far.
+pub async fn record_failed_login(
+    db_pool: &SqlitePool,
+    policy: &LockoutPolicy,
+    user_id: i64,
+    now: u64,
+) -> Result<u32, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    let failures = sqlx::query_scalar!(
+        "UPDATE users SET failed_logins = failed_logins + 1 WHERE id = ? RETURNING failed_logins",
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await? as u32;
+    let locked_until = now.saturating_add(policy.delay(failures)) as i64;
+    sqlx::query!(
+        "UPDATE users SET locked_until = ? WHERE id = ?",
+        locked_until,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(failures)
+}
+
+/// Clears a user's failed logins, so that they can log in again straight away.
+///
+/// # Returns
+///
+/// `false` if there is no user with that id.
+pub async fn unlock_user(db_pool: &SqlitePool, user_id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE users SET failed_logins = 0, locked_until = NULL WHERE id = ?",
+        user_id
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout_delay() {
+        let policy = LockoutPolicy {
+            threshold: 4,
+            lockout: 600,
+            backoff: 2,
+        };
+
+        assert_eq!(
+            (1..=5)
+                .map(|failures| policy.delay(failures))
+                .collect::<Vec<_>>(),
+            vec![2, 4, 8, 600, 600]
+        );
+
+        let short = LockoutPolicy {
+            lockout: 5,
+            ..policy
+        };
+        assert_eq!(short.delay(3), 5, "Back-off never exceeds the lockout.");
+    }
+}
@@ -1,69 +1,51 @@
-use crate::crypto::error::HashError;
-use bcrypt::{hash, DEFAULT_COST};
-use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, SqlitePool};
+pub mod admin;
+pub use admin::{Admin, AdminApiKey};
 
-use rocket::http::Status;
-use rocket::request::{self, FromRequest, Outcome, Request};
-use rocket::State;
-use std::collections::HashMap;
-use std::sync::Mutex;
-use std::time::{Duration, Instant};
-
-pub struct RateLimiter {
-    requests: Mutex<HashMap<String, Vec<Instant>>>,
-    limit: usize,
-    window: Duration,
-}
-
-impl RateLimiter {
-    pub fn new(limit: usize, window: Duration) -> Self {
-        RateLimiter {
-            requests: Mutex::new(HashMap::new()),
-            limit,
-            window,
-        }
-    }
+pub mod bearer;
+pub use bearer::{OpenId, Scoped};
 
-    fn allow(&self, ip: &str) -> bool {
-        let mut requests = self.requests.lock().unwrap();
-        let now = Instant::now();
-        let window_start = now - self.window;
+pub mod client;
+pub use client::{BasicCredentials, ClientCredentials, RequestingClient};
 
-        let entry = requests.entry(ip.to_string()).or_insert_with(Vec::new);
-        entry.retain(|&time| time > window_start);
+pub mod code;
 
-        if entry.len() < self.limit {
-            entry.push(now);
-            true
-        } else {
-            false
-        }
-    }
-}
+pub mod error;
+pub use error::{AuthError, BearerError, OAuthError};
 
-#[derive(Debug)]
-pub struct RateLimited;
+pub mod lockout;
+pub use lockout::{LockoutPolicy, LoginFailure};
 
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for RateLimited {
-    type Error = ();
+pub mod refresh;
 
-    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
-        let rate_limiter = request.guard::<&State<RateLimiter>>().await.unwrap();
-        let ip = request
-            .client_ip()
-            .map(|ip| ip.to_string())
-            .unwrap_or_else(|| "unknown".to_string());
+pub mod roles;
+pub use roles::UserGrant;
 
-        if rate_limiter.inner().allow(&ip) {
-            Outcome::Success(RateLimited)
-        } else {
-            Outcome::Error((Status::TooManyRequests, ()))
-        }
-    }
-}
+use crate::crypto::error::HashError;
+use crate::crypto::key_pair::unix_timestamp;
+use crate::crypto::{ClaimsBuilder, CryptoError, Jwt, KeyPair, TokenPolicy};
+use crate::net;
+use crate::rate_limit::RateLimitSubject;
+use crate::revocation;
+use base64::engine::general_purpose;
+use base64::Engine;
+use bcrypt::{hash, verify, DEFAULT_COST};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, SqlitePool};
+use std::sync::OnceLock;
+
+use rocket::data::{self, Data, FromData};
+use rocket::form::Form;
+use rocket::http::ContentType;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
 
+/// A request guard for the address of the client making the request, as resolved
+/// through any trusted proxies by [`net::client_ip`], for the audit logs.
+///
+/// The address is `unknown` if it cannot be told, rather than failing the request.
 pub struct ClientIp(pub String);
 
 #[rocket::async_trait]
@@ -71,18 +53,57 @@ impl<'r> FromRequest<'r> for ClientIp {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        match request.client_ip() {
-            Some(ip) => Outcome::Success(ClientIp(ip.to_string())),
-            None => Outcome::Error((Status::BadRequest, ())),
-        }
+        let ip = net::client_ip(request)
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        Outcome::Success(ClientIp(ip))
     }
 }
 
 /// Represents credentials with a username and password.
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Deserialize, FromForm, Default)]
 pub struct LoginDTO {
     pub username: String,
     pub password: String,
+    /// The registered client the token is requested for, which becomes its `aud` claim.
+    pub client_id: Option<String>,
+    /// The space separated scopes requested; by default every scope the user may request
+    /// other than `admin`.
+    pub scope: Option<String>,
+}
+
+impl RateLimitSubject for LoginDTO {
+    fn username(&self) -> Option<&str> {
+        Some(&self.username)
+    }
+
+    fn client_id(&self, _request: &Request<'_>) -> Option<String> {
+        self.client_id.clone()
+    }
+}
+
+/// Accepts `LoginDTO` either as a JSON body or as a form-encoded body.
+///
+/// Form-encoded bodies are recognised by their `Content-Type`; everything
+/// else is parsed as JSON.
+#[rocket::async_trait]
+impl<'r> FromData<'r> for LoginDTO {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        if request.content_type() == Some(&ContentType::Form) {
+            Form::<LoginDTO>::from_data(request, data)
+                .await
+                .map(Form::into_inner)
+                .map_error(|(status, _)| (status, ()))
+        } else {
+            Json::<LoginDTO>::from_data(request, data)
+                .await
+                .map(Json::into_inner)
+                .map_error(|(status, _)| (status, ()))
+        }
+    }
 }
 
 /// Represents credentials with a username and email.
@@ -92,6 +113,12 @@ pub struct RegisterDTO {
     pub email: String,
 }
 
+impl RateLimitSubject for RegisterDTO {
+    fn username(&self) -> Option<&str> {
+        Some(&self.username)
+    }
+}
+
 /// Represents successful registeration response DTO.
 #[derive(Debug, Serialize, Default)]
 pub struct PasswordDTO {
@@ -113,6 +140,22 @@ pub struct User {
     pub id: Option<i64>,
     /// The username of the user. Usernames are unique.
     pub username: String,
+    /// The email address of the user, if one was registered.
+    pub email: Option<String>,
+    /// Whether the user has proven they own their email address.
+    #[serde(default)]
+    pub email_verified: bool,
+    /// When the user's profile last changed, as a timestamp.
+    pub updated_at: Option<i64>,
+    /// Whether an administrator has disabled the user, which stops them logging in.
+    #[serde(default)]
+    pub disabled: bool,
+    /// How many times in a row logging in as the user has failed.
+    #[serde(default)]
+    pub failed_logins: i64,
+    /// Until when, as a timestamp, logins are refused after those failures.
+    #[serde(default)]
+    pub locked_until: Option<i64>,
     /// The hash of the user's password for secure storage.
     pub password_hash: String,
 }
@@ -144,11 +187,14 @@ pub async fn create_user(
         )))
     })?;
 
+    let updated_at = unix_timestamp() as i64;
+
     sqlx::query!(
-        "INSERT INTO users (username, email, password_hash) VALUES (?, ?, ?)",
+        "INSERT INTO users (username, email, password_hash, updated_at) VALUES (?, ?, ?, ?)",
         username,
         email,
-        password_hash
+        password_hash,
+        updated_at
     )
     .execute(db_pool)
     .await?;
@@ -160,10 +206,457 @@ pub async fn create_user(
     Ok(User {
         id: user_record.id,
         username,
+        email: Some(email.to_string()),
+        email_verified: false,
+        updated_at: Some(updated_at),
+        disabled: false,
+        failed_logins: 0,
+        locked_until: None,
         password_hash,
     })
 }
 
+/// Looks up a user by username.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `username` - The username to search for.
+///
+/// # Returns
+///
+/// Returns `Ok(Some(User))` when the user exists, `Ok(None)` when it does not,
+/// or an `Err` with an `sqlx::Error` on failure.
+pub async fn find_user_by_username(
+    db_pool: &SqlitePool,
+    username: &str,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as!(
+        User,
+        r#"SELECT id, username, email, email_verified AS "email_verified: bool", updated_at,
+                  disabled AS "disabled: bool", failed_logins, locked_until, password_hash
+           FROM users WHERE username = ?"#,
+        username
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+/// Looks up a user by id.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `user_id` - The unique identifier of the user.
+///
+/// # Returns
+///
+/// Returns `Ok(Some(User))` when the user exists, `Ok(None)` when it does not,
+/// or an `Err` with an `sqlx::Error` on failure.
+pub async fn find_user_by_id(
+    db_pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as!(
+        User,
+        r#"SELECT id, username, email, email_verified AS "email_verified: bool", updated_at,
+                  disabled AS "disabled: bool", failed_logins, locked_until, password_hash
+           FROM users WHERE id = ?"#,
+        user_id
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+/// Lists users in order of their id, a page at a time.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `limit` - The most users to return.
+/// * `offset` - How many users to skip.
+pub async fn list_users(
+    db_pool: &SqlitePool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<User>, sqlx::Error> {
+    sqlx::query_as!(
+        User,
+        r#"SELECT id, username, email, email_verified AS "email_verified: bool", updated_at,
+                  disabled AS "disabled: bool", failed_logins, locked_until, password_hash
+           FROM users ORDER BY id LIMIT ? OFFSET ?"#,
+        limit,
+        offset
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+/// Counts the registered users.
+pub async fn count_users(db_pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!("SELECT COUNT(*) FROM users")
+        .fetch_one(db_pool)
+        .await
+        .map(i64::from)
+}
+
+/// Revokes every access and refresh token issued to a user so far.
+async fn revoke_user_tokens(db_pool: &SqlitePool, user_id: i64) -> Result<(), sqlx::Error> {
+    refresh::revoke_user_refresh_tokens(db_pool, user_id).await?;
+    revocation::revoke_subject(db_pool, &user_id.to_string(), unix_timestamp()).await
+}
+
+/// Disables or re-enables a user.
+///
+/// Disabled users cannot log in, and disabling a user also revokes every token issued
+/// to them.
+///
+/// # Returns
+///
+/// `false` if there is no user with that id.
+pub async fn set_user_disabled(
+    db_pool: &SqlitePool,
+    user_id: i64,
+    disabled: bool,
+) -> Result<bool, sqlx::Error> {
+    let updated_at = unix_timestamp() as i64;
+    let result = sqlx::query!(
+        "UPDATE users SET disabled = ?, updated_at = ? WHERE id = ?",
+        disabled,
+        updated_at,
+        user_id
+    )
+    .execute(db_pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    if disabled {
+        revoke_user_tokens(db_pool, user_id).await?;
+    }
+    Ok(true)
+}
+
+/// Replaces a user's password, and revokes every token issued to them.
+///
+/// # Returns
+///
+/// `false` if there is no user with that id.
+///
+/// # Errors
+///
+/// Returns `AuthError::DatabaseError` if the password cannot be hashed or stored.
+pub async fn reset_password(
+    db_pool: &SqlitePool,
+    user_id: i64,
+    password: &str,
+) -> Result<bool, AuthError> {
+    let password_hash = hash_password(password)
+        .map_err(|err| AuthError::DatabaseError(sqlx::Error::Protocol(err.to_string())))?;
+    let updated_at = unix_timestamp() as i64;
+
+    let result = sqlx::query!(
+        "UPDATE users SET password_hash = ?, updated_at = ? WHERE id = ?",
+        password_hash,
+        updated_at,
+        user_id
+    )
+    .execute(db_pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    revoke_user_tokens(db_pool, user_id).await?;
+    Ok(true)
+}
+
+/// Deletes a user, with their roles, refresh tokens and authorization codes.
+///
+/// Their entries in `auth_logs` are kept without the user, and the access tokens
+/// issued to them are revoked.
+///
+/// # Returns
+///
+/// `false` if there is no user with that id.
+pub async fn delete_user(db_pool: &SqlitePool, user_id: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    sqlx::query!("DELETE FROM user_roles WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM refresh_tokens WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM authorization_codes WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "UPDATE auth_logs SET user_id = NULL WHERE user_id = ?",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let deleted = sqlx::query!("DELETE FROM users WHERE id = ?", user_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
+
+    if deleted == 0 {
+        return Ok(false);
+    }
+    revocation::revoke_subject(db_pool, &user_id.to_string(), unix_timestamp()).await?;
+    Ok(true)
+}
+
+/// Starts the claims of an access token for `user`.
+///
+/// The `sub` claim is the user's id, `aud` is `client_id` if one is given, and the
+/// user's `email` is added if they registered one. The granted scopes go in `scope`,
+/// if there are any, and the user's roles in `roles`.
+pub fn user_claims(
+    token_policy: &TokenPolicy,
+    user_id: i64,
+    user: &User,
+    client_id: Option<&str>,
+    grant: &UserGrant,
+) -> ClaimsBuilder {
+    let mut claims = ClaimsBuilder::new(token_policy, &user_id.to_string());
+    if let Some(client_id) = client_id {
+        claims = claims.audience(client_id);
+    }
+    if let Some(email) = &user.email {
+        claims = claims.claim("email", email.as_str());
+    }
+    if let Some(scope) = grant.scope() {
+        claims = claims.claim("scope", scope);
+    }
+    claims.claim("roles", grant.roles.clone())
+}
+
+/// Checks whether a space separated list of scopes contains `scope`.
+pub fn has_scope(scopes: Option<&str>, scope: &str) -> bool {
+    scopes.is_some_and(|scopes| scopes.split_whitespace().any(|s| s == scope))
+}
+
+/// Starts the claims of an OpenID Connect ID token for `user`, issued to `client_id`.
+///
+/// The profile claims follow the granted scopes: `preferred_username` needs the
+/// `profile` scope and `email` the `email` scope.
+///
+/// # Arguments
+///
+/// * `token_policy` - The issuer and lifetime of the token.
+/// * `user_id` - The unique identifier of the user, which becomes `sub`.
+/// * `user` - The user who logged in.
+/// * `client_id` - The client the token is issued to, which becomes `aud`.
+/// * `scope` - The space separated scopes granted at login, if any.
+/// * `auth_time` - When the user logged in, as a timestamp.
+/// * `nonce` - The `nonce` from the authorization request, if any.
+pub fn id_token_claims(
+    token_policy: &TokenPolicy,
+    user_id: i64,
+    user: &User,
+    client_id: &str,
+    scope: Option<&str>,
+    auth_time: u64,
+    nonce: Option<&str>,
+) -> ClaimsBuilder {
+    let mut claims = ClaimsBuilder::new(token_policy, &user_id.to_string())
+        .audience(client_id)
+        .claim("auth_time", auth_time);
+    if let Some(nonce) = nonce {
+        claims = claims.claim("nonce", nonce);
+    }
+    if has_scope(scope, "profile") {
+        claims = claims.claim("preferred_username", user.username.as_str());
+    }
+    if has_scope(scope, "email") {
+        if let Some(email) = &user.email {
+            claims = claims.claim("email", email.as_str());
+        }
+    }
+    claims
+}
+
+/// Signs an ID token issued alongside `access_token`, binding the two with the
+/// `at_hash` claim.
+///
+/// # Errors
+///
+/// Returns `CryptoError` if the token cannot be signed.
+pub fn sign_id_token(
+    key_pair: &KeyPair,
+    claims: ClaimsBuilder,
+    access_token: &str,
+    now: u64,
+) -> Result<String, CryptoError> {
+    let claims = claims
+        .claim("at_hash", key_pair.algorithm.at_hash(access_token))
+        .build(key_pair, now);
+    Jwt::from(key_pair, &claims)
+}
+
+/// A bcrypt hash that no password matches, checked against when there is no such
+/// user so that logging in takes as long whether the username exists or not.
+fn dummy_password_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_password(&generate_token()).unwrap_or_default())
+}
+
+/// Verifies a username and password against the `users` table.
+///
+/// The password is checked even when the login fails for another reason, so that
+/// the time taken does not reveal whether the username exists. Each failure is
+/// logged in `auth_logs` with its reason, and a wrong password counts towards
+/// locking the account as `lockout_policy` says.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `lockout_policy` - How failed logins lock the account.
+/// * `creds` - The credentials supplied by the client.
+/// * `request_ip` - The IP address the request came from, for the log.
+///
+/// # Returns
+///
+/// Returns the matching `User` on success, `AuthError::InvalidCredentials` if the
+/// user does not exist, is disabled or locked, or the password does not match, or
+/// `AuthError::DatabaseError` if the lookup fails.
+pub async fn authenticate_user(
+    db_pool: &SqlitePool,
+    lockout_policy: &LockoutPolicy,
+    creds: &LoginDTO,
+    request_ip: &str,
+) -> Result<User, AuthError> {
+    let now = unix_timestamp();
+    let user = find_user_by_username(db_pool, &creds.username).await?;
+    let password_hash = user
+        .as_ref()
+        .map_or(dummy_password_hash(), |user| user.password_hash.as_str());
+    let password_matches = verify_password(&creds.password, password_hash);
+
+    let failure = match &user {
+        None => LoginFailure::UnknownUser,
+        Some(user) if user.locked_until.is_some_and(|until| until > now as i64) => {
+            LoginFailure::Locked
+        }
+        Some(user) if user.disabled => LoginFailure::Disabled,
+        Some(_) if !password_matches => LoginFailure::WrongPassword,
+        Some(_) => return user.ok_or(AuthError::InvalidCredentials),
+    };
+
+    let user_id = user.and_then(|user| user.id);
+    log_failed_login(db_pool, request_ip, user_id, creds, failure).await?;
+    if let (LoginFailure::WrongPassword, Some(user_id)) = (failure, user_id) {
+        let failures = lockout::record_failed_login(db_pool, lockout_policy, user_id, now).await?;
+        if failures == lockout_policy.threshold {
+            warn!("Locking user {} after {} failed logins", user_id, failures);
+        }
+    }
+
+    Err(AuthError::InvalidCredentials)
+}
+
+/// Records a successful login by updating the user's `last_login` timestamp, and
+/// clears their failed logins.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `user_id` - The unique identifier of the user who logged in.
+pub async fn record_login(db_pool: &SqlitePool, user_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE users SET last_login = CURRENT_TIMESTAMP, failed_logins = 0, locked_until = NULL
+         WHERE id = ?",
+        user_id
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(())
+}
+
+/// Records a failed login in the `auth_logs` table.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `request_ip` - The IP address the request came from.
+/// * `user_id` - The user whose login failed, if the username exists.
+/// * `creds` - The credentials supplied, of which the username and `client_id` are
+///   logged.
+/// * `failure` - Why the login failed.
+async fn log_failed_login(
+    db_pool: &SqlitePool,
+    request_ip: &str,
+    user_id: Option<i64>,
+    creds: &LoginDTO,
+    failure: LoginFailure,
+) -> Result<(), sqlx::Error> {
+    let reason = failure.as_str();
+    sqlx::query!(
+        "INSERT INTO auth_logs (request_ip, user_id, client_id, success, username, failure_reason)
+         VALUES (?, ?, ?, FALSE, ?, ?)",
+        request_ip,
+        user_id,
+        creds.client_id,
+        creds.username,
+        reason
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(())
+}
+
+/// Records a request for a token in the `auth_logs` table.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `request_ip` - The IP address the request came from.
+/// * `user_id` - The user the token was issued to, if any.
+/// * `client_id` - The client the token was issued to, if any.
+pub async fn log_token_request(
+    db_pool: &SqlitePool,
+    request_ip: &str,
+    user_id: Option<i64>,
+    client_id: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO auth_logs (request_ip, user_id, client_id) VALUES (?, ?, ?)",
+        request_ip,
+        user_id,
+        client_id
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(())
+}
+
+/// Generates a random, URL safe token with 256 bits of entropy, for opaque tokens
+/// such as refresh tokens, authorization codes and client secrets.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hashes an opaque token for storage and lookup.
+///
+/// The tokens are random, so a plain SHA-256 hash is enough to keep them secret.
+fn hash_token(token: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Checks a plain text password against a bcrypt hash.
+///
+/// Malformed hashes are treated as a mismatch rather than an error.
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    verify(password, password_hash).unwrap_or(false)
+}
+
 /// Hashes a password using bcrypt.
 ///
 /// # Arguments
@@ -180,26 +673,14 @@ fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use sqlx::SqlitePool;
-    use tokio;
+    use sqlx::sqlite::SqlitePoolOptions;
 
     async fn setup_db() -> Result<SqlitePool, sqlx::Error> {
-        let pool = SqlitePool::connect(":memory:").await?;
-
-        sqlx::query(
-            "
-            CREATE TABLE IF NOT EXISTS users (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                username TEXT NOT NULL UNIQUE,
-                password_hash TEXT NOT NULL,
-                email TEXT UNIQUE,
-                date_registered TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                last_login TIMESTAMP      
-            )
-        ",
-        )
-        .execute(&pool)
-        .await?;
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await?;
+        sqlx::migrate!().run(&pool).await?;
         Ok(pool)
     }
 
@@ -223,4 +704,81 @@ mod tests {
 
         assert_eq!(count.0, 1, "A user should have been added.");
     }
+
+    #[tokio::test]
+    async fn test_authenticate_user() {
+        let db_pool = setup_db().await.expect("Failed to create the in-memory DB");
+        let lockout_policy = LockoutPolicy {
+            threshold: 2,
+            lockout: 900,
+            backoff: 0,
+        };
+
+        let user_id = create_user(&db_pool, "testuser", "test@test.com", "password123")
+            .await
+            .expect("Failed to create user")
+            .id
+            .expect("A created user should have an id");
+
+        let good = LoginDTO {
+            username: "testuser".to_string(),
+            password: "password123".to_string(),
+            ..Default::default()
+        };
+        let user = authenticate_user(&db_pool, &lockout_policy, &good, "127.0.0.1")
+            .await
+            .expect("Valid credentials should authenticate");
+        assert_eq!(user.username, "testuser");
+
+        let bad_password = LoginDTO {
+            username: "testuser".to_string(),
+            password: "wrong".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            authenticate_user(&db_pool, &lockout_policy, &bad_password, "127.0.0.1").await,
+            Err(AuthError::InvalidCredentials)
+        ));
+
+        let unknown_user = LoginDTO {
+            username: "nobody".to_string(),
+            password: "password123".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            authenticate_user(&db_pool, &lockout_policy, &unknown_user, "127.0.0.1").await,
+            Err(AuthError::InvalidCredentials)
+        ));
+
+        authenticate_user(&db_pool, &lockout_policy, &bad_password, "127.0.0.1")
+            .await
+            .expect_err("A wrong password should not authenticate");
+        assert!(
+            matches!(
+                authenticate_user(&db_pool, &lockout_policy, &good, "127.0.0.1").await,
+                Err(AuthError::InvalidCredentials)
+            ),
+            "The account should be locked after two failures."
+        );
+
+        let reasons: Vec<(Option<String>,)> =
+            sqlx::query_as("SELECT failure_reason FROM auth_logs WHERE NOT success ORDER BY id")
+                .fetch_all(&db_pool)
+                .await
+                .expect("Failed to fetch the failed logins");
+        assert_eq!(
+            reasons
+                .into_iter()
+                .map(|(reason,)| reason.unwrap_or_default())
+                .collect::<Vec<_>>(),
+            vec!["wrong_password", "unknown_user", "wrong_password", "locked"]
+        );
+
+        assert!(lockout::unlock_user(&db_pool, user_id)
+            .await
+            .expect("Failed to unlock the user"));
+        authenticate_user(&db_pool, &lockout_policy, &good, "127.0.0.1")
+            .await
+            .expect("An unlocked account should authenticate");
+    }
 }
@@ -0,0 +1,285 @@
+use super::{generate_token, hash_token, OAuthError};
+use crate::crypto::key_pair::unix_timestamp;
+use sqlx::{FromRow, SqlitePool};
+use uuid::Uuid;
+
+/// Represents a refresh token stored in the `refresh_tokens` table.
+///
+/// The token itself is never stored, only its SHA-256 hash.
+#[derive(FromRow, Debug)]
+pub struct RefreshToken {
+    /// The hash of the token.
+    pub token_hash: String,
+    /// The family of the token. A login starts a new family, and every token
+    /// obtained by rotating a token of the family joins it.
+    pub family_id: String,
+    /// The user the token was issued to.
+    pub user_id: i64,
+    /// The client the token was issued to, if any.
+    pub client_id: Option<String>,
+    /// The space separated scopes granted at login, if any.
+    pub scope: Option<String>,
+    /// When the user logged in, as a timestamp.
+    pub auth_time: i64,
+    /// The expiration time of the token as a timestamp.
+    pub expires_at: i64,
+    /// When the token was exchanged for a new one, as a timestamp.
+    pub used_at: Option<i64>,
+    /// Whether the token's family has been revoked.
+    pub revoked: bool,
+}
+
+/// Issues a new opaque refresh token and stores its hash.
+///
+/// Expired tokens are deleted at the same time.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `user_id` - The user the token is issued to.
+/// * `client_id` - The client the token is issued to, if any.
+/// * `scope` - The scopes granted at login, if any.
+/// * `auth_time` - When the user logged in, as a timestamp.
+/// * `family_id` - The family to add the token to, or `None` to start a new one.
+/// * `expires_at` - The expiration time of the token as a timestamp.
+///
+/// # Returns
+///
+/// The token to hand to the client.
+pub async fn issue_refresh_token(
+    db_pool: &SqlitePool,
+    user_id: i64,
+    client_id: Option<&str>,
+    scope: Option<&str>,
+    auth_time: u64,
+    family_id: Option<&str>,
+    expires_at: u64,
+) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+    let token_hash = hash_token(&token);
+    let family_id = family_id.map_or_else(|| Uuid::new_v4().to_string(), str::to_string);
+    let auth_time = auth_time as i64;
+    let expires_at = expires_at as i64;
+    let now = unix_timestamp() as i64;
+
+    sqlx::query!("DELETE FROM refresh_tokens WHERE expires_at < ?", now)
+        .execute(db_pool)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO refresh_tokens
+             (token_hash, family_id, user_id, client_id, scope, auth_time, expires_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+        token_hash,
+        family_id,
+        user_id,
+        client_id,
+        scope,
+        auth_time,
+        expires_at
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(token)
+}
+
+/// Looks up a refresh token, whether or not it can still be used.
+pub async fn find_refresh_token(
+    db_pool: &SqlitePool,
+    token: &str,
+) -> Result<Option<RefreshToken>, sqlx::Error> {
+    let token_hash = hash_token(token);
+
+    sqlx::query_as!(
+        RefreshToken,
+        r#"SELECT token_hash, family_id, user_id, client_id, scope, auth_time, expires_at, used_at,
+                  revoked AS "revoked: bool"
+           FROM refresh_tokens WHERE token_hash = ?"#,
+        token_hash
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+/// Revokes every token in a family.
+async fn revoke_family(db_pool: &SqlitePool, family_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked = TRUE WHERE family_id = ?",
+        family_id
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Revokes every refresh token issued to a user, such as when their password is reset.
+pub async fn revoke_user_refresh_tokens(
+    db_pool: &SqlitePool,
+    user_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE refresh_tokens SET revoked = TRUE WHERE user_id = ?",
+        user_id
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Exchanges a refresh token for a new one in the same family.
+///
+/// Each refresh token can be used once. Following the reuse detection in the
+/// OAuth 2.1 draft, presenting a token that was already used is taken as a sign
+/// that it was stolen, and revokes its whole family, including the token that
+/// replaced it.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `token` - The refresh token presented by the client.
+/// * `client_id` - The client presenting the token, if any.
+/// * `now` - The current time as a timestamp.
+/// * `expires_at` - The expiration time of the new token as a timestamp.
+///
+/// # Returns
+///
+/// The stored record of the presented token, and the new token.
+///
+/// # Errors
+///
+/// Returns `OAuthError::InvalidGrant` if the token is unknown, expired, revoked,
+/// already used, or was issued to a different client.
+pub async fn rotate_refresh_token(
+    db_pool: &SqlitePool,
+    token: &str,
+    client_id: Option<&str>,
+    now: u64,
+    expires_at: u64,
+) -> Result<(RefreshToken, String), OAuthError> {
+    let record = find_refresh_token(db_pool, token)
+        .await?
+        .ok_or(OAuthError::InvalidGrant)?;
+
+    if record.revoked || record.expires_at < now as i64 {
+        return Err(OAuthError::InvalidGrant);
+    }
+    if record.client_id.as_deref() != client_id {
+        return Err(OAuthError::InvalidGrant);
+    }
+
+    // Marking the token as used only if it is still unused makes concurrent
+    // exchanges of the same token count as reuse.
+    let used_at = now as i64;
+    let first_use = record.used_at.is_none()
+        && sqlx::query!(
+            "UPDATE refresh_tokens SET used_at = ? WHERE token_hash = ? AND used_at IS NULL",
+            used_at,
+            record.token_hash
+        )
+        .execute(db_pool)
+        .await?
+        .rows_affected()
+            == 1;
+
+    if !first_use {
+        warn!(
+            "Refresh token reused for user {}; revoking family {}",
+            record.user_id, record.family_id
+        );
+        revoke_family(db_pool, &record.family_id).await?;
+        return Err(OAuthError::InvalidGrant);
+    }
+
+    let new_token = issue_refresh_token(
+        db_pool,
+        record.user_id,
+        record.client_id.as_deref(),
+        record.scope.as_deref(),
+        record.auth_time as u64,
+        Some(&record.family_id),
+        expires_at,
+    )
+    .await?;
+
+    Ok((record, new_token))
+}
+
+/// Revokes a refresh token, and with it the rest of its family, on behalf of a client.
+///
+/// # Returns
+///
+/// `false` if `token` is not a refresh token issued to `client_id`.
+pub async fn revoke_refresh_token(
+    db_pool: &SqlitePool,
+    token: &str,
+    client_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let Some(record) = find_refresh_token(db_pool, token).await? else {
+        return Ok(false);
+    };
+    if record.client_id.as_deref() != Some(client_id) {
+        return Ok(false);
+    }
+
+    revoke_family(db_pool, &record.family_id).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_refresh_token_reuse_revokes_family() {
+        let db_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        sqlx::query!("INSERT INTO users (id, username, password_hash) VALUES (7, 'u', 'x')")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+        let now = unix_timestamp();
+        let later = now + 60;
+
+        let first = issue_refresh_token(&db_pool, 7, Some("app"), None, now, None, later)
+            .await
+            .unwrap();
+        assert!(matches!(
+            rotate_refresh_token(&db_pool, &first, Some("other-app"), now, later).await,
+            Err(OAuthError::InvalidGrant)
+        ));
+
+        let (record, second) = rotate_refresh_token(&db_pool, &first, Some("app"), now, later)
+            .await
+            .unwrap();
+        assert_eq!(record.user_id, 7);
+
+        assert!(matches!(
+            rotate_refresh_token(&db_pool, &first, Some("app"), now, later).await,
+            Err(OAuthError::InvalidGrant)
+        ));
+        assert!(
+            matches!(
+                rotate_refresh_token(&db_pool, &second, Some("app"), now, later).await,
+                Err(OAuthError::InvalidGrant)
+            ),
+            "Reusing a refresh token must revoke the token that replaced it."
+        );
+
+        let clientless = issue_refresh_token(&db_pool, 7, None, None, now, None, later)
+            .await
+            .unwrap();
+        assert!(
+            !revoke_refresh_token(&db_pool, &clientless, "app")
+                .await
+                .unwrap(),
+            "Clients must not revoke refresh tokens issued to no client."
+        );
+        assert!(
+            rotate_refresh_token(&db_pool, &clientless, None, now, later)
+                .await
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use super::bearer::{AdminScope, RequiredScope};
+use sqlx::SqlitePool;
+
+/// Scopes that are only granted when they are requested by name, never by default.
+pub const PRIVILEGED_SCOPES: &[&str] = &[AdminScope::SCOPE];
+
+/// Whether `scope` is one of the [`PRIVILEGED_SCOPES`].
+pub fn is_privileged_scope(scope: &str) -> bool {
+    PRIVILEGED_SCOPES.contains(&scope)
+}
+
+/// The scopes and roles granted to a user at login.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct UserGrant {
+    /// The space separated scopes granted.
+    pub scope: String,
+    /// Every role the user holds, including the default roles.
+    pub roles: Vec<String>,
+}
+
+impl UserGrant {
+    /// The granted scopes, or `None` if no scope was granted.
+    pub fn scope(&self) -> Option<&str> {
+        Some(self.scope.as_str()).filter(|scope| !scope.is_empty())
+    }
+}
+
+/// Lists every defined role.
+pub async fn list_roles(db_pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT name FROM roles ORDER BY name")
+        .fetch_all(db_pool)
+        .await
+}
+
+/// Lists the roles a user holds: those assigned to them in `user_roles`, and the
+/// default roles that every user holds.
+pub async fn user_roles(db_pool: &SqlitePool, user_id: i64) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT name FROM roles
+         WHERE is_default OR name IN (SELECT role FROM user_roles WHERE user_id = ?)
+         ORDER BY name",
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+/// Lists the scopes a user may request, which are the scopes of every role they hold.
+pub async fn allowed_scopes(
+    db_pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT DISTINCT role_scopes.scope FROM role_scopes
+         JOIN roles ON roles.name = role_scopes.role
+         WHERE roles.is_default
+            OR roles.name IN (SELECT role FROM user_roles WHERE user_id = ?)
+         ORDER BY role_scopes.scope",
+        user_id
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+/// Replaces the roles assigned to a user. Default roles need not be listed, since
+/// every user holds them anyway.
+///
+/// # Returns
+///
+/// `false`, leaving the user's roles unchanged, if any of `roles` is not defined.
+pub async fn set_user_roles(
+    db_pool: &SqlitePool,
+    user_id: i64,
+    roles: &[String],
+) -> Result<bool, sqlx::Error> {
+    let mut roles: Vec<&str> = roles.iter().map(String::as_str).collect();
+    roles.sort_unstable();
+    roles.dedup();
+    let mut tx = db_pool.begin().await?;
+
+    sqlx::query!("DELETE FROM user_roles WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+    for role in roles {
+        let inserted = sqlx::query!(
+            "INSERT INTO user_roles (user_id, role) SELECT ?, name FROM roles WHERE name = ?",
+            user_id,
+            role
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        // Dropping the transaction without committing rolls it back.
+        if inserted == 0 {
+            return Ok(false);
+        }
+    }
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Works out the scopes and roles to grant a user for a `scope` parameter.
+///
+/// Requested scopes that the user's roles do not allow are left out rather than
+/// rejected, as RFC 6749 section 3.3 permits, so a token may carry fewer scopes than
+/// were asked for. Without a `scope` parameter, every scope the user may request is
+/// granted except the [`PRIVILEGED_SCOPES`], which must be asked for.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `user_id` - The user logging in.
+/// * `requested` - The space separated scopes requested, if any.
+pub async fn grant_user_scopes(
+    db_pool: &SqlitePool,
+    user_id: i64,
+    requested: Option<&str>,
+) -> Result<UserGrant, sqlx::Error> {
+    let allowed = allowed_scopes(db_pool, user_id).await?;
+    let scope = match requested {
+        Some(requested) => requested
+            .split_whitespace()
+            .filter(|scope| allowed.iter().any(|allowed| allowed == scope))
+            .collect::<Vec<_>>()
+            .join(" "),
+        None => allowed
+            .iter()
+            .map(String::as_str)
+            .filter(|scope| !is_privileged_scope(scope))
+            .collect::<Vec<_>>()
+            .join(" "),
+    };
+
+    Ok(UserGrant {
+        scope,
+        roles: user_roles(db_pool, user_id).await?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_grant_user_scopes() {
+        let db_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        sqlx::query!(
+            "INSERT INTO users (id, username, password_hash) VALUES (1, 'alice', 'x'), (2, 'bob', 'x')"
+        )
+        .execute(&db_pool)
+        .await
+        .unwrap();
+        sqlx::query!("INSERT INTO user_roles (user_id, role) VALUES (1, 'admin')")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+
+        let admin = grant_user_scopes(&db_pool, 1, None).await.unwrap();
+        assert_eq!(
+            admin.scope, "email openid profile",
+            "The admin scope must only be granted when asked for."
+        );
+        assert_eq!(admin.roles, vec!["admin", "user"]);
+        let admin = grant_user_scopes(&db_pool, 1, Some("openid admin"))
+            .await
+            .unwrap();
+        assert_eq!(admin.scope, "openid admin");
+
+        let user = grant_user_scopes(&db_pool, 2, Some("openid admin"))
+            .await
+            .unwrap();
+        assert_eq!(
+            user.scope, "openid",
+            "Scopes outside the user's roles must be left out."
+        );
+        assert_eq!(user.roles, vec!["user"]);
+
+        let nothing = grant_user_scopes(&db_pool, 2, Some("")).await.unwrap();
+        assert_eq!(nothing.scope(), None);
+    }
+}
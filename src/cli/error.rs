@@ -0,0 +1,78 @@
+use crate::auth::AuthError;
+use crate::crypto::CryptoError;
+use sqlx::migrate::MigrateError;
+
+/// Represents errors that can occur while running a management command.
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum CliError {
+    /// The command was given arguments that do not make sense together, or names
+    /// a user or key that does not exist.
+    InvalidArgument(String),
+
+    /// An error arising from a database operation.
+    DatabaseError(sqlx::Error),
+
+    /// An error arising from applying the migrations.
+    MigrateError(MigrateError),
+
+    /// An error arising from a cryptographic operation, such as generating a key
+    /// or signing a token.
+    CryptoError(CryptoError),
+
+    /// An error arising from reading credentials or hashing a password.
+    AuthError(AuthError),
+
+    /// An error arising from writing output.
+    IoError(std::io::Error),
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::InvalidArgument(message) => write!(f, "{}", message),
+            CliError::DatabaseError(err) => write!(f, "database error: {}", err),
+            CliError::MigrateError(err) => write!(f, "migration error: {}", err),
+            CliError::CryptoError(err) => write!(f, "{}", err),
+            CliError::AuthError(err) => write!(f, "{}", err),
+            CliError::IoError(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Allows conversion from `sqlx::Error` to `CliError`.
+impl From<sqlx::Error> for CliError {
+    fn from(err: sqlx::Error) -> CliError {
+        CliError::DatabaseError(err)
+    }
+}
+
+/// Allows conversion from `MigrateError` to `CliError`.
+impl From<MigrateError> for CliError {
+    fn from(err: MigrateError) -> CliError {
+        CliError::MigrateError(err)
+    }
+}
+
+/// Allows conversion from `CryptoError` to `CliError`.
+impl From<CryptoError> for CliError {
+    fn from(err: CryptoError) -> CliError {
+        CliError::CryptoError(err)
+    }
+}
+
+/// Allows conversion from `AuthError` to `CliError`.
+impl From<AuthError> for CliError {
+    fn from(err: AuthError) -> CliError {
+        CliError::AuthError(err)
+    }
+}
+
+/// Allows conversion from `std::io::Error` to `CliError`.
+impl From<std::io::Error> for CliError {
+    fn from(err: std::io::Error) -> CliError {
+        CliError::IoError(err)
+    }
+}
@@ -0,0 +1,281 @@
+pub mod error;
+pub use error::CliError;
+
+pub mod keys;
+pub mod token;
+pub mod user;
+
+use crate::crypto::{CryptoError, KeyCipher, SigningAlgorithm, TokenPolicy};
+use crate::db;
+use crate::rotation::RotationPolicy;
+use clap::{Args, Parser, Subcommand};
+use sqlx::SqlitePool;
+
+/// The command line of the `jwks_server` binary.
+///
+/// Without a subcommand the server is started, as with `serve`. Every other
+/// subcommand works on the database in `DATABASE_URL` directly, with the same
+/// configuration as the server, and exits.
+#[derive(Parser, Debug)]
+#[command(version, about = "A JWKS server and OAuth 2.0 authorization server")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Runs the HTTP server (the default).
+    Serve(ServeArgs),
+    /// Applies any pending database migrations, creating the database if needed. The
+    /// server also does this when it starts.
+    Migrate,
+    /// Generates a signing key that signs from now on, next to the existing keys.
+    Keygen {
+        /// The algorithm the key signs with; by default the first in SIGNING_ALGORITHMS.
+        #[arg(long)]
+        alg: Option<SigningAlgorithm>,
+        /// The size of an RSA key in bits; by default KEY_SIZE.
+        #[arg(long)]
+        size: Option<usize>,
+    },
+    /// Brings the keys in line with the rotation policy, as the server does every
+    /// KEY_ROTATION_CHECK_SECS.
+    Rotate {
+        /// Retires every active key now, ahead of its schedule.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Lists, revokes and exports signing keys.
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+    /// Adds users and disables them or resets their passwords.
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Exports the JWKS.
+    Jwks {
+        #[command(subcommand)]
+        command: JwksCommand,
+    },
+    /// Issues tokens without going through an endpoint.
+    Token {
+        #[command(subcommand)]
+        command: TokenCommand,
+    },
+}
+
+#[derive(Args, Debug, Default)]
+pub struct ServeArgs {
+    /// Adds a demo user, `test` with the password `password`, unless it already exists.
+    #[arg(long)]
+    pub seed_demo: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeysCommand {
+    /// Lists every stored key, newest first.
+    List,
+    /// Revokes a key, and with it every token it signed.
+    Revoke { kid: String },
+    /// Prints a key as a public JWK, or its private key as PEM.
+    Export {
+        kid: String,
+        /// Prints the private key instead.
+        #[arg(long)]
+        private: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UserCommand {
+    /// Adds a user, printing their password if one is generated.
+    Add {
+        username: String,
+        email: String,
+        /// The user's password; by default one is generated.
+        #[arg(long)]
+        password: Option<String>,
+        /// A role to assign besides the default roles; may be repeated.
+        #[arg(long = "role")]
+        roles: Vec<String>,
+    },
+    /// Stops a user from logging in and revokes every token issued to them.
+    Disable { username: String },
+    /// Lets a disabled user log in again.
+    Enable { username: String },
+    /// Clears a user's failed logins, unlocking their account.
+    Unlock { username: String },
+    /// Replaces a user's password and revokes every token issued to them.
+    ResetPassword {
+        username: String,
+        /// The new password; by default one is generated and printed.
+        #[arg(long)]
+        password: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum JwksCommand {
+    /// Prints the JWKS, as served at /.well-known/jwks.json.
+    Export,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TokenCommand {
+    /// Signs an access token with an active key and prints it.
+    Mint {
+        /// The `sub` claim.
+        #[arg(long)]
+        sub: String,
+        /// How long the token is valid for, in seconds; by default TOKEN_TTL_SECS.
+        #[arg(long)]
+        ttl: Option<u64>,
+        /// The `aud` claim.
+        #[arg(long)]
+        aud: Option<String>,
+        /// The space separated `scope` claim.
+        #[arg(long)]
+        scope: Option<String>,
+        /// The algorithm to sign with; by default the first in SIGNING_ALGORITHMS.
+        #[arg(long)]
+        alg: Option<SigningAlgorithm>,
+    },
+}
+
+/// Connects to the database in `DATABASE_URL`, creating it if needed.
+pub async fn connect() -> Result<SqlitePool, CliError> {
+    let database_url = dotenv::var("DATABASE_URL").map_err(CryptoError::from)?;
+
+    Ok(db::connect(&database_url).await?)
+}
+
+/// Runs a management command and returns what it prints.
+///
+/// Commands read their configuration from the environment like the server does;
+/// only the commands that touch private keys need `NOT_MY_KEY`.
+///
+/// # Errors
+///
+/// Returns `CliError::InvalidArgument` if the command names a user or key that does
+/// not exist, or the error of whatever step failed.
+pub async fn run(command: Command) -> Result<String, CliError> {
+    let db_pool = connect().await?;
+
+    match command {
+        Command::Serve(_) => unreachable!("the server is launched by main"),
+        Command::Migrate => {
+            db::migrate(&db_pool).await?;
+            Ok("Database is up to date".to_string())
+        }
+        Command::Keygen { alg, size } => {
+            let policy = RotationPolicy::from_env()?;
+            let algorithm = alg.unwrap_or(policy.default_algorithm());
+            keys::keygen(&db_pool, &KeyCipher::from_env()?, &policy, algorithm, size).await
+        }
+        Command::Rotate { force } => {
+            keys::rotate(
+                &db_pool,
+                &KeyCipher::from_env()?,
+                &RotationPolicy::from_env()?,
+                force,
+            )
+            .await
+        }
+        Command::Keys { command } => match command {
+            KeysCommand::List => keys::list(&db_pool).await,
+            KeysCommand::Revoke { kid } => keys::revoke(&db_pool, &kid).await,
+            KeysCommand::Export { kid, private } => {
+                keys::export(&db_pool, &KeyCipher::from_env()?, &kid, private).await
+            }
+        },
+        Command::User { command } => match command {
+            UserCommand::Add {
+                username,
+                email,
+                password,
+                roles,
+            } => user::add(&db_pool, &username, &email, password, &roles).await,
+            UserCommand::Disable { username } => {
+                user::set_disabled(&db_pool, &username, true).await
+            }
+            UserCommand::Enable { username } => {
+                user::set_disabled(&db_pool, &username, false).await
+            }
+            UserCommand::Unlock { username } => user::unlock(&db_pool, &username).await,
+            UserCommand::ResetPassword { username, password } => {
+                user::reset_password(&db_pool, &username, password).await
+            }
+        },
+        Command::Jwks {
+            command: JwksCommand::Export,
+        } => keys::export_jwks(&db_pool, &KeyCipher::from_env()?).await,
+        Command::Token {
+            command:
+                TokenCommand::Mint {
+                    sub,
+                    ttl,
+                    aud,
+                    scope,
+                    alg,
+                },
+        } => {
+            let mut token_policy = TokenPolicy::from_env()?;
+            token_policy.ttl = ttl.unwrap_or(token_policy.ttl);
+            let algorithm = alg.unwrap_or(RotationPolicy::from_env()?.default_algorithm());
+            token::mint(
+                &db_pool,
+                &KeyCipher::from_env()?,
+                &token_policy,
+                algorithm,
+                &sub,
+                aud.as_deref(),
+                scope.as_deref(),
+            )
+            .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_parse_commands() {
+        Cli::command().debug_assert();
+
+        assert!(Cli::parse_from(["jwks_server"]).command.is_none());
+
+        let cli = Cli::parse_from([
+            "jwks_server",
+            "token",
+            "mint",
+            "--sub",
+            "42",
+            "--ttl",
+            "60",
+            "--alg",
+            "ES256",
+        ]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Token {
+                command: TokenCommand::Mint {
+                    ttl: Some(60),
+                    alg: Some(SigningAlgorithm::ES256),
+                    ..
+                }
+            })
+        ));
+
+        assert!(
+            Cli::try_parse_from(["jwks_server", "keygen", "--alg", "HS256"]).is_err(),
+            "Unknown algorithms must be rejected."
+        );
+    }
+}
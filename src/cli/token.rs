@@ -0,0 +1,54 @@
+use super::CliError;
+use crate::crypto::key_pair::unix_timestamp;
+use crate::crypto::{ClaimsBuilder, Jwt, KeyCipher, SigningAlgorithm, TokenPolicy};
+use crate::db::load_key_pairs;
+use sqlx::SqlitePool;
+
+/// Signs an access token for `sub` with an active key, as `/auth` would, but without
+/// checking any credentials.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `key_cipher` - The cipher used to open the stored keys.
+/// * `token_policy` - The issuer and lifetime of the token.
+/// * `algorithm` - The algorithm to sign with.
+/// * `sub` - The `sub` claim.
+/// * `aud` - The `aud` claim, if any.
+/// * `scope` - The `scope` claim, if any.
+///
+/// # Errors
+///
+/// Returns `CliError::InvalidArgument` if no active key signs with `algorithm`.
+pub async fn mint(
+    db_pool: &SqlitePool,
+    key_cipher: &KeyCipher,
+    token_policy: &TokenPolicy,
+    algorithm: SigningAlgorithm,
+    sub: &str,
+    aud: Option<&str>,
+    scope: Option<&str>,
+) -> Result<String, CliError> {
+    let key_pairs = load_key_pairs(db_pool, key_cipher).await?;
+    let key_pair = key_pairs
+        .iter()
+        .find(|kp| kp.algorithm == algorithm && kp.state.can_sign())
+        .ok_or_else(|| {
+            CliError::InvalidArgument(format!(
+                "no active {algorithm} key; run `jwks_server keygen --alg {algorithm}` first"
+            ))
+        })?;
+
+    let mut claims = ClaimsBuilder::new(token_policy, sub);
+    if let Some(aud) = aud {
+        claims = claims.audience(aud);
+    }
+    if let Some(scope) = scope {
+        claims = claims.claim("scope", scope);
+    }
+
+    Ok(Jwt::from(
+        key_pair,
+        &claims.build(key_pair, unix_timestamp()),
+    )?)
+}
@@ -0,0 +1,174 @@
+use super::CliError;
+use crate::auth::roles::{list_roles, set_user_roles};
+use crate::auth::{
+    create_user, find_user_by_username, lockout, reset_password as set_password, set_user_disabled,
+};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+/// Finds the id of the user called `username`.
+async fn user_id(db_pool: &SqlitePool, username: &str) -> Result<i64, CliError> {
+    find_user_by_username(db_pool, username)
+        .await?
+        .and_then(|user| user.id)
+        .ok_or_else(|| CliError::InvalidArgument(format!("no user named '{username}'")))
+}
+
+/// Adds a user with the given roles, besides the default roles, the same way
+/// `/register` does.
+///
+/// # Returns
+///
+/// A confirmation, followed by the password if one was generated.
+///
+/// # Errors
+///
+/// Returns `CliError::InvalidArgument` if a role is not defined.
+pub async fn add(
+    db_pool: &SqlitePool,
+    username: &str,
+    email: &str,
+    password: Option<String>,
+    roles: &[String],
+) -> Result<String, CliError> {
+    let defined = list_roles(db_pool).await?;
+    if let Some(role) = roles.iter().find(|role| !defined.contains(role)) {
+        return Err(CliError::InvalidArgument(format!("unknown role '{role}'")));
+    }
+
+    let generated = password.is_none().then(|| Uuid::new_v4().to_string());
+    let password = password.or(generated.clone()).unwrap_or_default();
+    let user = create_user(db_pool, username, email, &password).await?;
+    let user_id = user.id.unwrap_or_default();
+    set_user_roles(db_pool, user_id, roles).await?;
+
+    let mut output = format!("Added user '{username}' with id {user_id}");
+    if let Some(generated) = generated {
+        output.push_str(&format!("\nPassword: {generated}"));
+    }
+    Ok(output)
+}
+
+/// Disables or enables the user called `username`. Disabling also revokes every
+/// token issued to them.
+///
+/// # Errors
+///
+/// Returns `CliError::InvalidArgument` if there is no such user.
+pub async fn set_disabled(
+    db_pool: &SqlitePool,
+    username: &str,
+    disabled: bool,
+) -> Result<String, CliError> {
+    let user_id = user_id(db_pool, username).await?;
+    set_user_disabled(db_pool, user_id, disabled).await?;
+
+    Ok(if disabled {
+        format!("Disabled user '{username}' and revoked their tokens")
+    } else {
+        format!("Enabled user '{username}'")
+    })
+}
+
+/// Clears the failed logins of the user called `username`, unlocking their account.
+///
+/// # Errors
+///
+/// Returns `CliError::InvalidArgument` if there is no such user.
+pub async fn unlock(db_pool: &SqlitePool, username: &str) -> Result<String, CliError> {
+    let user_id = user_id(db_pool, username).await?;
+    lockout::unlock_user(db_pool, user_id).await?;
+
+    Ok(format!("Unlocked user '{username}'"))
+}
+
+/// Replaces the password of the user called `username`, generating one if none is
+/// given, and revokes every token issued to them.
+///
+/// # Returns
+///
+/// A confirmation, followed by the password if one was generated.
+///
+/// # Errors
+///
+/// Returns `CliError::InvalidArgument` if there is no such user.
+pub async fn reset_password(
+    db_pool: &SqlitePool,
+    username: &str,
+    password: Option<String>,
+) -> Result<String, CliError> {
+    let user_id = user_id(db_pool, username).await?;
+    let generated = password.is_none().then(|| Uuid::new_v4().to_string());
+    let password = password.or(generated.clone()).unwrap_or_default();
+    set_password(db_pool, user_id, &password).await?;
+
+    let mut output = format!("Reset the password of user '{username}' and revoked their tokens");
+    if let Some(generated) = generated {
+        output.push_str(&format!("\nPassword: {generated}"));
+    }
+    Ok(output)
+}
+
+/// Adds the demo user `test`, with the email `test@test.com` and the password
+/// `password`, for trying the server out locally. Does nothing if a user called
+/// `test` already exists.
+///
+/// # Returns
+///
+/// Whether the user was added.
+pub async fn seed_demo(db_pool: &SqlitePool) -> Result<bool, CliError> {
+    if find_user_by_username(db_pool, "test").await?.is_some() {
+        return Ok(false);
+    }
+    create_user(db_pool, "test", "test@test.com", "password").await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::envelope::MasterKey;
+    use crate::crypto::{KeyCipher, KeyPair, SigningAlgorithm};
+    use crate::db;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[tokio::test]
+    async fn test_restart_keeps_data() {
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+
+        db::migrate(&db_pool).await.unwrap();
+        create_user(&db_pool, "alice", "alice@example.com", "password123")
+            .await
+            .unwrap();
+        let key_pair = KeyPair::generate(SigningAlgorithm::ES256, 3600).unwrap();
+        db::insert_key_pair(&db_pool, &key_cipher, &key_pair)
+            .await
+            .unwrap();
+        assert!(seed_demo(&db_pool).await.unwrap());
+
+        // Starting again, as `serve --seed-demo` does, must keep every row.
+        db::migrate(&db_pool).await.unwrap();
+        assert!(
+            !seed_demo(&db_pool).await.unwrap(),
+            "The demo user must only be added once."
+        );
+
+        let usernames: Vec<(String,)> = sqlx::query_as("SELECT username FROM users ORDER BY id")
+            .fetch_all(&db_pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            usernames,
+            vec![("alice".to_string(),), ("test".to_string(),)]
+        );
+        let key_pairs = db::load_key_pairs(&db_pool, &key_cipher).await.unwrap();
+        assert_eq!(key_pairs.len(), 1);
+        assert_eq!(key_pairs[0].kid, key_pair.kid);
+    }
+}
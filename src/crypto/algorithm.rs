@@ -0,0 +1,176 @@
+use crate::crypto::error::CryptoError;
+use base64::engine::general_purpose;
+use base64::Engine;
+use jsonwebtoken::Algorithm;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::str::FromStr;
+
+/// A JWS algorithm that a signing key can be generated for.
+///
+/// The variant names match the `alg` values from RFC 7518 and RFC 8037, and are
+/// stored as-is in the `alg` column of the `keys` table.
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[sqlx(type_name = "TEXT")]
+#[allow(clippy::upper_case_acronyms)]
+pub enum SigningAlgorithm {
+    /// RSASSA-PKCS1-v1_5 using SHA-256.
+    RS256,
+    /// RSASSA-PKCS1-v1_5 using SHA-384.
+    RS384,
+    /// RSASSA-PKCS1-v1_5 using SHA-512.
+    RS512,
+    /// RSASSA-PSS using SHA-256 and MGF1 with SHA-256.
+    PS256,
+    /// RSASSA-PSS using SHA-384 and MGF1 with SHA-384.
+    PS384,
+    /// RSASSA-PSS using SHA-512 and MGF1 with SHA-512.
+    PS512,
+    /// ECDSA using P-256 and SHA-256.
+    ES256,
+    /// ECDSA using P-384 and SHA-384.
+    ES384,
+    /// EdDSA using Ed25519.
+    EdDSA,
+}
+
+impl SigningAlgorithm {
+    /// Returns the `alg` value used in JWT headers and JWKs.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SigningAlgorithm::RS256 => "RS256",
+            SigningAlgorithm::RS384 => "RS384",
+            SigningAlgorithm::RS512 => "RS512",
+            SigningAlgorithm::PS256 => "PS256",
+            SigningAlgorithm::PS384 => "PS384",
+            SigningAlgorithm::PS512 => "PS512",
+            SigningAlgorithm::ES256 => "ES256",
+            SigningAlgorithm::ES384 => "ES384",
+            SigningAlgorithm::EdDSA => "EdDSA",
+        }
+    }
+
+    /// Returns the matching `jsonwebtoken` algorithm.
+    pub fn to_jwt_algorithm(self) -> Algorithm {
+        match self {
+            SigningAlgorithm::RS256 => Algorithm::RS256,
+            SigningAlgorithm::RS384 => Algorithm::RS384,
+            SigningAlgorithm::RS512 => Algorithm::RS512,
+            SigningAlgorithm::PS256 => Algorithm::PS256,
+            SigningAlgorithm::PS384 => Algorithm::PS384,
+            SigningAlgorithm::PS512 => Algorithm::PS512,
+            SigningAlgorithm::ES256 => Algorithm::ES256,
+            SigningAlgorithm::ES384 => Algorithm::ES384,
+            SigningAlgorithm::EdDSA => Algorithm::EdDSA,
+        }
+    }
+
+    /// Checks whether this algorithm signs with an RSA key.
+    ///
+    /// All RSA algorithms share the same key material, so an RSA key pair can be
+    /// generated and stored the same way whichever of them it is configured for.
+    pub fn is_rsa(self) -> bool {
+        matches!(
+            self,
+            SigningAlgorithm::RS256
+                | SigningAlgorithm::RS384
+                | SigningAlgorithm::RS512
+                | SigningAlgorithm::PS256
+                | SigningAlgorithm::PS384
+                | SigningAlgorithm::PS512
+        )
+    }
+
+    /// Computes the OpenID Connect `at_hash` of an access token: the base64url encoded
+    /// left half of its hash, using the hash function of this algorithm.
+    ///
+    /// EdDSA uses SHA-512, as Ed25519 does.
+    pub fn at_hash(self, access_token: &str) -> String {
+        let digest = match self {
+            SigningAlgorithm::RS256 | SigningAlgorithm::PS256 | SigningAlgorithm::ES256 => {
+                Sha256::digest(access_token).to_vec()
+            }
+            SigningAlgorithm::RS384 | SigningAlgorithm::PS384 | SigningAlgorithm::ES384 => {
+                Sha384::digest(access_token).to_vec()
+            }
+            SigningAlgorithm::RS512 | SigningAlgorithm::PS512 | SigningAlgorithm::EdDSA => {
+                Sha512::digest(access_token).to_vec()
+            }
+        };
+        general_purpose::URL_SAFE_NO_PAD.encode(&digest[..digest.len() / 2])
+    }
+
+    /// Parses a comma separated list of algorithms, such as `RS256,ES256,EdDSA`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CryptoError::UnsupportedAlgorithm` for the first unknown entry.
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, CryptoError> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|alg| !alg.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+}
+
+impl FromStr for SigningAlgorithm {
+    type Err = CryptoError;
+
+    fn from_str(alg: &str) -> Result<Self, Self::Err> {
+        match alg {
+            "RS256" => Ok(SigningAlgorithm::RS256),
+            "RS384" => Ok(SigningAlgorithm::RS384),
+            "RS512" => Ok(SigningAlgorithm::RS512),
+            "PS256" => Ok(SigningAlgorithm::PS256),
+            "PS384" => Ok(SigningAlgorithm::PS384),
+            "PS512" => Ok(SigningAlgorithm::PS512),
+            "ES256" => Ok(SigningAlgorithm::ES256),
+            "ES384" => Ok(SigningAlgorithm::ES384),
+            "EdDSA" => Ok(SigningAlgorithm::EdDSA),
+            other => Err(CryptoError::UnsupportedAlgorithm(other.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for SigningAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list() {
+        let algorithms = SigningAlgorithm::parse_list("RS256, PS256,ES256,EdDSA").unwrap();
+        assert_eq!(
+            algorithms,
+            vec![
+                SigningAlgorithm::RS256,
+                SigningAlgorithm::PS256,
+                SigningAlgorithm::ES256,
+                SigningAlgorithm::EdDSA
+            ]
+        );
+        assert!(SigningAlgorithm::PS512.is_rsa());
+        assert!(!SigningAlgorithm::ES256.is_rsa());
+
+        assert!(matches!(
+            SigningAlgorithm::parse_list("RS256,HS256"),
+            Err(CryptoError::UnsupportedAlgorithm(alg)) if alg == "HS256"
+        ));
+    }
+
+    #[test]
+    fn test_at_hash() {
+        // The example from OpenID Connect Core 1.0 appendix A.3.
+        assert_eq!(
+            SigningAlgorithm::RS256.at_hash("jHkWEdUXMU1BwAsC4vtUsZwnNvTIxEl0z9K3vx5KF0Y"),
+            "77QmUPtjPfzWtF2AnpK9RQ"
+        );
+        assert_eq!(SigningAlgorithm::EdDSA.at_hash("token").len(), 43);
+    }
+}
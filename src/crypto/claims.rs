@@ -0,0 +1,200 @@
+use crate::crypto::error::CryptoError;
+use crate::crypto::KeyPair;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+/// The registered claim names that [`ClaimsBuilder::claim`] will not overwrite.
+const REGISTERED_CLAIMS: [&str; 7] = ["iss", "sub", "aud", "exp", "nbf", "iat", "jti"];
+
+/// Configures the claims of every token this server issues.
+#[derive(Debug, Clone)]
+pub struct TokenPolicy {
+    /// The `iss` claim, identifying this server.
+    pub issuer: String,
+    /// How long, in seconds, a token is valid for. Tokens never outlive the key
+    /// that signed them, so the effective lifetime can be shorter.
+    pub ttl: u64,
+    /// How many seconds of clock skew to allow when checking `exp` and `nbf`.
+    pub leeway: u64,
+    /// How long, in seconds, a refresh token is valid for.
+    pub refresh_ttl: u64,
+}
+
+impl Default for TokenPolicy {
+    fn default() -> Self {
+        Self {
+            issuer: "http://localhost:8080".to_string(),
+            ttl: 3_600,
+            leeway: 60,
+            refresh_ttl: 2_592_000,
+        }
+    }
+}
+
+impl TokenPolicy {
+    /// Builds a `TokenPolicy` from the `ISSUER`, `TOKEN_TTL_SECS`,
+    /// `TOKEN_LEEWAY_SECS` and `REFRESH_TOKEN_TTL_SECS` environment variables,
+    /// falling back to the defaults for any that are not set.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CryptoError::ParseIntError` if a numeric variable is set but is not a number.
+    pub fn from_env() -> Result<Self, CryptoError> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            issuer: dotenv::var("ISSUER").unwrap_or(defaults.issuer),
+            ttl: match dotenv::var("TOKEN_TTL_SECS") {
+                Ok(ttl) => ttl.parse()?,
+                Err(_) => defaults.ttl,
+            },
+            leeway: match dotenv::var("TOKEN_LEEWAY_SECS") {
+                Ok(leeway) => leeway.parse()?,
+                Err(_) => defaults.leeway,
+            },
+            refresh_ttl: match dotenv::var("REFRESH_TOKEN_TTL_SECS") {
+                Ok(refresh_ttl) => refresh_ttl.parse()?,
+                Err(_) => defaults.refresh_ttl,
+            },
+        })
+    }
+}
+
+/// The claims carried by a JWT issued by this server.
+///
+/// Besides the registered claims from RFC 7519, a token can carry any number of
+/// extra claims, such as `email`, which are serialized alongside them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Claims {
+    /// The issuer of the token.
+    pub iss: String,
+    /// The subject of the token (typically a user identifier).
+    pub sub: String,
+    /// The intended audience of the token, usually the requesting client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// The expiration time of the token as a timestamp.
+    pub exp: u64,
+    /// The time before which the token must not be accepted, as a timestamp.
+    pub nbf: u64,
+    /// The time at which the token was issued, as a timestamp.
+    pub iat: u64,
+    /// A unique identifier for the token.
+    pub jti: String,
+    /// Any additional claims, such as `email`.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Builds the [`Claims`] for a new token.
+///
+/// # Examples
+///
+/// ```
+/// let claims = ClaimsBuilder::new(&token_policy, "42")
+///     .audience("my-client")
+///     .claim("email", "user@example.com")
+///     .build(&key_pair, unix_timestamp());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClaimsBuilder {
+    iss: String,
+    sub: String,
+    aud: Option<String>,
+    ttl: u64,
+    extra: Map<String, Value>,
+}
+
+impl ClaimsBuilder {
+    /// Starts building claims for `sub`, using the issuer and lifetime from `policy`.
+    pub fn new(policy: &TokenPolicy, sub: &str) -> Self {
+        Self {
+            iss: policy.issuer.clone(),
+            sub: sub.to_string(),
+            aud: None,
+            ttl: policy.ttl,
+            extra: Map::new(),
+        }
+    }
+
+    /// Sets the `aud` claim.
+    pub fn audience(mut self, aud: &str) -> Self {
+        self.aud = Some(aud.to_string());
+        self
+    }
+
+    /// Overrides the lifetime, in seconds, from the `TokenPolicy`.
+    pub fn ttl(mut self, ttl: u64) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Adds an extra claim. Registered claims such as `sub` or `exp` are set by the
+    /// builder itself, so attempts to add them here are ignored.
+    pub fn claim(mut self, name: &str, value: impl Into<Value>) -> Self {
+        if !REGISTERED_CLAIMS.contains(&name) {
+            self.extra.insert(name.to_string(), value.into());
+        }
+        self
+    }
+
+    /// Finishes the claims for a token signed by `key_pair` and issued at `now`.
+    ///
+    /// The token expires `ttl` seconds after `now`, or when `key_pair` expires if
+    /// that is sooner. Each call generates a new random `jti`.
+    pub fn build(self, key_pair: &KeyPair, now: u64) -> Claims {
+        Claims {
+            iss: self.iss,
+            sub: self.sub,
+            aud: self.aud,
+            exp: now.saturating_add(self.ttl).min(key_pair.expiry),
+            nbf: now,
+            iat: now,
+            jti: Uuid::new_v4().to_string(),
+            extra: self.extra,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key_pair::unix_timestamp;
+    use crate::crypto::SigningAlgorithm;
+
+    #[test]
+    fn test_claims_builder() {
+        let key_pair = KeyPair::generate(SigningAlgorithm::ES256, 600).unwrap();
+        let policy = TokenPolicy {
+            issuer: "https://issuer.test".to_string(),
+            ttl: 60,
+            leeway: 0,
+            ..Default::default()
+        };
+        let now = unix_timestamp();
+
+        let claims = ClaimsBuilder::new(&policy, "42")
+            .audience("client-a")
+            .claim("email", "user@example.com")
+            .claim("sub", "someone-else")
+            .build(&key_pair, now);
+
+        assert_eq!(claims.iss, "https://issuer.test");
+        assert_eq!(claims.sub, "42", "Extra claims must not override sub.");
+        assert_eq!(claims.aud.as_deref(), Some("client-a"));
+        assert_eq!((claims.iat, claims.nbf, claims.exp), (now, now, now + 60));
+        assert_eq!(claims.extra["email"], "user@example.com");
+
+        let long_lived = TokenPolicy {
+            ttl: 86_400,
+            ..policy
+        };
+        let capped = ClaimsBuilder::new(&long_lived, "42").build(&key_pair, now);
+        assert_eq!(
+            capped.exp, key_pair.expiry,
+            "Tokens must not outlive their signing key."
+        );
+        assert_ne!(capped.jti, claims.jti, "Every token needs a unique jti.");
+    }
+}
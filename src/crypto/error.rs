@@ -1,5 +1,7 @@
+use super::SigningAlgorithm;
 use bcrypt::BcryptError;
 use dotenv;
+use rocket::serde::json::{json, Json};
 use rocket::{
     http::Status,
     response::{self, Responder, Response},
@@ -9,6 +11,7 @@ use sqlx::error::{DatabaseError, ErrorKind};
 
 /// Represents errors that can occur within cryptographic operations.
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum CryptoError {
     /// An error arising from RSA key pair generation or manipulation.
     ///
@@ -17,6 +20,13 @@ pub enum CryptoError {
     /// or when manipulating existing keys.
     KeyPairError(rsa::errors::Error),
 
+    /// An error arising from generating, parsing or using an ECDSA or Ed25519 key.
+    ///
+    /// `ring` does not say why it rejects a key, so this carries a description of
+    /// what was being done. These keys are generated and stored by the server, so
+    /// the failure is never the client's fault.
+    InvalidKey(&'static str),
+
     /// An error related to system time operations.
     ///
     /// This variant is used when encountering issues with retrieving or
@@ -45,8 +55,107 @@ pub enum CryptoError {
     /// It is typically encountered when converting configuration values
     /// or parameters from text to numbers.
     ParseIntError(std::num::ParseIntError),
+
+    /// Indicates a failure to encrypt a private key for storage.
+    EncryptionError,
+
+    /// Indicates a failure to decrypt a stored private key.
+    ///
+    /// This error is returned when an envelope is malformed, was sealed by an
+    /// unknown master key, or fails authentication. Callers must treat it as
+    /// fatal for the request rather than falling back to unencrypted data.
+    DecryptionError,
+
+    /// An error arising from reading or writing keys in the database.
+    KeyStoreError(sqlx::Error),
+
+    /// Indicates that a signing algorithm was requested or configured that this
+    /// server does not support, or for which it has no usable key.
+    UnsupportedAlgorithm(String),
+}
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CryptoError::KeyPairError(err) => write!(f, "key pair error: {}", err),
+            CryptoError::InvalidKey(err) => write!(f, "key pair error: {}", err),
+            CryptoError::SystemTimeError(err) => write!(f, "system time error: {}", err),
+            CryptoError::TokenCreationError => write!(f, "failed to create token"),
+            CryptoError::EnvVarError(err) => write!(f, "environment variable error: {}", err),
+            CryptoError::ParseIntError(err) => write!(f, "failed to parse integer: {}", err),
+            CryptoError::EncryptionError => write!(f, "failed to encrypt private key"),
+            CryptoError::DecryptionError => write!(f, "failed to decrypt private key"),
+            CryptoError::KeyStoreError(err) => write!(f, "key store error: {}", err),
+            CryptoError::UnsupportedAlgorithm(alg) => {
+                write!(f, "unsupported signing algorithm: {}", alg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+/// Describes why a token failed verification.
+#[derive(Debug)]
+pub enum TokenError {
+    /// The token is not a well-formed JWT, or its claims cannot be read.
+    Malformed,
+    /// The token header has no `kid`, so the signing key cannot be found.
+    MissingKeyId,
+    /// No published key has the token's `kid`.
+    UnknownKey(String),
+    /// The token header names a different algorithm from the one its key signs with.
+    AlgorithmMismatch {
+        /// The algorithm the key signs with.
+        expected: SigningAlgorithm,
+        /// The algorithm named in the token header.
+        found: String,
+    },
+    /// The signature does not match the token contents.
+    InvalidSignature,
+    /// The token's `exp` has passed, even allowing for leeway.
+    Expired,
+    /// The token's `nbf` has not been reached, even allowing for leeway.
+    NotYetValid,
+    /// The token was not issued by this server.
+    InvalidIssuer,
+    /// The token was not issued for the expected audience.
+    InvalidAudience,
+    /// The token lacks a claim that every token from this server carries.
+    MissingClaim(String),
+    /// The token, or every token for its subject, has been revoked.
+    Revoked,
+    /// The signing keys could not be loaded.
+    KeyError(CryptoError),
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenError::Malformed => write!(f, "token is malformed"),
+            TokenError::MissingKeyId => write!(f, "token header has no kid"),
+            TokenError::UnknownKey(kid) => write!(f, "no published key has kid {}", kid),
+            TokenError::AlgorithmMismatch { expected, found } => {
+                write!(
+                    f,
+                    "token uses {} but its key signs with {}",
+                    found, expected
+                )
+            }
+            TokenError::InvalidSignature => write!(f, "token signature is invalid"),
+            TokenError::Expired => write!(f, "token has expired"),
+            TokenError::NotYetValid => write!(f, "token is not valid yet"),
+            TokenError::InvalidIssuer => write!(f, "token was issued by another issuer"),
+            TokenError::InvalidAudience => write!(f, "token was issued for another audience"),
+            TokenError::MissingClaim(claim) => write!(f, "token has no {} claim", claim),
+            TokenError::Revoked => write!(f, "token has been revoked"),
+            TokenError::KeyError(err) => write!(f, "{}", err),
+        }
+    }
 }
 
+impl std::error::Error for TokenError {}
+
 /// A structured error type for hashing operations, encapsulating details about the error.
 #[derive(Debug)]
 pub struct HashError {
@@ -104,7 +213,7 @@ impl DatabaseError for HashError {
         self
     }
 
-    fn into_error(self: Box<Self>) -> Box<(dyn std::error::Error + Send + Sync + 'static)> {
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
         self
     }
 }
@@ -137,6 +246,13 @@ impl From<jsonwebtoken::errors::Error> for CryptoError {
     }
 }
 
+/// Allows conversion from `sqlx::Error` to `CryptoError`.
+impl From<sqlx::Error> for CryptoError {
+    fn from(err: sqlx::Error) -> CryptoError {
+        CryptoError::KeyStoreError(err)
+    }
+}
+
 /// Enables conversion from `std::time::SystemTimeError` to `CryptoError`.
 impl From<std::time::SystemTimeError> for CryptoError {
     fn from(err: std::time::SystemTimeError) -> CryptoError {
@@ -154,13 +270,69 @@ impl<'r> Responder<'r, 'static> for CryptoError {
     /// A Rocket response indicating an error occurred.
     fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
         match self {
-            CryptoError::KeyPairError(_) => Response::build().status(Status::BadRequest).ok(),
+            CryptoError::KeyPairError(_) | CryptoError::UnsupportedAlgorithm(_) => {
+                Response::build().status(Status::BadRequest).ok()
+            }
             CryptoError::TokenCreationError
+            | CryptoError::InvalidKey(_)
             | CryptoError::SystemTimeError(_)
             | CryptoError::ParseIntError(_)
-            | CryptoError::EnvVarError(_) => {
+            | CryptoError::EnvVarError(_)
+            | CryptoError::EncryptionError
+            | CryptoError::DecryptionError
+            | CryptoError::KeyStoreError(_) => {
                 Response::build().status(Status::InternalServerError).ok()
             }
         }
     }
 }
+
+/// Allows conversion from `CryptoError` to `TokenError`.
+impl From<CryptoError> for TokenError {
+    fn from(err: CryptoError) -> TokenError {
+        TokenError::KeyError(err)
+    }
+}
+
+/// Allows conversion from `sqlx::Error` to `TokenError`.
+impl From<sqlx::Error> for TokenError {
+    fn from(err: sqlx::Error) -> TokenError {
+        TokenError::KeyError(CryptoError::KeyStoreError(err))
+    }
+}
+
+/// Maps a `jsonwebtoken` validation failure to the matching `TokenError`.
+impl From<jsonwebtoken::errors::Error> for TokenError {
+    fn from(err: jsonwebtoken::errors::Error) -> TokenError {
+        use jsonwebtoken::errors::ErrorKind as JwtErrorKind;
+
+        match err.into_kind() {
+            JwtErrorKind::InvalidSignature => TokenError::InvalidSignature,
+            JwtErrorKind::ExpiredSignature => TokenError::Expired,
+            JwtErrorKind::ImmatureSignature => TokenError::NotYetValid,
+            JwtErrorKind::InvalidIssuer => TokenError::InvalidIssuer,
+            JwtErrorKind::InvalidAudience => TokenError::InvalidAudience,
+            JwtErrorKind::MissingRequiredClaim(claim) => TokenError::MissingClaim(claim),
+            _ => TokenError::Malformed,
+        }
+    }
+}
+
+/// Implementation of the `Responder` trait for `TokenError`.
+impl<'r> Responder<'r, 'static> for TokenError {
+    /// Converts a `TokenError` into a Rocket response.
+    ///
+    /// # Returns
+    ///
+    /// A `401 Unauthorized` response whose JSON body describes why the token was
+    /// rejected, or the status of the underlying error if the keys could not be loaded.
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            TokenError::KeyError(err) => err.respond_to(request),
+            err => {
+                let body = Json(json!({ "error": err.to_string() }));
+                (Status::Unauthorized, body).respond_to(request)
+            }
+        }
+    }
+}
@@ -1,9 +1,12 @@
+use super::key_pair::PublicKey;
+use super::KeyPair;
 use base64::engine::general_purpose;
 use base64::Engine;
 #[warn(unused_imports)] // Trait used by base64::engine::general_purpose
 use rsa::traits::PublicKeyParts;
 use rsa::RsaPublicKey;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 
 /// Represents a single JSON Web Key (JWK).
 ///
@@ -17,15 +20,34 @@ pub struct Jwk {
     pub kty: String,
     /// The intended use of the public key. Commonly used values include
     /// `sig` (for signature) or `enc` (for encryption).
+    #[serde(rename = "use")]
     pub use_: String,
     /// A unique identifier for the key. This can be used to match a specific key.
     pub kid: String,
+    /// The algorithm the key signs with, such as `RS256` or `PS256`, so that
+    /// verifiers can pin it instead of trusting the token header. Empty if unknown.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub alg: String,
     /// The RSA public key modulus for the RSA public key represented
-    /// as a base64url-encoded string.
+    /// as a base64url-encoded string. Empty for non-RSA keys.
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub n: String,
     /// The RSA public key exponent for the RSA public key represented
-    /// as a base64url-encoded string.
+    /// as a base64url-encoded string. Empty for non-RSA keys.
+    #[serde(skip_serializing_if = "String::is_empty")]
     pub e: String,
+    /// The curve of an `EC` or `OKP` key, such as `P-256` or `Ed25519`.
+    /// Empty for RSA keys.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub crv: String,
+    /// The x coordinate of an `EC` key, or the public key of an `OKP` key,
+    /// represented as a base64url-encoded string. Empty for RSA keys.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub x: String,
+    /// The y coordinate of an `EC` key represented as a base64url-encoded
+    /// string. Empty for RSA and `OKP` keys.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub y: String,
 }
 
 impl Jwk {
@@ -67,8 +89,83 @@ impl Jwk {
             kty: "RSA".to_string(),
             use_: "sig".to_string(),
             kid: kid.to_string(),
+            alg: String::new(),
             n: general_purpose::URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
             e: general_purpose::URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+            crv: String::new(),
+            x: String::new(),
+            y: String::new(),
+        }
+    }
+
+    /// Creates the published `Jwk` for a key pair, including its `alg`.
+    ///
+    /// # Parameters
+    ///
+    /// - `key_pair`: The key pair whose public key and algorithm are published.
+    pub fn from_key_pair(key_pair: &KeyPair) -> Self {
+        Self {
+            alg: key_pair.algorithm.as_str().to_string(),
+            ..Self::from_public_key(&key_pair.kid, &key_pair.public_key)
+        }
+    }
+
+    /// Computes the RFC 7638 SHA-256 thumbprint of the key, base64url-encoded.
+    ///
+    /// Only the members required for the key type are hashed, in lexicographic
+    /// order and without whitespace, so the thumbprint does not depend on `kid`,
+    /// `use` or `alg`.
+    pub fn thumbprint(&self) -> String {
+        let members = match self.kty.as_str() {
+            "RSA" => format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, self.e, self.n),
+            "EC" => format!(
+                r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+                self.crv, self.x, self.y
+            ),
+            kty => format!(
+                r#"{{"crv":"{}","kty":"{}","x":"{}"}}"#,
+                self.crv, kty, self.x
+            ),
+        };
+
+        general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(members.as_bytes()))
+    }
+
+    /// Creates a new `Jwk` instance for any supported public key.
+    ///
+    /// RSA keys are encoded with `n` and `e`, elliptic curve keys with `kty` set
+    /// to `"EC"` and `crv`, `x` and `y`, and Ed25519 keys with `kty` set to
+    /// `"OKP"` and `crv` and `x`, following RFC 7518 and RFC 8037.
+    ///
+    /// # Parameters
+    ///
+    /// - `kid`: A unique identifier for the key.
+    /// - `public_key`: The public key to encode.
+    pub fn from_public_key(kid: &str, public_key: &PublicKey) -> Self {
+        match public_key {
+            PublicKey::Rsa(public_key) => Self::new(kid, public_key),
+            PublicKey::Ec { crv, x, y } => Self {
+                kty: "EC".to_string(),
+                use_: "sig".to_string(),
+                kid: kid.to_string(),
+                alg: String::new(),
+                n: String::new(),
+                e: String::new(),
+                crv: crv.clone(),
+                x: general_purpose::URL_SAFE_NO_PAD.encode(x),
+                y: general_purpose::URL_SAFE_NO_PAD.encode(y),
+            },
+            PublicKey::Okp { crv, x } => Self {
+                kty: "OKP".to_string(),
+                use_: "sig".to_string(),
+                kid: kid.to_string(),
+                alg: String::new(),
+                n: String::new(),
+                e: String::new(),
+                crv: crv.clone(),
+                x: general_purpose::URL_SAFE_NO_PAD.encode(x),
+                y: String::new(),
+            },
         }
     }
 }
@@ -76,6 +173,7 @@ impl Jwk {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::SigningAlgorithm;
     use rand::rngs::OsRng;
     use rsa::RsaPrivateKey;
 
@@ -103,4 +201,72 @@ mod tests {
         assert_eq!(jwk.n, n_encoded, "Modulus (n) is not correctly encoded.");
         assert_eq!(jwk.e, e_encoded, "Exponent (e) is not correctly encoded.");
     }
+
+    #[test]
+    fn test_jwk_serializes_ec_and_okp_members() {
+        let ec = Jwk::from_public_key(
+            "ec_kid",
+            &PublicKey::Ec {
+                crv: "P-256".to_string(),
+                x: vec![1; 32],
+                y: vec![2; 32],
+            },
+        );
+        let ec_json = serde_json::to_value(&ec).unwrap();
+        assert_eq!(ec_json["kty"], "EC");
+        assert_eq!(ec_json["use"], "sig");
+        assert_eq!(ec_json["crv"], "P-256");
+        assert_eq!(
+            ec_json["x"],
+            general_purpose::URL_SAFE_NO_PAD.encode([1; 32])
+        );
+        assert_eq!(
+            ec_json["y"],
+            general_purpose::URL_SAFE_NO_PAD.encode([2; 32])
+        );
+        assert!(ec_json.get("n").is_none(), "EC keys should not carry n.");
+
+        let okp = Jwk::from_public_key(
+            "okp_kid",
+            &PublicKey::Okp {
+                crv: "Ed25519".to_string(),
+                x: vec![3; 32],
+            },
+        );
+        let okp_json = serde_json::to_value(&okp).unwrap();
+        assert_eq!(okp_json["kty"], "OKP");
+        assert_eq!(okp_json["crv"], "Ed25519");
+        assert!(okp_json.get("y").is_none(), "OKP keys should not carry y.");
+    }
+
+    #[test]
+    fn test_jwk_from_key_pair_publishes_alg() {
+        let key_pair = KeyPair::generate(SigningAlgorithm::PS256, 3600).unwrap();
+        let jwk_json = serde_json::to_value(Jwk::from_key_pair(&key_pair)).unwrap();
+
+        assert_eq!(jwk_json["kid"], key_pair.kid.as_str());
+        assert_eq!(jwk_json["kty"], "RSA");
+        assert_eq!(jwk_json["alg"], "PS256");
+    }
+
+    #[test]
+    fn test_thumbprint_matches_rfc_7638_example() {
+        // The example key from RFC 7638, section 3.1.
+        let jwk = Jwk {
+            kty: "RSA".to_string(),
+            use_: "sig".to_string(),
+            kid: "2011-04-29".to_string(),
+            alg: "RS256".to_string(),
+            n: "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw".to_string(),
+            e: "AQAB".to_string(),
+            crv: String::new(),
+            x: String::new(),
+            y: String::new(),
+        };
+
+        assert_eq!(
+            jwk.thumbprint(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
 }
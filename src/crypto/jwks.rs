@@ -13,11 +13,11 @@ pub struct Jwks {
 }
 
 impl Jwks {
-    /// Filters and returns a `Jwks` instance containing only the non-expired keys
+    /// Filters and returns a `Jwks` instance containing only the published keys
     /// from the given `key_pairs`.
     ///
-    /// This method is used to prepare a JWKS response with valid keys, omitting
-    /// any that have expired.
+    /// This method is used to prepare a JWKS response with pending, active and
+    /// retiring keys, omitting any that have expired or been revoked.
     ///
     /// # Arguments
     ///
@@ -25,14 +25,14 @@ impl Jwks {
     ///
     /// # Returns
     ///
-    /// Returns a `Jwks` instance containing only valid, non-expired `Jwk` keys.
+    /// Returns a `Jwks` instance containing only published `Jwk` keys.
     pub fn from_valid_pairs(key_pairs: Vec<KeyPair>) -> Self {
         Self {
             keys: key_pairs
                 .into_iter()
                 .filter_map(|jwt_key| {
-                    if !jwt_key.is_expired() {
-                        Some(Jwk::new(&jwt_key.kid.to_string(), &jwt_key.public_key))
+                    if jwt_key.state.is_published() {
+                        Some(Jwk::from_key_pair(&jwt_key))
                     } else {
                         None
                     }
@@ -45,24 +45,23 @@ impl Jwks {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::KeyState;
 
-    fn mock_key_pair(kid: i64, is_expired: bool) -> KeyPair {
-        let expiration = if is_expired { -72_000 } else { 72_000 };
-
-        KeyPair::new(kid, expiration).unwrap()
+    fn mock_key_pair(state: KeyState) -> KeyPair {
+        KeyPair::new(72_000).unwrap().with_state(state)
     }
 
     #[test]
     fn test_from_valid_pairs() {
         let key_pairs = vec![
-            mock_key_pair(1, false),
-            mock_key_pair(2, true),
-            mock_key_pair(3, false),
-            mock_key_pair(4, true),
-            mock_key_pair(5, false),
-            mock_key_pair(6, false),
-            mock_key_pair(7, true),
-            mock_key_pair(8, false),
+            mock_key_pair(KeyState::Active),
+            mock_key_pair(KeyState::Expired),
+            mock_key_pair(KeyState::Pending),
+            mock_key_pair(KeyState::Revoked),
+            mock_key_pair(KeyState::Retiring),
+            mock_key_pair(KeyState::Active),
+            mock_key_pair(KeyState::Expired),
+            mock_key_pair(KeyState::Active),
         ];
 
         let jwks = Jwks::from_valid_pairs(key_pairs);
@@ -70,7 +69,7 @@ mod tests {
         assert_eq!(
             jwks.keys.len(),
             5,
-            "Jwks should only include non-expired keys."
+            "Jwks should only include pending, active and retiring keys."
         );
     }
 }
@@ -1,28 +1,23 @@
-use super::{CryptoError, Jwk, KeyPair};
-use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
-use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+use super::key_pair::PublicKey;
+use super::{Claims, CryptoError, Jwk, KeyPair, SigningAlgorithm, TokenError, TokenPolicy};
+use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 
-/// Represents custom claims for a JWT.
-#[derive(Serialize, Deserialize)]
-struct CustomClaims {
-    /// The subject of the token (typically a user identifier).
-    sub: String,
-    /// The expiration time of the token as a timestamp.
-    exp: u64,
-}
-
 /// A struct for handling JSON Web Tokens (JWTs).
 #[derive(Serialize, Deserialize)]
 pub struct Jwt {}
 
 impl Jwt {
-    /// Creates a new JWT for a specified subject with an expiration.
+    /// Creates a new JWT carrying the given claims, signed with the given key pair.
+    ///
+    /// The header names the key pair's algorithm and `kid`. Use
+    /// [`ClaimsBuilder`](super::ClaimsBuilder) to make sure the token does not
+    /// outlive the key pair.
     ///
     /// # Arguments
     ///
-    /// * `sub` - A string slice that holds the subject of the token.
-    /// * `exp` - The expiration time of the token in seconds since the Epoch.
+    /// * `key_pair` - The key pair whose private key signs the token.
+    /// * `claims` - The claims of the token.
     ///
     /// # Returns
     ///
@@ -32,44 +27,108 @@ impl Jwt {
     /// # Examples
     ///
     /// ```
-    /// let jwt = Jwt::new("user123", 3600)?;
+    /// let claims = ClaimsBuilder::new(&token_policy, "user123").build(&key_pair, now);
+    /// let jwt = Jwt::from(&key_pair, &claims)?;
     /// ```
-    pub fn from(key_pair: &KeyPair) -> Result<String, CryptoError> {
-        let claims = CustomClaims {
-            sub: key_pair.kid.to_string(),
-            exp: key_pair.expiry,
-        };
+    pub fn from(key_pair: &KeyPair, claims: &Claims) -> Result<String, CryptoError> {
+        let mut header = Header::new(key_pair.algorithm.to_jwt_algorithm());
+        header.kid = Some(key_pair.kid.clone());
 
-        let pem_result = key_pair
-            .clone()
-            .private_key
-            .unwrap()
-            .to_pkcs8_pem(LineEnding::CRLF)
-            .map_err(|_| CryptoError::KeyPairError);
+        let encoding_key = encoding_key(key_pair)?;
 
-        let pem = match pem_result {
-            Ok(pem) => pem,
-            Err(_) => return Err(CryptoError::KeyPairError(rsa::errors::Error::Internal)),
-        };
+        encode(&header, claims, &encoding_key).map_err(|_| CryptoError::TokenCreationError)
+    }
 
-        let jwk = Jwk::new(&key_pair.kid.to_string(), &key_pair.public_key);
+    /// Verifies a JWT issued by this server and returns its claims.
+    ///
+    /// The `kid` in the token header must name one of the published `key_pairs`, and
+    /// the header algorithm must be the one that key signs with. The signature, `exp`
+    /// and `nbf` (allowing `policy.leeway` seconds of clock skew) and `iss` are always
+    /// checked; `aud` is checked when an `audience` is given.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The encoded JWT.
+    /// * `key_pairs` - The key pairs to look the `kid` up in.
+    /// * `policy` - The issuer and leeway to validate against.
+    /// * `audience` - The audience the token must have been issued for, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `TokenError` describing the first check that failed.
+    pub fn verify(
+        token: &str,
+        key_pairs: &[KeyPair],
+        policy: &TokenPolicy,
+        audience: Option<&str>,
+    ) -> Result<Claims, TokenError> {
+        let header = decode_header(token).map_err(|_| TokenError::Malformed)?;
+        let kid = header.kid.ok_or(TokenError::MissingKeyId)?;
+
+        let key_pair = key_pairs
+            .iter()
+            .find(|kp| kp.kid == kid && kp.state.is_published())
+            .ok_or(TokenError::UnknownKey(kid))?;
+
+        let algorithm = key_pair.algorithm.to_jwt_algorithm();
+        if header.alg != algorithm {
+            return Err(TokenError::AlgorithmMismatch {
+                expected: key_pair.algorithm,
+                found: format!("{:?}", header.alg),
+            });
+        }
 
-        let mut header = Header::new(Algorithm::RS256);
-        header.kid = Some(jwk.kid.clone());
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = policy.leeway;
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&policy.issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+        match audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
 
-        let encoding_key = EncodingKey::from_rsa_pem(&pem.as_bytes()).map_err(CryptoError::from)?;
+        Ok(decode::<Claims>(token, &decoding_key(key_pair)?, &validation)?.claims)
+    }
+}
 
-        encode(&header, &claims, &encoding_key).map_err(|_| CryptoError::TokenCreationError)
+/// Builds the `jsonwebtoken` verification key for a key pair from its published JWK.
+fn decoding_key(key_pair: &KeyPair) -> Result<DecodingKey, TokenError> {
+    let jwk = Jwk::from_key_pair(key_pair);
+
+    match key_pair.public_key {
+        PublicKey::Rsa(_) => DecodingKey::from_rsa_components(&jwk.n, &jwk.e),
+        PublicKey::Ec { .. } => DecodingKey::from_ec_components(&jwk.x, &jwk.y),
+        PublicKey::Okp { .. } => DecodingKey::from_ed_components(&jwk.x),
     }
+    .map_err(|_| TokenError::KeyError(CryptoError::InvalidKey("malformed public key")))
+}
+
+/// Builds the `jsonwebtoken` signing key for a key pair from its DER encoded private key.
+fn encoding_key(key_pair: &KeyPair) -> Result<EncodingKey, CryptoError> {
+    let der = key_pair.to_private_key_der()?;
+
+    Ok(match key_pair.algorithm {
+        SigningAlgorithm::ES256 | SigningAlgorithm::ES384 => EncodingKey::from_ec_der(&der),
+        SigningAlgorithm::EdDSA => EncodingKey::from_ed_der(&der),
+        _ => EncodingKey::from_rsa_der(&der),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::key_pair::unix_timestamp;
+    use crate::crypto::{ClaimsBuilder, KeyState};
+
+    fn claims_for(key_pair: &KeyPair) -> Claims {
+        ClaimsBuilder::new(&TokenPolicy::default(), "1").build(key_pair, unix_timestamp())
+    }
 
     #[test]
     fn test_jwt_creation_success() {
-        match Jwt::from(&KeyPair::new(1, 3600).unwrap()) {
+        let key_pair = KeyPair::new(3600).unwrap();
+        match Jwt::from(&key_pair, &claims_for(&key_pair)) {
             Ok(jwt) => {
                 assert!(
                     !jwt.is_empty(),
@@ -82,4 +141,99 @@ mod tests {
             ),
         }
     }
+
+    #[test]
+    fn test_jwt_verifies_for_every_algorithm() {
+        for algorithm in [
+            SigningAlgorithm::RS256,
+            SigningAlgorithm::RS384,
+            SigningAlgorithm::RS512,
+            SigningAlgorithm::PS256,
+            SigningAlgorithm::PS384,
+            SigningAlgorithm::PS512,
+            SigningAlgorithm::ES256,
+            SigningAlgorithm::ES384,
+            SigningAlgorithm::EdDSA,
+        ] {
+            let key_pair = KeyPair::generate(algorithm, 3600).unwrap();
+            let token = Jwt::from(&key_pair, &claims_for(&key_pair)).unwrap();
+
+            let header = decode_header(&token).unwrap();
+            assert_eq!(header.alg, algorithm.to_jwt_algorithm());
+            assert_eq!(header.kid, Some(key_pair.kid.clone()));
+
+            let claims = Jwt::verify(
+                &token,
+                std::slice::from_ref(&key_pair),
+                &TokenPolicy::default(),
+                None,
+            )
+            .unwrap();
+            assert_eq!(claims.sub, "1", "{algorithm} token did not verify.");
+        }
+    }
+
+    #[test]
+    fn test_jwt_verify_rejects_bad_tokens() {
+        let policy = TokenPolicy::default();
+        let key_pair = KeyPair::generate(SigningAlgorithm::ES256, 3600).unwrap();
+        let key_pairs = vec![key_pair.clone()];
+        let now = unix_timestamp();
+
+        let token = Jwt::from(
+            &key_pair,
+            &ClaimsBuilder::new(&policy, "1")
+                .audience("client-a")
+                .build(&key_pair, now),
+        )
+        .unwrap();
+        assert!(Jwt::verify(&token, &key_pairs, &policy, Some("client-a")).is_ok());
+        assert!(matches!(
+            Jwt::verify(&token, &key_pairs, &policy, Some("client-b")),
+            Err(TokenError::InvalidAudience)
+        ));
+
+        let other_issuer = TokenPolicy {
+            issuer: "https://elsewhere.test".to_string(),
+            ..policy.clone()
+        };
+        assert!(matches!(
+            Jwt::verify(&token, &key_pairs, &other_issuer, None),
+            Err(TokenError::InvalidIssuer)
+        ));
+
+        let expired = Jwt::from(
+            &key_pair,
+            &ClaimsBuilder::new(&policy, "1").build(&key_pair, now - 7_200),
+        )
+        .unwrap();
+        assert!(matches!(
+            Jwt::verify(&expired, &key_pairs, &policy, None),
+            Err(TokenError::Expired)
+        ));
+
+        let revoked = vec![key_pair.clone().with_state(KeyState::Revoked)];
+        assert!(matches!(
+            Jwt::verify(&token, &revoked, &policy, None),
+            Err(TokenError::UnknownKey(_))
+        ));
+
+        let impostor = KeyPair {
+            kid: key_pair.kid.clone(),
+            ..KeyPair::generate(SigningAlgorithm::ES256, 3600).unwrap()
+        };
+        assert!(matches!(
+            Jwt::verify(&token, &[impostor], &policy, None),
+            Err(TokenError::InvalidSignature)
+        ));
+
+        let mismatched = vec![KeyPair {
+            algorithm: SigningAlgorithm::ES384,
+            ..key_pair
+        }];
+        assert!(matches!(
+            Jwt::verify(&token, &mismatched, &policy, None),
+            Err(TokenError::AlgorithmMismatch { .. })
+        ));
+    }
 }
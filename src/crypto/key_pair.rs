@@ -1,29 +1,92 @@
+use crate::crypto::algorithm::SigningAlgorithm;
 use crate::crypto::error::CryptoError;
+use crate::crypto::jwk::Jwk;
+use crate::crypto::key_state::KeyState;
 use dotenv;
 use rand::rngs::OsRng;
-use rsa::pkcs1::DecodeRsaPrivateKey;
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, KeyPair as _, ECDSA_P256_SHA256_FIXED_SIGNING,
+    ECDSA_P384_SHA384_FIXED_SIGNING,
+};
+use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey};
 use rsa::pkcs8::{DecodePublicKey, EncodePublicKey, LineEnding};
 use rsa::{RsaPrivateKey, RsaPublicKey};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::usize;
 
-/// Represents a RSA key pair with a unique identifier and expiry timestamp.
+/// The public half of a key pair, in the form needed to publish it as a JWK.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PublicKey {
+    /// An RSA public key, serialized and deserialized as PEM format.
+    Rsa(
+        #[serde(
+            serialize_with = "serialize_rsa_public_key",
+            deserialize_with = "deserialize_rsa_public_key"
+        )]
+        RsaPublicKey,
+    ),
+    /// An elliptic curve public key given by its curve name and affine coordinates.
+    Ec {
+        /// The curve name, such as `P-256` or `P-384`.
+        crv: String,
+        /// The big-endian x coordinate.
+        x: Vec<u8>,
+        /// The big-endian y coordinate.
+        y: Vec<u8>,
+    },
+    /// An octet key pair public key, as used by EdDSA.
+    Okp {
+        /// The curve name, such as `Ed25519`.
+        crv: String,
+        /// The raw public key bytes.
+        x: Vec<u8>,
+    },
+}
+
+impl PublicKey {
+    /// Computes the RFC 7638 SHA-256 thumbprint of the public key, base64url-encoded.
+    ///
+    /// The thumbprint depends only on the key material, so the same key always gets
+    /// the same identifier wherever it is used.
+    pub fn thumbprint(&self) -> String {
+        Jwk::from_public_key("", self).thumbprint()
+    }
+}
+
+/// The private half of a key pair.
+#[derive(Clone)]
+pub enum PrivateKey {
+    /// An RSA private key.
+    Rsa(Box<RsaPrivateKey>),
+    /// An ECDSA or Ed25519 private key held as a PKCS#8 DER document.
+    Pkcs8(Vec<u8>),
+}
+
+/// Represents a key pair with a unique identifier, signing algorithm, lifecycle state and expiry timestamp.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct KeyPair {
-    /// A unique identifier for the key pair.
-    pub kid: i64,
-    /// The RSA public key, serialized and deserialized as PEM format.
-    #[serde(
-        serialize_with = "serialize_rsa_public_key",
-        deserialize_with = "deserialize_rsa_public_key"
-    )]
-    pub public_key: RsaPublicKey,
-    /// The RSA private key, which is excluded from serialization and deserialization.
+    /// A unique identifier for the key pair: the RFC 7638 thumbprint of its public
+    /// key, optionally prefixed.
+    pub kid: String,
+    /// The algorithm this key pair signs with.
+    pub algorithm: SigningAlgorithm,
+    /// The public key, which is published in the JWKS.
+    pub public_key: PublicKey,
+    /// The private key, which is excluded from serialization and deserialization.
     #[serde(skip)]
-    pub private_key: Option<RsaPrivateKey>,
+    pub private_key: Option<PrivateKey>,
+    /// The time from which the key pair may sign new tokens, in UNIX timestamp format.
+    pub not_before: u64,
+    /// The time after which the key pair no longer signs new tokens, in UNIX timestamp format.
+    ///
+    /// Between `retire_at` and `expiry` the public key is still published so that
+    /// tokens signed before retirement can be verified.
+    pub retire_at: u64,
     /// The expiry timestamp of the key pair in UNIX timestamp format.
     pub expiry: u64,
+    /// The lifecycle state of the key pair, which decides whether it signs and is published.
+    pub state: KeyState,
 }
 
 /// Serializes an `RsaPublicKey` to a PEM format string for storage or transmission.
@@ -46,15 +109,73 @@ where
     RsaPublicKey::from_public_key_pem(&pem).map_err(serde::de::Error::custom)
 }
 
+/// Returns the `ring` signing parameters and curve name for an ECDSA algorithm.
+fn ecdsa_parameters(
+    algorithm: SigningAlgorithm,
+) -> Option<(
+    &'static ring::signature::EcdsaSigningAlgorithm,
+    &'static str,
+)> {
+    match algorithm {
+        SigningAlgorithm::ES256 => Some((&ECDSA_P256_SHA256_FIXED_SIGNING, "P-256")),
+        SigningAlgorithm::ES384 => Some((&ECDSA_P384_SHA384_FIXED_SIGNING, "P-384")),
+        _ => None,
+    }
+}
+
+/// Derives the public key from a PKCS#8 encoded ECDSA or Ed25519 private key.
+fn pkcs8_public_key(algorithm: SigningAlgorithm, pkcs8: &[u8]) -> Result<PublicKey, CryptoError> {
+    let invalid = || CryptoError::InvalidKey("malformed PKCS#8 private key");
+
+    if let Some((parameters, crv)) = ecdsa_parameters(algorithm) {
+        let key_pair = EcdsaKeyPair::from_pkcs8(parameters, pkcs8, &SystemRandom::new())
+            .map_err(|_| invalid())?;
+        // ring returns the uncompressed SEC1 point: 0x04 || x || y.
+        let point = key_pair.public_key().as_ref();
+        let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+        Ok(PublicKey::Ec {
+            crv: crv.to_string(),
+            x: x.to_vec(),
+            y: y.to_vec(),
+        })
+    } else if algorithm == SigningAlgorithm::EdDSA {
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8).map_err(|_| invalid())?;
+        Ok(PublicKey::Okp {
+            crv: "Ed25519".to_string(),
+            x: key_pair.public_key().as_ref().to_vec(),
+        })
+    } else {
+        Err(CryptoError::UnsupportedAlgorithm(algorithm.to_string()))
+    }
+}
+
+/// Returns the current system time as a UNIX timestamp in seconds.
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+/// Converts a signed duration in seconds, relative to now, into a UNIX timestamp.
+///
+/// Negative durations produce timestamps in the past, which is how already
+/// expired key pairs are created. The result saturates instead of overflowing.
+fn expiry_from_now(expiry_duration: i64) -> u64 {
+    unix_timestamp().saturating_add_signed(expiry_duration)
+}
+
 impl KeyPair {
-    /// Creates a new RSA `KeyPair` with the specified unique identifier (`kid`), key size, and expiry duration.
+    /// Creates a new RSA `KeyPair` with the configured key size and the given expiry duration.
     ///
     /// This function generates a new RSA key pair of the given size and sets its expiry based on the provided duration.
-    /// It encapsulates the generated key pair within a `KeyPair` struct along with a unique identifier and expiry timestamp.
+    /// The key pair starts out `Active`, signing from now until it expires; use
+    /// [`KeyPair::with_signing_window`] to narrow that.
+    /// Its `kid` is the RFC 7638 thumbprint of the public key; use
+    /// [`KeyPair::with_kid_prefix`] to namespace it.
     ///
     /// # Parameters
     ///
-    /// * `kid` - A unique identifier for the key pair. This is typically a UUID.
     /// * `expiry_duration` - The duration in seconds from the current time after which the key pair is considered expired.
     ///
     /// # Returns
@@ -70,84 +191,221 @@ impl KeyPair {
     ///
     /// - The RSA key generation fails due to invalid parameters or internal errors.
     /// - There are issues with system time retrieval.
-    pub fn new(kid: i64, expiry_duration: i64) -> Result<Self, CryptoError> {
+    pub fn new(expiry_duration: i64) -> Result<Self, CryptoError> {
         let key_size_str = dotenv::var("KEY_SIZE")?;
         let key_size = key_size_str.parse::<usize>().map_err(CryptoError::from)?;
 
+        Self::generate_rsa(SigningAlgorithm::RS256, key_size, expiry_duration)
+    }
+
+    /// Creates a new RSA `KeyPair` of `key_size` bits for the given RSA algorithm,
+    /// regardless of `KEY_SIZE`.
+    ///
+    /// # Parameters
+    ///
+    /// * `algorithm` - The RSA algorithm the key pair will sign with.
+    /// * `key_size` - The size of the modulus in bits, such as 2048 or 4096.
+    /// * `expiry_duration` - The duration in seconds from the current time after which the key pair is considered expired.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CryptoError::UnsupportedAlgorithm` if `algorithm` is not an RSA
+    /// algorithm, and `CryptoError::KeyPairError` if the key cannot be generated at
+    /// that size.
+    pub fn generate_rsa(
+        algorithm: SigningAlgorithm,
+        key_size: usize,
+        expiry_duration: i64,
+    ) -> Result<Self, CryptoError> {
+        if !algorithm.is_rsa() {
+            return Err(CryptoError::UnsupportedAlgorithm(algorithm.to_string()));
+        }
+
         let mut rng = OsRng;
         let private_key = RsaPrivateKey::new(&mut rng, key_size)?;
         let public_key = RsaPublicKey::from(&private_key);
 
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs() as u64;
+        Ok(Self::assemble(
+            algorithm,
+            PublicKey::Rsa(public_key),
+            PrivateKey::Rsa(Box::new(private_key)),
+            expiry_from_now(expiry_duration),
+        ))
+    }
 
-        let expiry = if expiry_duration < 0 {
-            current_time
-                .checked_sub(expiry_duration.abs() as u64)
-                .unwrap_or(0)
-        } else {
-            current_time
-                .checked_add(expiry_duration as u64)
-                .unwrap_or(u64::MAX)
-        };
+    /// Creates a new `KeyPair` for the given signing algorithm.
+    ///
+    /// RSA keys, whichever RSA algorithm they sign with, use the size configured in
+    /// `KEY_SIZE`. ECDSA and EdDSA keys have a
+    /// fixed size determined by their curve.
+    ///
+    /// # Parameters
+    ///
+    /// * `algorithm` - The algorithm the key pair will sign with.
+    /// * `expiry_duration` - The duration in seconds from the current time after which the key pair is considered expired.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `KEY_SIZE` is missing or invalid for RSA keys, or if key
+    /// generation fails.
+    pub fn generate(
+        algorithm: SigningAlgorithm,
+        expiry_duration: i64,
+    ) -> Result<Self, CryptoError> {
+        if algorithm.is_rsa() {
+            let mut key_pair = Self::new(expiry_duration)?;
+            key_pair.algorithm = algorithm;
+            return Ok(key_pair);
+        }
 
-        let private_key = Some(private_key);
+        let rng = SystemRandom::new();
+        let generated = match ecdsa_parameters(algorithm) {
+            Some((parameters, _)) => EcdsaKeyPair::generate_pkcs8(parameters, &rng),
+            None => Ed25519KeyPair::generate_pkcs8(&rng),
+        }
+        .map_err(|_| CryptoError::InvalidKey("failed to generate key pair"))?;
+        let pkcs8 = generated.as_ref().to_vec();
 
-        Ok(Self {
-            kid,
+        Ok(Self::assemble(
+            algorithm,
+            pkcs8_public_key(algorithm, &pkcs8)?,
+            PrivateKey::Pkcs8(pkcs8),
+            expiry_from_now(expiry_duration),
+        ))
+    }
+
+    /// Builds an `Active` key pair that signs from now until `expiry`, identified by
+    /// its thumbprint.
+    fn assemble(
+        algorithm: SigningAlgorithm,
+        public_key: PublicKey,
+        private_key: PrivateKey,
+        expiry: u64,
+    ) -> Self {
+        Self {
+            kid: public_key.thumbprint(),
+            algorithm,
             public_key,
-            private_key,
+            private_key: Some(private_key),
+            not_before: unix_timestamp(),
+            retire_at: expiry,
             expiry,
-        })
+            state: KeyState::Active,
+        }
     }
 
+    /// Rebuilds a `KeyPair` from a stored private key.
+    ///
+    /// RSA keys for every RSA algorithm are stored as PKCS#1 DER, and ECDSA and EdDSA keys as PKCS#8 DER.
+    /// The key pair starts out `Active`; use [`KeyPair::with_state`] to restore its
+    /// stored state.
+    ///
+    /// # Parameters
+    ///
+    /// * `kid` - The unique identifier of the stored key pair.
+    /// * `algorithm` - The algorithm the stored key pair signs with.
+    /// * `key` - The DER encoded private key.
+    /// * `expiry` - The absolute expiry of the key pair in UNIX timestamp format.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CryptoError::KeyPairError` if the bytes are not a valid RSA private
+    /// key, or `CryptoError::InvalidKey` if they are not a valid ECDSA or Ed25519 one.
     pub fn from_private_key(
-        kid: i64,
-        key: &Vec<u8>,
-        expiry_duration: i64,
+        kid: &str,
+        algorithm: SigningAlgorithm,
+        key: &[u8],
+        expiry: u64,
     ) -> Result<Self, CryptoError> {
-        let private_key = RsaPrivateKey::from_pkcs1_der(&key)
-            .expect("Failed to decode PKCS#1 DER bytes into RsaPrivateKey");
-        let public_key = RsaPublicKey::from(&private_key);
-
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs() as u64;
-
-        let expiry = if expiry_duration < 0 {
-            current_time
-                .checked_sub(expiry_duration.abs() as u64)
-                .unwrap_or(0)
+        let (public_key, private_key) = if algorithm.is_rsa() {
+            let private_key =
+                RsaPrivateKey::from_pkcs1_der(key).map_err(rsa::errors::Error::from)?;
+            let public_key = RsaPublicKey::from(&private_key);
+            (
+                PublicKey::Rsa(public_key),
+                PrivateKey::Rsa(Box::new(private_key)),
+            )
         } else {
-            current_time
-                .checked_add(expiry_duration as u64)
-                .unwrap_or(u64::MAX)
+            (
+                pkcs8_public_key(algorithm, key)?,
+                PrivateKey::Pkcs8(key.to_vec()),
+            )
         };
 
-        let private_key = Some(private_key);
-
         Ok(Self {
-            kid,
+            kid: kid.to_string(),
+            algorithm,
             public_key,
-            private_key,
+            private_key: Some(private_key),
+            not_before: 0,
+            retire_at: expiry,
             expiry,
+            state: KeyState::Active,
         })
     }
 
-    /// Checks whether the key pair has expired based on the current system time.
+    /// Prepends `prefix` to the key pair's `kid`, for example to tell apart keys from
+    /// different environments.
+    pub fn with_kid_prefix(mut self, prefix: &str) -> Self {
+        self.kid.insert_str(0, prefix);
+        self
+    }
+
+    /// Sets the window during which the key pair signs new tokens.
     ///
-    /// # Returns
+    /// # Parameters
     ///
-    /// `true` if the key pair has expired, `false` otherwise.
-    pub fn is_expired(&self) -> bool {
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs() as u64;
-        self.expiry < current_time
+    /// * `not_before` - The UNIX timestamp from which the key pair may sign.
+    /// * `retire_at` - The UNIX timestamp after which the key pair stops signing.
+    pub fn with_signing_window(mut self, not_before: u64, retire_at: u64) -> Self {
+        self.not_before = not_before;
+        self.retire_at = retire_at;
+        self
+    }
+
+    /// Sets the lifecycle state of the key pair.
+    pub fn with_state(mut self, state: KeyState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Works out which state the key pair's schedule puts it in at time `now`.
+    ///
+    /// Only the rotation scheduler should compare timestamps; everything else reads
+    /// `state`. Revoked and expired key pairs never move to another state.
+    ///
+    /// # Parameters
+    ///
+    /// * `now` - The time to evaluate the schedule at, as a UNIX timestamp.
+    pub fn scheduled_state(&self, now: u64) -> KeyState {
+        match self.state {
+            KeyState::Revoked | KeyState::Expired => self.state,
+            _ if self.expiry <= now => KeyState::Expired,
+            _ if self.retire_at <= now => KeyState::Retiring,
+            _ if self.not_before <= now => KeyState::Active,
+            _ => KeyState::Pending,
+        }
+    }
+
+    /// Encodes the private key as DER for storage.
+    ///
+    /// RSA keys are encoded as PKCS#1, and ECDSA and EdDSA keys as PKCS#8.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CryptoError::InvalidKey` if the key pair has no private key, or
+    /// `CryptoError::KeyPairError` if the RSA key cannot be encoded.
+    pub fn to_private_key_der(&self) -> Result<Vec<u8>, CryptoError> {
+        match &self.private_key {
+            Some(PrivateKey::Rsa(private_key)) => {
+                let der = private_key
+                    .to_pkcs1_der()
+                    .map_err(rsa::errors::Error::from)?;
+                Ok(der.as_bytes().to_vec())
+            }
+            Some(PrivateKey::Pkcs8(der)) => Ok(der.clone()),
+            None => Err(CryptoError::InvalidKey("key pair has no private key")),
+        }
     }
 }
 
@@ -157,12 +415,15 @@ mod tests {
 
     #[test]
     fn key_pair_generation() {
-        let kid = 1;
         let expiry_duration: i64 = 3600;
 
-        let key_pair = KeyPair::new(kid, expiry_duration).unwrap();
+        let key_pair = KeyPair::new(expiry_duration).unwrap();
 
-        assert_eq!(key_pair.kid, kid);
+        assert_eq!(key_pair.kid, key_pair.public_key.thumbprint());
+        assert_eq!(
+            key_pair.clone().with_kid_prefix("prod-").kid,
+            format!("prod-{}", key_pair.kid)
+        );
         assert!(key_pair.private_key.is_some());
         // Check if the expiry is roughly in the future by at least the expiry duration minus a small delta
         let now_i64 = SystemTime::now()
@@ -173,7 +434,7 @@ mod tests {
         let now_u64 = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards")
-            .as_secs() as u64;
+            .as_secs();
 
         assert!(key_pair.expiry > now_u64 && key_pair.expiry <= (now_i64 + expiry_duration) as u64);
     }
@@ -181,10 +442,66 @@ mod tests {
     #[test]
     fn key_pair_expiry() {
         let expiry_duration = 1; // 1 second
-        let key_pair = KeyPair::new(1, expiry_duration).unwrap();
+        let key_pair = KeyPair::new(expiry_duration).unwrap();
 
         // Sleep for 2 seconds to ensure the key expires
         std::thread::sleep(std::time::Duration::new(2, 0));
-        assert!(key_pair.is_expired());
+        assert_eq!(
+            key_pair.scheduled_state(unix_timestamp()),
+            KeyState::Expired
+        );
+    }
+
+    #[test]
+    fn key_pair_scheduled_state() {
+        let key_pair = KeyPair::new(3600).unwrap();
+        let now = unix_timestamp();
+        assert_eq!(key_pair.scheduled_state(now), KeyState::Active);
+
+        let pending = key_pair.clone().with_signing_window(now + 600, now + 1200);
+        assert_eq!(pending.scheduled_state(now), KeyState::Pending);
+
+        let retiring = key_pair.clone().with_signing_window(now - 1200, now - 600);
+        assert_eq!(retiring.scheduled_state(now), KeyState::Retiring);
+
+        let revoked = key_pair.with_state(KeyState::Revoked);
+        assert_eq!(revoked.scheduled_state(now), KeyState::Revoked);
+    }
+
+    #[test]
+    fn key_pair_round_trips_for_every_algorithm() {
+        for algorithm in [
+            SigningAlgorithm::RS256,
+            SigningAlgorithm::PS256,
+            SigningAlgorithm::ES256,
+            SigningAlgorithm::ES384,
+            SigningAlgorithm::EdDSA,
+        ] {
+            let key_pair = KeyPair::generate(algorithm, 3600).unwrap();
+            let der = key_pair.to_private_key_der().unwrap();
+            let restored =
+                KeyPair::from_private_key(&key_pair.kid, algorithm, &der, key_pair.expiry).unwrap();
+
+            assert_eq!(restored.algorithm, algorithm);
+            assert_eq!(
+                restored.public_key, key_pair.public_key,
+                "{} public key should survive a round trip.",
+                algorithm
+            );
+        }
+    }
+
+    #[test]
+    fn key_pair_rejects_corrupt_pkcs8() {
+        for algorithm in [SigningAlgorithm::ES256, SigningAlgorithm::EdDSA] {
+            assert!(
+                matches!(
+                    KeyPair::from_private_key("kid", algorithm, b"corrupt", 0),
+                    Err(CryptoError::InvalidKey(_))
+                ),
+                "A corrupt stored {} key is a server error.",
+                algorithm
+            );
+        }
     }
 }
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+/// The lifecycle state of a signing key.
+///
+/// Keys normally move forward through `Pending`, `Active`, `Retiring` and `Expired`
+/// as the rotation schedule advances. `Revoked` can be entered from any state and
+/// is never left.
+#[derive(Serialize, Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum KeyState {
+    /// Published in the JWKS ahead of time, but not yet used for signing.
+    Pending,
+    /// Published and used to sign new tokens.
+    Active,
+    /// Still published so existing tokens verify, but no longer signs new tokens.
+    Retiring,
+    /// No longer published. Kept only until the retention period passes.
+    Expired,
+    /// Withdrawn before its schedule ended. Never published and never used to sign.
+    Revoked,
+}
+
+impl KeyState {
+    /// Returns the name of the state as it is stored in the `keys` table.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeyState::Pending => "pending",
+            KeyState::Active => "active",
+            KeyState::Retiring => "retiring",
+            KeyState::Expired => "expired",
+            KeyState::Revoked => "revoked",
+        }
+    }
+
+    /// Checks whether keys in this state belong in the JWKS.
+    pub fn is_published(self) -> bool {
+        matches!(
+            self,
+            KeyState::Pending | KeyState::Active | KeyState::Retiring
+        )
+    }
+
+    /// Checks whether keys in this state may sign new tokens.
+    pub fn can_sign(self) -> bool {
+        self == KeyState::Active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_published_and_signing_states() {
+        assert!(KeyState::Pending.is_published());
+        assert!(KeyState::Active.is_published());
+        assert!(KeyState::Retiring.is_published());
+        assert!(!KeyState::Expired.is_published());
+        assert!(!KeyState::Revoked.is_published());
+
+        assert!(KeyState::Active.can_sign());
+        assert!(!KeyState::Pending.can_sign());
+        assert!(!KeyState::Retiring.can_sign());
+        assert!(!KeyState::Revoked.can_sign());
+    }
+}
@@ -1,5 +1,14 @@
+pub mod algorithm;
+pub use algorithm::SigningAlgorithm;
+
+pub mod claims;
+pub use claims::{Claims, ClaimsBuilder, TokenPolicy};
+
+pub mod envelope;
+pub use envelope::KeyCipher;
+
 pub mod error;
-pub use error::CryptoError;
+pub use error::{CryptoError, TokenError};
 
 pub mod jwk;
 pub use jwk::Jwk;
@@ -12,3 +21,6 @@ pub use jwt::Jwt;
 
 pub mod key_pair;
 pub use key_pair::KeyPair;
+
+pub mod key_state;
+pub use key_state::KeyState;
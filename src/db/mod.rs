@@ -1,5 +1,393 @@
+use crate::crypto::{CryptoError, KeyCipher, KeyPair, KeyState, SigningAlgorithm};
+use serde::Serialize;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::SqlitePool;
+use std::str::FromStr;
+
 pub struct KeysTable {
-    pub kid: i64,
+    pub kid: String,
+    pub alg: SigningAlgorithm,
     pub key: Vec<u8>,
     pub exp: i64,
+    pub nbf: i64,
+    pub retire_at: i64,
+    pub state: KeyState,
+}
+
+/// The schedule and state of a stored key, without its private key.
+#[derive(Debug, Serialize)]
+pub struct KeyInfo {
+    /// The unique identifier of the key.
+    pub kid: String,
+    /// The algorithm the key signs with.
+    pub alg: SigningAlgorithm,
+    /// The lifecycle state of the key.
+    pub state: KeyState,
+    /// When the key starts signing, as a timestamp.
+    pub nbf: i64,
+    /// When the key stops signing, as a timestamp.
+    pub retire_at: i64,
+    /// When the key expires, as a timestamp.
+    pub exp: i64,
+}
+
+/// Connects to the SQLite database at `database_url`, creating the file if it does
+/// not exist yet.
+///
+/// # Errors
+///
+/// Returns `sqlx::Error::Configuration` if `database_url` is not a SQLite URL, or
+/// the error from opening the database.
+pub async fn connect(database_url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+
+    SqlitePool::connect_with(options).await
+}
+
+/// Applies every migration in `migrations/` that has not been applied yet. The
+/// migrations are embedded in the binary, so no other files are needed at runtime.
+pub async fn migrate(db_pool: &SqlitePool) -> Result<(), sqlx::migrate::MigrateError> {
+    sqlx::migrate!().run(db_pool).await
+}
+
+/// Loads every key pair from the `keys` table, decrypting the private keys.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `key_cipher` - The cipher used to open the encrypted private keys.
+///
+/// # Errors
+///
+/// Fails closed: if any row cannot be decrypted or decoded, no keys are returned and
+/// the error is propagated to the caller.
+pub async fn load_key_pairs(
+    db_pool: &SqlitePool,
+    key_cipher: &KeyCipher,
+) -> Result<Vec<KeyPair>, CryptoError> {
+    let rows: Vec<KeysTable> = sqlx::query_as!(
+        KeysTable,
+        r#"SELECT kid, alg AS "alg: SigningAlgorithm", key, exp, nbf, retire_at, state AS "state: KeyState" FROM keys"#
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            let der = key_cipher.decrypt(&row.key)?;
+            Ok(
+                KeyPair::from_private_key(&row.kid, row.alg, &der, row.exp as u64)?
+                    .with_signing_window(row.nbf as u64, row.retire_at as u64)
+                    .with_state(row.state),
+            )
+        })
+        .collect()
+}
+
+/// Encrypts a key pair's private key and stores it in the `keys` table under its `kid`.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `key_cipher` - The cipher used to seal the private key.
+/// * `key_pair` - The key pair to store.
+pub async fn insert_key_pair(
+    db_pool: &SqlitePool,
+    key_cipher: &KeyCipher,
+    key_pair: &KeyPair,
+) -> Result<(), CryptoError> {
+    let envelope = key_cipher.encrypt(&key_pair.to_private_key_der()?)?;
+    let expiry = key_pair.expiry as i64;
+    let not_before = key_pair.not_before as i64;
+    let retire_at = key_pair.retire_at as i64;
+
+    sqlx::query!(
+        "INSERT INTO keys (kid, alg, key, exp, nbf, retire_at, state) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        key_pair.kid,
+        key_pair.algorithm,
+        envelope,
+        expiry,
+        not_before,
+        retire_at,
+        key_pair.state
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Updates the lifecycle state of a stored key.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `kid` - The unique identifier of the key.
+/// * `state` - The new state of the key.
+///
+/// # Returns
+///
+/// `false` if there is no key with that `kid`.
+pub async fn update_key_state(
+    db_pool: &SqlitePool,
+    kid: &str,
+    state: KeyState,
+) -> Result<bool, CryptoError> {
+    let result = sqlx::query!("UPDATE keys SET state = ? WHERE kid = ?", state, kid)
+        .execute(db_pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Lists stored keys, newest first, a page at a time, without decrypting them.
+pub async fn list_keys(
+    db_pool: &SqlitePool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<KeyInfo>, CryptoError> {
+    let keys = sqlx::query_as!(
+        KeyInfo,
+        r#"SELECT kid, alg AS "alg: SigningAlgorithm", state AS "state: KeyState", nbf, retire_at, exp
+           FROM keys ORDER BY nbf DESC, kid LIMIT ? OFFSET ?"#,
+        limit,
+        offset
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    Ok(keys)
+}
+
+/// Counts the stored keys.
+pub async fn count_keys(db_pool: &SqlitePool) -> Result<i64, CryptoError> {
+    let count = sqlx::query_scalar!("SELECT COUNT(*) FROM keys")
+        .fetch_one(db_pool)
+        .await?;
+
+    Ok(count.into())
+}
+
+/// Ends the signing window of every active key at `now`, and brings forward the
+/// signing window of every pending key so that it starts at `now`.
+///
+/// # Returns
+///
+/// The number of keys that were retired.
+pub async fn retire_active_keys(db_pool: &SqlitePool, now: i64) -> Result<u64, CryptoError> {
+    let mut tx = db_pool.begin().await?;
+
+    let retired = sqlx::query!(
+        "UPDATE keys SET retire_at = ?, state = 'retiring' WHERE state = 'active'",
+        now
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    sqlx::query!(
+        "UPDATE keys SET nbf = ? WHERE state = 'pending' AND nbf > ?",
+        now,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(retired)
+}
+
+/// Deletes every expired or revoked key whose expiry is earlier than the given timestamp.
+///
+/// # Returns
+///
+/// The number of keys that were deleted.
+pub async fn purge_keys_expired_before(
+    db_pool: &SqlitePool,
+    timestamp: i64,
+) -> Result<u64, CryptoError> {
+    let result = sqlx::query!(
+        "DELETE FROM keys WHERE state IN ('expired', 'revoked') AND exp < ?",
+        timestamp
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Re-encrypts every stored private key that was sealed by a previous master key.
+///
+/// This completes a master secret rotation: after it succeeds, the old secret can be
+/// removed from `NOT_MY_KEY_PREVIOUS`. Keys stored in plaintext, as DER, by versions
+/// from before private keys were encrypted are sealed too. All rows are rewritten in
+/// one transaction, so a failure leaves the table unchanged.
+///
+/// # Returns
+///
+/// The number of rows that were re-encrypted.
+///
+/// # Errors
+///
+/// Returns `CryptoError::DecryptionError` if a key is neither sealed by a known master
+/// key nor a plaintext private key.
+pub async fn reencrypt_keys(
+    db_pool: &SqlitePool,
+    key_cipher: &KeyCipher,
+) -> Result<usize, CryptoError> {
+    let mut tx = db_pool.begin().await?;
+
+    let rows: Vec<KeysTable> = sqlx::query_as!(
+        KeysTable,
+        r#"SELECT kid, alg AS "alg: SigningAlgorithm", key, exp, nbf, retire_at, state AS "state: KeyState" FROM keys"#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut reencrypted = 0;
+    for row in rows
+        .iter()
+        .filter(|row| key_cipher.needs_reencryption(&row.key))
+    {
+        let der = match key_cipher.decrypt(&row.key) {
+            Ok(der) => der,
+            Err(err) => {
+                // A DER private key never starts with the envelope's version byte, so
+                // a blob that parses as one was stored before keys were encrypted.
+                if KeyPair::from_private_key(&row.kid, row.alg, &row.key, row.exp as u64).is_err() {
+                    return Err(err);
+                }
+                warn!("Encrypting the plaintext private key of key {}", row.kid);
+                row.key.clone()
+            }
+        };
+        let envelope = key_cipher.encrypt(&der)?;
+        sqlx::query!("UPDATE keys SET key = ? WHERE kid = ?", envelope, row.kid)
+            .execute(&mut *tx)
+            .await?;
+        reencrypted += 1;
+    }
+
+    tx.commit().await?;
+    Ok(reencrypted)
+}
+
+/// Replaces every `kid` that is not derived from its key's thumbprint with
+/// `kid_prefix` followed by the RFC 7638 thumbprint of the public key.
+///
+/// This upgrades the integer kids used before thumbprints were introduced. Kids
+/// that already end in their thumbprint are left alone, even if they carry an
+/// older prefix. All rows are rewritten in one transaction.
+///
+/// # Returns
+///
+/// The number of keys that were relabelled.
+pub async fn relabel_legacy_kids(
+    db_pool: &SqlitePool,
+    key_cipher: &KeyCipher,
+    kid_prefix: &str,
+) -> Result<usize, CryptoError> {
+    let mut tx = db_pool.begin().await?;
+
+    let rows: Vec<KeysTable> = sqlx::query_as!(
+        KeysTable,
+        r#"SELECT kid, alg AS "alg: SigningAlgorithm", key, exp, nbf, retire_at, state AS "state: KeyState" FROM keys"#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut relabelled = 0;
+    for row in &rows {
+        let der = key_cipher.decrypt(&row.key)?;
+        let thumbprint = KeyPair::from_private_key(&row.kid, row.alg, &der, row.exp as u64)?
+            .public_key
+            .thumbprint();
+        if row.kid.ends_with(&thumbprint) {
+            continue;
+        }
+
+        let kid = format!("{kid_prefix}{thumbprint}");
+        sqlx::query!("UPDATE keys SET kid = ? WHERE kid = ?", kid, row.kid)
+            .execute(&mut *tx)
+            .await?;
+        relabelled += 1;
+    }
+
+    tx.commit().await?;
+    Ok(relabelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::envelope::MasterKey;
+
+    #[tokio::test]
+    async fn test_relabel_legacy_kids() {
+        let db_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+
+        let key_pair = KeyPair::generate(SigningAlgorithm::ES256, 3600).unwrap();
+        let legacy = KeyPair {
+            kid: "1".to_string(),
+            ..key_pair.clone()
+        };
+        insert_key_pair(&db_pool, &key_cipher, &legacy)
+            .await
+            .unwrap();
+
+        let relabelled = relabel_legacy_kids(&db_pool, &key_cipher, "prod-")
+            .await
+            .unwrap();
+        assert_eq!(relabelled, 1);
+
+        let key_pairs = load_key_pairs(&db_pool, &key_cipher).await.unwrap();
+        assert_eq!(key_pairs[0].kid, format!("prod-{}", key_pair.kid));
+
+        let relabelled = relabel_legacy_kids(&db_pool, &key_cipher, "other-")
+            .await
+            .unwrap();
+        assert_eq!(relabelled, 0, "Thumbprint kids should be left alone.");
+    }
+
+    #[tokio::test]
+    async fn test_reencrypt_plaintext_keys() {
+        let db_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+
+        // A row as the server wrote it before private keys were encrypted: an integer
+        // kid and a plaintext PKCS#1 DER RSA key.
+        let key_pair = KeyPair::generate_rsa(SigningAlgorithm::RS256, 2048, 3600).unwrap();
+        let der = key_pair.to_private_key_der().unwrap();
+        let expiry = key_pair.expiry as i64;
+        sqlx::query!(
+            "INSERT INTO keys (kid, key, exp) VALUES ('1', ?, ?)",
+            der,
+            expiry
+        )
+        .execute(&db_pool)
+        .await
+        .unwrap();
+
+        assert_eq!(reencrypt_keys(&db_pool, &key_cipher).await.unwrap(), 1);
+        assert_eq!(
+            relabel_legacy_kids(&db_pool, &key_cipher, "")
+                .await
+                .unwrap(),
+            1
+        );
+        let key_pairs = load_key_pairs(&db_pool, &key_cipher).await.unwrap();
+        assert_eq!(key_pairs[0].kid, key_pair.kid);
+
+        let stored: (Vec<u8>,) = sqlx::query_as("SELECT key FROM keys")
+            .fetch_one(&db_pool)
+            .await
+            .unwrap();
+        assert_ne!(
+            stored.0, der,
+            "The key must no longer be stored in plaintext."
+        );
+        assert_eq!(reencrypt_keys(&db_pool, &key_cipher).await.unwrap(), 0);
+    }
 }
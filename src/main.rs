@@ -1,115 +1,285 @@
 #[macro_use]
 extern crate rocket;
 
-use auth::RateLimiter;
-use crypto::KeyPair;
-use dotenv;
-use rand::rngs::StdRng;
-use rand::{Rng, SeedableRng};
+use auth::{AdminApiKey, LockoutPolicy};
+use clap::Parser;
+use cli::{Cli, Command, ServeArgs};
+use crypto::key_pair::unix_timestamp;
+use crypto::{KeyCipher, TokenPolicy};
+use net::TrustedProxies;
+use rate_limit::RateLimits;
 use rocket::fairing::AdHoc;
-use rsa::pkcs1::EncodeRsaPrivateKey;
+use rocket::{Build, Rocket};
+use rotation::RotationPolicy;
 use sqlx::SqlitePool;
-use std::time::Duration;
+use std::process::ExitCode;
 
 mod auth;
+mod cli;
 mod crypto;
 mod db;
+mod net;
+mod rate_limit;
+mod revocation;
+mod rotation;
 mod routes;
 
-/// Launches the Rocket web server with configured routes and database pool.
+/// Runs the subcommand given on the command line, or the server if there is none.
+///
+/// Management commands print their output and exit with a failure status if they
+/// fail; see [`cli::run`].
+#[rocket::main]
+async fn main() -> ExitCode {
+    // Load environment variables from the .env file, if present.
+    dotenv::dotenv().ok();
+
+    let result = match Cli::parse().command {
+        None => serve(ServeArgs::default()).await,
+        Some(Command::Serve(args)) => serve(args).await,
+        Some(command) => cli::run(command)
+            .await
+            .map(|output| println!("{output}"))
+            .map_err(|err| err.to_string()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the server until it shuts down.
+async fn serve(args: ServeArgs) -> Result<(), String> {
+    match rocket(args).await.launch().await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.pretty_print().to_string()),
+    }
+}
+
+/// Prepares the Rocket web server with configured routes and database pool.
 ///
 /// This function initializes the Rocket instance, sets up the database connection pool,
 /// and mounts the application's routes. It reads the `DATABASE_URL` from the environment,
-/// connects to the SQLite database, and injects the connection pool into Rocket's state
-/// for use across the application.
+/// connects to the SQLite database, creating it if needed, and applies any pending
+/// migrations from `migrations/`, which are embedded in the binary. Existing data is
+/// kept. The connection pool is then injected into Rocket's state for use across the
+/// application. Signing keys are brought up to date with the
+/// rotation policy before launch and then maintained by a background task.
 ///
 /// # Panics
 /// The function panics if:
-/// - The `DATABASE_URL` environment variable is not set.
-/// - The connection to the SQLite database fails.
+/// - The `DATABASE_URL` or `NOT_MY_KEY` environment variable is not set.
+/// - The connection to the SQLite database fails, or the migrations cannot be applied.
+/// - `--seed-demo` was given and the demo user cannot be added.
+/// - Stored private keys cannot be re-encrypted under the current master key, or
+///   legacy key IDs cannot be replaced with thumbprints.
+/// - The key rotation policy is invalid or the initial signing keys cannot be created.
+/// - `RATE_LIMIT_STORE` names a store other than `memory` or `sqlite`.
+///
+/// # Arguments
+/// * `args` - The options of the `serve` command, such as `--seed-demo`.
 ///
 /// # Returns
 /// A configured `rocket::Rocket` instance ready for launching.
-#[launch]
-async fn rocket() -> _ {
-    // Load environment variables from the .env file, if present.
-    dotenv::dotenv().ok();
-
+async fn rocket(args: ServeArgs) -> Rocket<Build> {
     let database_url = dotenv::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let db_pool = SqlitePool::connect(&database_url)
+    let db_pool = db::connect(&database_url)
         .await
         .expect("Failed to create pool");
+    db::migrate(&db_pool)
+        .await
+        .expect("Failed to apply database migrations");
 
-    sqlx::query!("DELETE FROM keys")
-        .execute(&db_pool)
+    // Rocket's logger is only set up when it ignites, so what is done here is logged
+    // once the server has launched.
+    let mut startup = Vec::new();
+    if args.seed_demo
+        && cli::user::seed_demo(&db_pool)
+            .await
+            .expect("Failed to add the demo user")
+    {
+        startup.push("Added the demo user 'test' with the password 'password'".to_string());
+    }
+
+    let key_cipher = KeyCipher::from_env().expect("NOT_MY_KEY must be set");
+    let reencrypted = db::reencrypt_keys(&db_pool, &key_cipher)
         .await
-        .expect("err");
+        .expect("Failed to re-encrypt private keys");
+    if reencrypted > 0 {
+        startup.push(format!(
+            "Re-encrypted {reencrypted} private keys under the current master key"
+        ));
+    }
 
-    sqlx::query!("DELETE FROM auth_logs")
-        .execute(&db_pool)
+    let clients = auth::client::register_clients_from_env(&db_pool)
         .await
-        .expect("err");
+        .expect("Failed to register OAUTH_CLIENTS");
+    if clients > 0 {
+        startup.push(format!("Registered {clients} clients from OAUTH_CLIENTS"));
+    }
 
-    sqlx::query!("DELETE FROM users")
-        .execute(&db_pool)
+    let rotation_policy = RotationPolicy::from_env().expect("Invalid key rotation policy");
+    let relabelled = db::relabel_legacy_kids(&db_pool, &key_cipher, &rotation_policy.kid_prefix)
         .await
-        .expect("err");
+        .expect("Failed to relabel key IDs");
+    if relabelled > 0 {
+        startup.push(format!(
+            "Replaced {relabelled} legacy key IDs with thumbprints"
+        ));
+    }
 
-    sqlx::query!(
-        "INSERT INTO users (id, username, email, password_hash) VALUES (1, 'test', 'test@test.com', 'password')"
-    )
-    .execute(&db_pool)
-    .await
-    .expect("err");
+    rotation::rotate_keys(&db_pool, &key_cipher, &rotation_policy, unix_timestamp())
+        .await
+        .expect("Failed to prepare signing keys");
 
-    let mut rng = StdRng::from_rng(rand::thread_rng()).expect("Failed to seed StdRng");
-    let mut key_pairs = Vec::<KeyPair>::new();
-    for i in 0..40 {
-        let expiry: i64 = rng.gen_range(-360_000..=360_000);
-        let key_pair = KeyPair::new(i, expiry).unwrap();
-        key_pairs.push(key_pair.clone());
-        let expiry_i32 = key_pair.expiry as i32;
-        let der_bytes: Vec<u8> = key_pair
-            .private_key
-            .unwrap()
-            .to_pkcs1_der()
-            .unwrap()
-            .as_bytes()
-            .to_vec();
+    let rate_limit_store = rate_limit::policy::store_from_env(&db_pool)
+        .expect("RATE_LIMIT_STORE must be memory or sqlite");
 
-        sqlx::query!(
-            "INSERT INTO keys (kid, key, exp) VALUES (?, ?, ?)",
-            key_pair.kid,
-            der_bytes,
-            expiry_i32
-        )
-        .execute(&db_pool)
-        .await
-        .expect("err");
-    }
+    build_rocket(
+        db_pool,
+        key_cipher,
+        rotation_policy,
+        TokenPolicy::from_env().expect("Invalid token policy"),
+        LockoutPolicy::from_env().expect("Invalid lockout policy"),
+        AdminApiKey::from_env(),
+        RateLimits::from_env().with_store(rate_limit_store),
+        TrustedProxies::from_env(),
+    )
+    .attach(AdHoc::on_liftoff("Startup", |_| {
+        Box::pin(async move {
+            for message in startup {
+                info!("{}", message);
+            }
+        })
+    }))
+}
 
+/// Builds the Rocket instance with every route, catcher and piece of managed state.
+///
+/// This is separate from [`rocket`], which also prepares the database and reads the
+/// configuration from the environment, so that tests can run the whole server
+/// in-process with Rocket's local client.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to a migrated SQLite database.
+/// * `key_cipher` - The cipher used to open the stored private keys.
+/// * `rotation_policy` - The key rotation policy.
+/// * `token_policy` - The issuer and lifetimes of issued tokens.
+/// * `lockout_policy` - How accounts are locked after failed logins.
+/// * `admin_api_key` - The key that grants access to the `/admin` endpoints.
+/// * `rate_limits` - The rate limit policies of the routes that have them.
+/// * `trusted_proxies` - The reverse proxies whose forwarding headers are believed.
+#[allow(clippy::too_many_arguments)]
+pub fn build_rocket(
+    db_pool: SqlitePool,
+    key_cipher: KeyCipher,
+    rotation_policy: RotationPolicy,
+    token_policy: TokenPolicy,
+    lockout_policy: LockoutPolicy,
+    admin_api_key: AdminApiKey,
+    rate_limits: RateLimits,
+    trusted_proxies: TrustedProxies,
+) -> Rocket<Build> {
     rocket::build()
         .attach(AdHoc::on_ignite("SQLite Database", |rocket| async {
             rocket.manage(db_pool)
         }))
-        .manage(key_pairs)
-        .manage(RateLimiter::new(10, Duration::from_secs(1)))
+        .manage(key_cipher)
+        .manage(rotation_policy)
+        .manage(token_policy)
+        .manage(lockout_policy)
+        .manage(admin_api_key)
+        .attach(rotation::scheduler())
+        .manage(trusted_proxies)
+        .manage(rate_limits)
+        .attach(rate_limit::evictor())
+        .attach(rate_limit::headers())
         .mount(
             "/",
             routes![
                 routes::index,
                 routes::auth,
                 routes::get_jwks,
-                routes::register
+                routes::openid_configuration,
+                routes::register,
+                routes::verify,
+                routes::introspect,
+                routes::authorize_form,
+                routes::authorize,
+                routes::token,
+                routes::revoke,
+                routes::userinfo,
+                routes::userinfo_post,
+                routes::admin_revoke,
+                routes::admin_list_users,
+                routes::admin_create_user,
+                routes::admin_get_user,
+                routes::admin_delete_user,
+                routes::admin_disable_user,
+                routes::admin_enable_user,
+                routes::admin_unlock_user,
+                routes::admin_reset_password,
+                routes::admin_set_roles,
+                routes::admin_list_keys,
+                routes::admin_rotate_keys,
+                routes::admin_revoke_key,
+                routes::admin_list_clients,
+                routes::admin_get_client,
+                routes::admin_put_client,
+                routes::admin_delete_client
             ],
         )
         .register("/auth", catchers![routes::not_found_to_method_not_allow])
+        .register("/verify", catchers![routes::not_found_to_method_not_allow])
+        .register(
+            "/introspect",
+            catchers![
+                routes::not_found_to_method_not_allow,
+                routes::invalid_client
+            ],
+        )
+        .register(
+            "/authorize",
+            catchers![routes::not_found_to_method_not_allow],
+        )
+        .register(
+            "/token",
+            catchers![
+                routes::not_found_to_method_not_allow,
+                routes::invalid_client
+            ],
+        )
+        .register(
+            "/revoke",
+            catchers![
+                routes::not_found_to_method_not_allow,
+                routes::invalid_client
+            ],
+        )
+        .register(
+            "/userinfo",
+            catchers![routes::not_found_to_method_not_allow],
+        )
         .register(
             "/.well-known/jwks.json",
             catchers![routes::not_found_to_method_not_allow],
         )
+        .register(
+            "/.well-known/openid-configuration",
+            catchers![routes::not_found_to_method_not_allow],
+        )
         .register(
             "/",
-            catchers![routes::not_found, routes::method_not_allowed],
+            catchers![
+                routes::not_found,
+                routes::method_not_allowed,
+                routes::unauthorized,
+                routes::forbidden,
+                routes::too_many_requests
+            ],
         )
 }
@@ -0,0 +1,103 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Parses a node as proxies report it: an IP address, optionally quoted, with IPv6
+/// addresses optionally in brackets and either optionally followed by a port, as in
+/// `"[2001:db8::17]:4711"` or `192.0.2.60:443`.
+///
+/// # Returns
+///
+/// `None` for anything else, such as the `unknown` and obfuscated identifiers that
+/// RFC 7239 section 6 allows.
+pub fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    let ip = if let Some(bracketed) = node.strip_prefix('[') {
+        bracketed.split_once(']')?.0.parse().ok()?
+    } else if let Ok(ip) = node.parse() {
+        ip
+    } else {
+        let (host, port) = node.rsplit_once(':')?;
+        port.parse::<u16>().ok()?;
+        IpAddr::V4(host.parse::<Ipv4Addr>().ok()?)
+    };
+
+    Some(ip.to_canonical())
+}
+
+/// Parses the `for` parameters of `Forwarded` headers, as described in RFC 7239.
+///
+/// # Arguments
+///
+/// * `values` - Every `Forwarded` header of the request, in order.
+///
+/// # Returns
+///
+/// One entry for each hop, from the original client to the nearest proxy, which is
+/// `None` if the hop has no `for` parameter or does not give an IP address.
+pub fn parse_forwarded(values: &[&str]) -> Vec<Option<IpAddr>> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .filter(|element| !element.trim().is_empty())
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| parse_node(node))
+        })
+        .collect()
+}
+
+/// Parses `X-Forwarded-For` headers, the de facto standard that `Forwarded` replaces.
+///
+/// # Arguments
+///
+/// * `values` - Every `X-Forwarded-For` header of the request, in order.
+///
+/// # Returns
+///
+/// One entry for each hop, from the original client to the nearest proxy, which is
+/// `None` if the hop is not an IP address.
+pub fn parse_x_forwarded_for(values: &[&str]) -> Vec<Option<IpAddr>> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .filter(|node| !node.trim().is_empty())
+        .map(parse_node)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_forwarding_headers() {
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+
+        // The examples from RFC 7239 section 4.
+        assert_eq!(
+            parse_forwarded(&[
+                "for=\"_gazonk\"",
+                "For=\"[2001:db8:cafe::17]:4711\"",
+                "for=192.0.2.60;proto=http;by=203.0.113.43",
+                "for=192.0.2.43, for=198.51.100.17"
+            ]),
+            vec![
+                None,
+                ip("2001:db8:cafe::17"),
+                ip("192.0.2.60"),
+                ip("192.0.2.43"),
+                ip("198.51.100.17")
+            ]
+        );
+        assert_eq!(parse_forwarded(&["proto=https"]), vec![None]);
+
+        assert_eq!(
+            parse_x_forwarded_for(&["203.0.113.195, 70.41.3.18:8080", "::ffff:10.0.0.1,"]),
+            vec![ip("203.0.113.195"), ip("70.41.3.18"), ip("10.0.0.1")]
+        );
+        assert_eq!(parse_x_forwarded_for(&["unknown"]), vec![None]);
+    }
+}
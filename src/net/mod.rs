@@ -0,0 +1,196 @@
+pub mod cidr;
+pub use cidr::Cidr;
+
+pub mod forwarded;
+
+use rocket::Request;
+use std::net::IpAddr;
+
+/// The reverse proxies whose forwarding headers are believed.
+///
+/// Requests from anyone else are attributed to the address they connect from, and
+/// any `Forwarded`, `X-Forwarded-For` or `X-Real-IP` header they send is ignored,
+/// since a client can put whatever it likes in them.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    /// Trusts the proxies in `networks`.
+    pub fn new(networks: Vec<Cidr>) -> Self {
+        Self { networks }
+    }
+
+    /// Reads the `TRUSTED_PROXIES` environment variable, a comma separated list of
+    /// networks in CIDR notation such as `10.0.0.0/8,::1`.
+    ///
+    /// If the variable is unset, or names something that is not a network, no proxy
+    /// is trusted.
+    pub fn from_env() -> Self {
+        let networks = dotenv::var("TRUSTED_PROXIES").unwrap_or_default();
+        match Cidr::parse_list(&networks) {
+            Ok(networks) => Self::new(networks),
+            Err(entry) => {
+                warn!("Ignoring TRUSTED_PROXIES, '{}' is not a network", entry);
+                Self::default()
+            }
+        }
+    }
+
+    /// Whether `ip` is a trusted proxy.
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    /// Finds the address of the client that a request from `peer` was made for.
+    ///
+    /// When `peer` is trusted, the hops reported by `Forwarded` headers, or failing
+    /// that by `X-Forwarded-For` headers or `X-Real-IP`, are walked from the nearest
+    /// to the farthest, and the first hop that is not itself a trusted proxy is the
+    /// client. Each proxy appends the hop it received the request from, so the hops
+    /// left of that one are whatever the client claimed and are not believed. A hop
+    /// that is not an IP address stops the walk at the proxy that reported it.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - The address the request was received from.
+    /// * `forwarded` - Every `Forwarded` header of the request, in order.
+    /// * `x_forwarded_for` - Every `X-Forwarded-For` header of the request, in order.
+    /// * `x_real_ip` - The `X-Real-IP` header of the request, if any.
+    pub fn resolve(
+        &self,
+        peer: IpAddr,
+        forwarded: &[&str],
+        x_forwarded_for: &[&str],
+        x_real_ip: Option<&str>,
+    ) -> IpAddr {
+        let peer = peer.to_canonical();
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let hops = if !forwarded.is_empty() {
+            forwarded::parse_forwarded(forwarded)
+        } else if !x_forwarded_for.is_empty() {
+            forwarded::parse_x_forwarded_for(x_forwarded_for)
+        } else {
+            x_real_ip.map(forwarded::parse_node).into_iter().collect()
+        };
+
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            let Some(hop) = hop else {
+                break;
+            };
+            // Proxies may report IPv4 hops as IPv4-mapped IPv6 addresses.
+            let hop = hop.to_canonical();
+            client = hop;
+            if !self.is_trusted(hop) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+/// The client address of a request, once resolved.
+struct ResolvedIp(Option<IpAddr>);
+
+/// Finds the address of the client that made `request`, looking through the
+/// [`TrustedProxies`] in Rocket's managed state, if any; see
+/// [`TrustedProxies::resolve`]. The address is resolved once per request.
+///
+/// # Returns
+///
+/// `None` if the address the request was received from is not known, as when it
+/// comes through a Unix socket.
+pub fn client_ip(request: &Request<'_>) -> Option<IpAddr> {
+    request
+        .local_cache(|| {
+            let Some(peer) = request.remote().map(|remote| remote.ip()) else {
+                return ResolvedIp(None);
+            };
+            let default = TrustedProxies::default();
+            let trusted_proxies = request.rocket().state().unwrap_or(&default);
+            let headers = request.headers();
+
+            ResolvedIp(Some(trusted_proxies.resolve(
+                peer,
+                &headers.get("Forwarded").collect::<Vec<_>>(),
+                &headers.get("X-Forwarded-For").collect::<Vec<_>>(),
+                headers.get_one("X-Real-IP"),
+            )))
+        })
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_client_ip() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let proxies = TrustedProxies::new(Cidr::parse_list("10.0.0.0/8").unwrap());
+
+        assert_eq!(
+            proxies.resolve(ip("192.0.2.1"), &[], &["203.0.113.9"], None),
+            ip("192.0.2.1"),
+            "Headers from untrusted peers must be ignored."
+        );
+        assert_eq!(
+            proxies.resolve(
+                ip("10.0.0.1"),
+                &[],
+                &["1.1.1.1, 203.0.113.9, 10.0.0.2"],
+                None
+            ),
+            ip("203.0.113.9"),
+            "Hops left of the first untrusted one may be spoofed."
+        );
+        assert_eq!(
+            proxies.resolve(
+                ip("::ffff:10.0.0.1"),
+                &["for=198.51.100.17"],
+                &["203.0.113.9"],
+                None
+            ),
+            ip("198.51.100.17"),
+            "Forwarded is preferred to X-Forwarded-For."
+        );
+        assert_eq!(
+            proxies.resolve(
+                ip("10.0.0.1"),
+                &["for=198.51.100.17, for=\"[::ffff:10.0.0.2]\""],
+                &[],
+                None
+            ),
+            ip("198.51.100.17"),
+            "IPv4-mapped hops must be recognised as trusted proxies."
+        );
+        assert_eq!(
+            proxies.resolve(
+                ip("10.0.0.1"),
+                &[],
+                &["198.51.100.17, ::ffff:10.0.0.2"],
+                None
+            ),
+            ip("198.51.100.17")
+        );
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &[], &[], Some("198.51.100.17")),
+            ip("198.51.100.17")
+        );
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &["for=unknown"], &[], None),
+            ip("10.0.0.1")
+        );
+        assert_eq!(
+            proxies.resolve(ip("10.0.0.1"), &[], &["10.0.0.3, 10.0.0.2"], None),
+            ip("10.0.0.3"),
+            "When every hop is a proxy, the farthest is the client."
+        );
+    }
+}
@@ -0,0 +1,550 @@
+pub mod policy;
+pub use policy::{RateLimitPolicy, RateLimits, Subject};
+
+pub mod sqlite;
+pub use sqlite::SqliteStore;
+
+pub mod store;
+pub use store::{MemoryStore, RateLimitStore};
+
+use crate::net;
+use rocket::data::{self, Data, FromData};
+use rocket::fairing::AdHoc;
+use rocket::form::Form;
+use rocket::http::{Header, Method, Status};
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use rocket::serde::json::Json;
+use rocket::State;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::IpAddr;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How many independently locked shards the keys are spread over, so that requests
+/// from different clients rarely wait on the same lock.
+const SHARDS: usize = 16;
+
+/// The most keys a shard tracks. When a shard is full, the key closest to being
+/// idle is dropped, so memory stays bounded however many clients there are between
+/// two evictions.
+const MAX_KEYS_PER_SHARD: usize = 4_096;
+
+/// The outcome of checking a request against a [`RateLimiter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitDecision {
+    /// Whether the request may go ahead.
+    pub allowed: bool,
+    /// How many requests a client may make in a burst.
+    pub limit: u32,
+    /// How many more requests the client may make right away.
+    pub remaining: u32,
+    /// How long until the client may make a full burst again.
+    pub reset: Duration,
+    /// How long until the client may make another request, if this one was refused.
+    pub retry_after: Option<Duration>,
+}
+
+/// One shard of the keys, each with its theoretical arrival time.
+type Shard = HashMap<String, Instant>;
+
+/// Limits how often each client may make requests, allowing bursts of `limit`
+/// requests and `limit` requests per `window` on average.
+///
+/// This is a token bucket implemented with the generic cell rate algorithm (GCRA):
+/// for each key it stores only the theoretical arrival time (TAT) of the next
+/// request, which advances by `window / limit` with every request allowed. A
+/// request is refused while the TAT is more than `window` ahead of now.
+///
+/// A key whose TAT has passed has a full bucket, so forgetting it changes nothing;
+/// [`evictor`] drops such idle keys every `window`.
+///
+/// Clones share the same counters.
+#[derive(Clone)]
+pub struct RateLimiter {
+    shards: Arc<Vec<Mutex<Shard>>>,
+    hasher: RandomState,
+    limit: u32,
+    window: Duration,
+}
+
+impl RateLimiter {
+    /// Creates a `RateLimiter` that allows bursts of `limit` requests, refilled at
+    /// `limit` requests per `window`.
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter {
+            shards: Arc::new((0..SHARDS).map(|_| Mutex::default()).collect()),
+            hasher: RandomState::new(),
+            limit: limit.max(1),
+            window,
+        }
+    }
+
+    /// Checks a request from `key`, such as a client IP, and counts it if it is
+    /// allowed.
+    pub fn check(&self, key: &str) -> RateLimitDecision {
+        self.check_at(key, Instant::now())
+    }
+
+    /// Decides whether `key` could make a request now, without counting one.
+    pub fn peek(&self, key: &str) -> RateLimitDecision {
+        self.decide(key, Instant::now(), false)
+    }
+
+    /// Checks a request from `key` made at `now`.
+    fn check_at(&self, key: &str, now: Instant) -> RateLimitDecision {
+        self.decide(key, now, true)
+    }
+
+    /// Decides whether `key` may make a request at `now`, and counts it if it may and
+    /// `count` is set.
+    fn decide(&self, key: &str, now: Instant, count: bool) -> RateLimitDecision {
+        let interval = self.window / self.limit;
+        let shard = &self.shards[self.hasher.hash_one(key) as usize % SHARDS];
+        let mut shard = shard
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let tat = shard.get(key).copied().unwrap_or(now).max(now);
+        let next_tat = tat + interval;
+        let ahead = next_tat - now;
+
+        if ahead > self.window {
+            return RateLimitDecision {
+                allowed: false,
+                limit: self.limit,
+                remaining: 0,
+                reset: tat - now,
+                retry_after: Some(ahead - self.window),
+            };
+        }
+
+        if !count {
+            return RateLimitDecision {
+                allowed: true,
+                limit: self.limit,
+                remaining: ((self.window - ahead).as_nanos() / interval.as_nanos().max(1)) as u32
+                    + 1,
+                reset: tat - now,
+                retry_after: None,
+            };
+        }
+
+        if !shard.contains_key(key) && shard.len() >= MAX_KEYS_PER_SHARD {
+            if let Some(idlest) = shard
+                .iter()
+                .min_by_key(|(_, tat)| **tat)
+                .map(|(key, _)| key.clone())
+            {
+                shard.remove(&idlest);
+            }
+        }
+        shard.insert(key.to_string(), next_tat);
+
+        RateLimitDecision {
+            allowed: true,
+            limit: self.limit,
+            remaining: ((self.window - ahead).as_nanos() / interval.as_nanos().max(1)) as u32,
+            reset: ahead,
+            retry_after: None,
+        }
+    }
+
+    /// Drops the keys that have been idle long enough to have a full bucket again.
+    ///
+    /// # Returns
+    ///
+    /// The number of keys still tracked.
+    fn evict_idle(&self, now: Instant) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let mut shard = shard
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                shard.retain(|_, tat| *tat > now);
+                shard.len()
+            })
+            .sum()
+    }
+}
+
+/// The error of a request refused by a rate limit, which responds with
+/// `429 Too Many Requests`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyRequests;
+
+impl std::fmt::Display for TooManyRequests {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "too many requests")
+    }
+}
+
+impl std::error::Error for TooManyRequests {}
+
+/// The decision that [`headers`] describes in the response, kept with the request.
+#[derive(Default)]
+struct CachedDecision(Mutex<Option<RateLimitDecision>>);
+
+impl CachedDecision {
+    /// Keeps `decision`, unless it allows the request and an earlier one refused it.
+    fn record(&self, decision: RateLimitDecision) {
+        let mut cached = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !decision.allowed || cached.is_none_or(|cached| cached.allowed) {
+            *cached = Some(decision);
+        }
+    }
+
+    fn get(&self) -> Option<RateLimitDecision> {
+        *self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Whether a request has been counted under its route's policy, kept with the request
+/// so that it is counted once however many guards check it.
+#[derive(Default)]
+struct Counted(AtomicBool);
+
+/// A request guard for routes that are rate limited, by the policy of the route in
+/// [`RateLimits`].
+///
+/// When the policy is keyed by the client IP alone, or the request has no body, the
+/// guard checks the request itself and refuses it with `429 Too Many Requests`. When
+/// the key includes the username or `client_id`, which are in the request body, the
+/// route reads its body with [`RateLimitedBody`], which checks the request once the
+/// body has been read, and which also checks requests under IP keyed policies, for
+/// routes that do not take this guard. Routes that log users in call [`RateLimited::check_login`] and
+/// [`RateLimited::login_failed`] as well. Requests from allowlisted networks are never
+/// limited. Decisions are kept with the request so that [`headers`] can describe
+/// them in the response.
+pub struct RateLimited<'r> {
+    rate_limits: &'r RateLimits,
+    policy: Option<&'static str>,
+    ip: Option<IpAddr>,
+    exempt: bool,
+    counted: &'r Counted,
+    decision: &'r CachedDecision,
+}
+
+impl<'r> RateLimited<'r> {
+    /// Prepares to check `request`, without checking anything yet.
+    async fn for_request(request: &'r Request<'_>) -> RateLimited<'r> {
+        let rate_limits = request.guard::<&State<RateLimits>>().await.unwrap();
+        let ip = net::client_ip(request);
+
+        RateLimited {
+            rate_limits,
+            policy: policy::policy_for_path(request.uri().path().as_str()),
+            ip,
+            exempt: rate_limits.is_allowlisted(ip),
+            counted: request.local_cache(Counted::default),
+            decision: request.local_cache(CachedDecision::default),
+        }
+    }
+
+    /// Whether the route has a policy in force that is keyed by more than the client
+    /// IP, and so can only be checked once the body has been read.
+    fn needs_body(&self) -> bool {
+        self.policy
+            .and_then(|policy| self.rate_limits.policy(policy))
+            .is_some_and(RateLimitPolicy::needs_body)
+    }
+
+    /// Counts the request under the route's policy, unless it has been already.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TooManyRequests` if the policy refuses the request.
+    async fn check(
+        &self,
+        username: Option<&str>,
+        client_id: Option<&str>,
+    ) -> Result<(), TooManyRequests> {
+        let Some(policy) = self.policy.filter(|_| !self.exempt) else {
+            return Ok(());
+        };
+        if self.counted.0.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let subject = Subject {
+            ip: self.ip,
+            username,
+            client_id,
+        };
+
+        match self.rate_limits.check(policy, &subject).await {
+            Some(decision) => self.record(decision),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that the slow lane for failed logins is not exhausted for `username`,
+    /// without counting anything.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TooManyRequests` if too many logins have failed lately.
+    pub async fn check_login(&self, username: &str) -> Result<(), TooManyRequests> {
+        if self.exempt {
+            return Ok(());
+        }
+        let subject = Subject {
+            ip: self.ip,
+            username: Some(username),
+            client_id: None,
+        };
+
+        match self
+            .rate_limits
+            .peek(policy::LOGIN_FAILURES, &subject)
+            .await
+        {
+            // The slow lane only shows in the headers once it refuses logins.
+            Some(decision) if !decision.allowed => self.record(decision),
+            _ => Ok(()),
+        }
+    }
+
+    /// Keeps `decision` for the headers and turns it into a result.
+    fn record(&self, decision: RateLimitDecision) -> Result<(), TooManyRequests> {
+        self.decision.record(decision);
+        if decision.allowed {
+            Ok(())
+        } else {
+            Err(TooManyRequests)
+        }
+    }
+
+    /// Counts a failed login for `username` in the slow lane.
+    pub async fn login_failed(&self, username: &str) {
+        if self.exempt {
+            return;
+        }
+        let subject = Subject {
+            ip: self.ip,
+            username: Some(username),
+            client_id: None,
+        };
+        self.rate_limits
+            .check(policy::LOGIN_FAILURES, &subject)
+            .await;
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimited<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let rate_limited = RateLimited::for_request(request).await;
+
+        // Requests with a body are left to `RateLimitedBody` if the key needs the body.
+        let has_body = request.method() != Method::Get;
+        if !(has_body && rate_limited.needs_body()) && rate_limited.check(None, None).await.is_err()
+        {
+            return Outcome::Error((Status::TooManyRequests, ()));
+        }
+
+        Outcome::Success(rate_limited)
+    }
+}
+
+/// A request body that carries the username or `client_id` that a rate limit policy
+/// can be keyed by.
+pub trait RateLimitSubject {
+    /// The username being logged in or registered, if any.
+    fn username(&self) -> Option<&str> {
+        None
+    }
+
+    /// The OAuth client making `request`, if it names one.
+    fn client_id(&self, _request: &Request<'_>) -> Option<String> {
+        None
+    }
+}
+
+impl<T: RateLimitSubject> RateLimitSubject for Form<T> {
+    fn username(&self) -> Option<&str> {
+        T::username(self)
+    }
+
+    fn client_id(&self, request: &Request<'_>) -> Option<String> {
+        T::client_id(self, request)
+    }
+}
+
+impl<T: RateLimitSubject> RateLimitSubject for Json<T> {
+    fn username(&self) -> Option<&str> {
+        T::username(self)
+    }
+
+    fn client_id(&self, request: &Request<'_>) -> Option<String> {
+        T::client_id(self, request)
+    }
+}
+
+/// A data guard that reads a request body with `D` and then counts the request under
+/// the route's policy, keyed by the username or `client_id` in the body if the policy
+/// asks for them, refusing it with `429 Too Many Requests` before the route runs.
+///
+/// A request is counted once even if the route also takes [`RateLimited`], which
+/// counts requests under IP keyed policies before the body is read.
+#[derive(Debug)]
+pub struct RateLimitedBody<D>(pub D);
+
+impl<D> Deref for RateLimitedBody<D> {
+    type Target = D;
+
+    fn deref(&self) -> &D {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, D> FromData<'r> for RateLimitedBody<D>
+where
+    D: FromData<'r> + RateLimitSubject + Send,
+{
+    /// The error reading the body, or `None` if the rate limit refused the request.
+    type Error = Option<D::Error>;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let body = match D::from_data(request, data).await {
+            Outcome::Success(body) => body,
+            Outcome::Error((status, err)) => return Outcome::Error((status, Some(err))),
+            Outcome::Forward(forward) => return Outcome::Forward(forward),
+        };
+
+        let rate_limited = RateLimited::for_request(request).await;
+        let client_id = body.client_id(request);
+        if rate_limited
+            .check(body.username(), client_id.as_deref())
+            .await
+            .is_err()
+        {
+            return Outcome::Error((Status::TooManyRequests, None));
+        }
+
+        Outcome::Success(RateLimitedBody(body))
+    }
+}
+
+/// Creates a fairing that runs [`RateLimitStore::evict_idle`] for every policy in
+/// [`RateLimits`], each in a background task every `window` once Rocket has
+/// launched, so that clients that have gone away are forgotten.
+pub fn evictor() -> AdHoc {
+    AdHoc::on_liftoff("Rate Limit Evictor", |rocket| {
+        Box::pin(async move {
+            let Some(rate_limits) = rocket.state::<RateLimits>() else {
+                error!("Rate limit evictor is missing managed state; not starting");
+                return;
+            };
+
+            for (name, policy) in rate_limits.policies() {
+                let store = rate_limits.store();
+                let policy = policy.clone();
+                tokio::spawn(async move {
+                    let mut interval =
+                        tokio::time::interval(policy.window.max(Duration::from_secs(1)));
+                    loop {
+                        interval.tick().await;
+                        if let Err(err) = store.evict_idle(name, &policy).await {
+                            error!("Failed to evict idle '{}' rate limits: {}", name, err);
+                        }
+                    }
+                });
+            }
+        })
+    })
+}
+
+/// Rounds a duration up to whole seconds, as HTTP headers express it.
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Creates a fairing that describes the rate limit in the response to every request
+/// that was checked by [`RateLimited`]. When a request was refused, the headers describe
+/// the policy that refused it.
+///
+/// The headers are `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`,
+/// as in the IETF draft "RateLimit header fields for HTTP", and `Retry-After` as in
+/// RFC 9110 section 10.2.3 when the request was refused. Times are in seconds.
+pub fn headers() -> AdHoc {
+    AdHoc::on_response("Rate Limit Headers", |request, response| {
+        Box::pin(async move {
+            let Some(decision) = request.local_cache(CachedDecision::default).get() else {
+                return;
+            };
+
+            response.set_header(Header::new("RateLimit-Limit", decision.limit.to_string()));
+            response.set_header(Header::new(
+                "RateLimit-Remaining",
+                decision.remaining.to_string(),
+            ));
+            response.set_header(Header::new(
+                "RateLimit-Reset",
+                ceil_secs(decision.reset).to_string(),
+            ));
+            if let Some(retry_after) = decision.retry_after {
+                response.set_header(Header::new(
+                    "Retry-After",
+                    ceil_secs(retry_after).max(1).to_string(),
+                ));
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(3));
+        let start = Instant::now();
+
+        for remaining in [2, 1, 0] {
+            let decision = limiter.check_at("1.2.3.4", start);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+
+        let refused = limiter.check_at("1.2.3.4", start);
+        assert!(!refused.allowed, "A burst must be limited to `limit`.");
+        assert_eq!(refused.retry_after, Some(Duration::from_secs(1)));
+        assert!(
+            limiter.check_at("5.6.7.8", start).allowed,
+            "Clients must be limited separately."
+        );
+
+        assert_eq!(limiter.peek("9.9.9.9").remaining, 3);
+        assert_eq!(
+            limiter.peek("9.9.9.9").remaining,
+            3,
+            "Peeking must not count a request."
+        );
+
+        let refilled = limiter.check_at("1.2.3.4", start + Duration::from_secs(1));
+        assert!(refilled.allowed, "One request is refilled per second.");
+        assert_eq!(refilled.remaining, 0);
+
+        assert_eq!(limiter.evict_idle(start + Duration::from_secs(2)), 1);
+        assert_eq!(
+            limiter.evict_idle(start + Duration::from_secs(4)),
+            0,
+            "Keys must be dropped once their bucket is full again."
+        );
+    }
+}
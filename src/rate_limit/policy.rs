@@ -0,0 +1,379 @@
+use super::{MemoryStore, RateLimitDecision, RateLimitStore, SqliteStore};
+use crate::net::Cidr;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The policy for `POST /auth`.
+pub const AUTH: &str = "auth";
+/// The policy for the login form at `POST /authorize`.
+pub const AUTHORIZE: &str = "authorize";
+/// The policy for `POST /register`.
+pub const REGISTER: &str = "register";
+/// The policy for `POST /token`.
+pub const TOKEN: &str = "token";
+/// The policy for `GET /.well-known/jwks.json`.
+pub const JWKS: &str = "jwks";
+/// The slow lane that failed logins at `/auth` and `/authorize` are counted in. Once
+/// it is exhausted, further logins are refused without checking the password.
+pub const LOGIN_FAILURES: &str = "login_failures";
+
+/// The policy that applies to each rate limited path.
+const ROUTES: [(&str, &str); 5] = [
+    ("/auth", AUTH),
+    ("/authorize", AUTHORIZE),
+    ("/register", REGISTER),
+    ("/token", TOKEN),
+    ("/.well-known/jwks.json", JWKS),
+];
+
+/// Finds the name of the policy that applies to requests for `path`.
+pub fn policy_for_path(path: &str) -> Option<&'static str> {
+    ROUTES
+        .iter()
+        .find(|(route, _)| *route == path)
+        .map(|(_, policy)| *policy)
+}
+
+/// A part of the key that a policy counts requests under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyPart {
+    /// The client IP.
+    Ip,
+    /// The username being logged in or registered.
+    Username,
+    /// The OAuth client making the request.
+    ClientId,
+}
+
+impl FromStr for KeyPart {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "ip" => Ok(KeyPart::Ip),
+            "username" => Ok(KeyPart::Username),
+            "client_id" => Ok(KeyPart::ClientId),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Who a request comes from, as far as is known.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Subject<'a> {
+    pub ip: Option<IpAddr>,
+    pub username: Option<&'a str>,
+    pub client_id: Option<&'a str>,
+}
+
+/// How many requests may be made under each key, and what the key is made of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitPolicy {
+    /// How many requests may be made in a burst, and per `window` on average.
+    pub limit: u32,
+    pub window: Duration,
+    /// The parts that together identify who is limited; requests that share all of
+    /// them share a limit.
+    pub key: Vec<KeyPart>,
+}
+
+impl RateLimitPolicy {
+    /// Creates a policy of `limit` requests per `window` seconds.
+    pub fn new(limit: u32, window: u64, key: &[KeyPart]) -> Self {
+        Self {
+            limit,
+            window: Duration::from_secs(window),
+            key: key.to_vec(),
+        }
+    }
+
+    /// Parses the part of a `RATE_LIMITS` entry after the policy name, which has the
+    /// form `limit/seconds;key=ip+username`. The key is one or more of `ip`,
+    /// `username` and `client_id`, joined with `+`, and is `ip` if left out.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the limit or window is not a positive number, or the key has an
+    /// unknown part.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut attributes = spec.trim().split(';');
+        let (limit, window) = attributes.next()?.split_once('/')?;
+        let limit: u32 = limit.trim().parse().ok().filter(|limit| *limit > 0)?;
+        let window: u64 = window.trim().parse().ok().filter(|window| *window > 0)?;
+
+        let mut policy = Self::new(limit, window, &[KeyPart::Ip]);
+        for attribute in attributes {
+            match attribute.split_once('=')? {
+                ("key", key) => {
+                    policy.key = key
+                        .split('+')
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                        .ok()?
+                }
+                _ => return None,
+            }
+        }
+
+        Some(policy)
+    }
+
+    /// Whether the key has parts other than the client IP, which are only known once
+    /// the request body has been read.
+    pub fn needs_body(&self) -> bool {
+        self.key.iter().any(|part| *part != KeyPart::Ip)
+    }
+
+    /// Builds the key that `subject` is counted under. Unknown parts are left
+    /// empty, so that, for example, every request without a `client_id` shares one
+    /// limit under a policy keyed by `client_id`.
+    pub fn key_for(&self, subject: &Subject) -> String {
+        self.key
+            .iter()
+            .map(|part| match part {
+                KeyPart::Ip => subject.ip.map(|ip| ip.to_string()).unwrap_or_default(),
+                KeyPart::Username => subject.username.unwrap_or_default().to_string(),
+                KeyPart::ClientId => subject.client_id.unwrap_or_default().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Every rate limit policy in force, by name, the networks exempt from them, and the
+/// store their counters are kept in.
+pub struct RateLimits {
+    policies: HashMap<&'static str, RateLimitPolicy>,
+    allowlist: Vec<Cidr>,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl Default for RateLimits {
+    /// The default policies, with no allowlist.
+    fn default() -> Self {
+        Self::new(Self::default_policies(), Vec::new())
+    }
+}
+
+impl RateLimits {
+    /// Creates the given policies, counted in process memory. Requests from the
+    /// networks in `allowlist` are never limited.
+    pub fn new(
+        policies: impl IntoIterator<Item = (&'static str, RateLimitPolicy)>,
+        allowlist: Vec<Cidr>,
+    ) -> Self {
+        Self {
+            policies: policies.into_iter().collect(),
+            allowlist,
+            store: Arc::new(MemoryStore::default()),
+        }
+    }
+
+    /// Keeps the counters in `store` instead.
+    pub fn with_store(mut self, store: Arc<dyn RateLimitStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// The policies in force unless `RATE_LIMITS` says otherwise.
+    pub fn default_policies() -> Vec<(&'static str, RateLimitPolicy)> {
+        use KeyPart::Ip;
+
+        vec![
+            (AUTH, RateLimitPolicy::new(10, 1, &[Ip])),
+            (AUTHORIZE, RateLimitPolicy::new(10, 1, &[Ip])),
+            (REGISTER, RateLimitPolicy::new(5, 60, &[Ip])),
+            (TOKEN, RateLimitPolicy::new(10, 1, &[Ip])),
+            (JWKS, RateLimitPolicy::new(100, 1, &[Ip])),
+            (LOGIN_FAILURES, RateLimitPolicy::new(10, 300, &[Ip])),
+        ]
+    }
+
+    /// Reads the policies from the environment.
+    ///
+    /// `RATE_LIMITS` is a comma separated list of entries of the form
+    /// `name:limit/seconds;key=...`, which replace the default policy of that name,
+    /// or `name:off` to turn it off; see [`RateLimitPolicy::parse`].
+    /// `RATE_LIMIT_ALLOWLIST` is a comma separated list of networks in CIDR notation
+    /// that are exempt from every policy. Malformed entries are skipped with a
+    /// warning. The counters are kept in process memory; see [`store_from_env`].
+    pub fn from_env() -> Self {
+        let mut policies: HashMap<_, _> = Self::default_policies().into_iter().collect();
+
+        for entry in dotenv::var("RATE_LIMITS").unwrap_or_default().split(',') {
+            if entry.trim().is_empty() {
+                continue;
+            }
+            let Some((name, spec)) = entry.split_once(':') else {
+                warn!("Skipping malformed RATE_LIMITS entry '{}'", entry);
+                continue;
+            };
+            let Some((&name, _)) = policies.get_key_value(name.trim()) else {
+                warn!("Skipping RATE_LIMITS entry for unknown policy '{}'", name);
+                continue;
+            };
+
+            if spec.trim() == "off" {
+                policies.remove(name);
+            } else if let Some(policy) = RateLimitPolicy::parse(spec) {
+                policies.insert(name, policy);
+            } else {
+                warn!("Skipping malformed RATE_LIMITS entry '{}'", entry);
+            }
+        }
+
+        let allowlist = dotenv::var("RATE_LIMIT_ALLOWLIST").unwrap_or_default();
+        let allowlist = Cidr::parse_list(&allowlist).unwrap_or_else(|entry| {
+            warn!(
+                "Ignoring RATE_LIMIT_ALLOWLIST, '{}' is not a network",
+                entry
+            );
+            Vec::new()
+        });
+
+        Self::new(policies, allowlist)
+    }
+
+    /// Looks up a policy that is in force.
+    pub fn policy(&self, name: &str) -> Option<&RateLimitPolicy> {
+        self.policies.get(name)
+    }
+
+    /// Every policy in force, by name.
+    pub fn policies(&self) -> impl Iterator<Item = (&'static str, &RateLimitPolicy)> {
+        self.policies.iter().map(|(name, policy)| (*name, policy))
+    }
+
+    /// The store the counters are kept in.
+    pub fn store(&self) -> Arc<dyn RateLimitStore> {
+        self.store.clone()
+    }
+
+    /// Whether requests from `ip` are exempt from every policy.
+    pub fn is_allowlisted(&self, ip: Option<IpAddr>) -> bool {
+        ip.is_some_and(|ip| self.allowlist.iter().any(|network| network.contains(ip)))
+    }
+
+    /// Counts a request from `subject` under the policy `name`.
+    ///
+    /// # Returns
+    ///
+    /// The decision, or `None` if the policy is off. If the store fails, the error is
+    /// logged and the request is let through rather than refused.
+    pub async fn check(&self, name: &str, subject: &Subject<'_>) -> Option<RateLimitDecision> {
+        self.decide(name, subject, true).await
+    }
+
+    /// Decides whether `subject` could make a request under the policy `name`,
+    /// without counting one.
+    ///
+    /// # Returns
+    ///
+    /// The decision, or `None` if the policy is off or the store fails.
+    pub async fn peek(&self, name: &str, subject: &Subject<'_>) -> Option<RateLimitDecision> {
+        self.decide(name, subject, false).await
+    }
+
+    async fn decide(
+        &self,
+        name: &str,
+        subject: &Subject<'_>,
+        count: bool,
+    ) -> Option<RateLimitDecision> {
+        let policy = self.policies.get(name)?;
+        match self
+            .store
+            .check(name, policy, &policy.key_for(subject), count)
+            .await
+        {
+            Ok(decision) => Some(decision),
+            Err(err) => {
+                error!("Failed to check the '{}' rate limit: {}", name, err);
+                None
+            }
+        }
+    }
+}
+
+/// Creates the store named by the `RATE_LIMIT_STORE` environment variable: `memory`,
+/// the default, or `sqlite` to share the counters with every server using the
+/// database of `db_pool`.
+///
+/// # Returns
+///
+/// `None` if `RATE_LIMIT_STORE` names another store.
+pub fn store_from_env(db_pool: &SqlitePool) -> Option<Arc<dyn RateLimitStore>> {
+    match dotenv::var("RATE_LIMIT_STORE").as_deref() {
+        Err(_) | Ok("") | Ok("memory") => Some(Arc::new(MemoryStore::default())),
+        Ok("sqlite") => Some(Arc::new(SqliteStore::new(db_pool.clone()))),
+        Ok(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rate_limit_policies() {
+        assert_eq!(
+            RateLimitPolicy::parse("5/60;key=ip+username"),
+            Some(RateLimitPolicy::new(
+                5,
+                60,
+                &[KeyPart::Ip, KeyPart::Username]
+            ))
+        );
+        assert_eq!(
+            RateLimitPolicy::parse(" 3/1 "),
+            Some(RateLimitPolicy::new(3, 1, &[KeyPart::Ip]))
+        );
+        assert!(RateLimitPolicy::parse("0/60").is_none());
+        assert!(RateLimitPolicy::parse("5/60;key=email").is_none());
+
+        let rate_limits = RateLimits::new(
+            [(TOKEN, RateLimitPolicy::new(1, 60, &[KeyPart::ClientId]))],
+            vec!["10.0.0.0/8".parse().unwrap()],
+        );
+        let subject = |ip: &str, client_id| Subject {
+            ip: ip.parse().ok(),
+            client_id,
+            ..Subject::default()
+        };
+
+        assert!(
+            rate_limits
+                .check(TOKEN, &subject("192.0.2.1", Some("a")))
+                .await
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            !rate_limits
+                .check(TOKEN, &subject("192.0.2.2", Some("a")))
+                .await
+                .unwrap()
+                .allowed,
+            "Requests must be counted by client whatever their IP."
+        );
+        assert!(
+            rate_limits
+                .check(TOKEN, &subject("192.0.2.1", Some("b")))
+                .await
+                .unwrap()
+                .allowed
+        );
+        assert!(rate_limits
+            .check(AUTH, &subject("192.0.2.1", None))
+            .await
+            .is_none());
+
+        assert!(rate_limits.is_allowlisted("10.0.0.1".parse().ok()));
+        assert!(!rate_limits.is_allowlisted("192.0.2.1".parse().ok()));
+        assert!(!rate_limits.is_allowlisted(None));
+    }
+}
@@ -0,0 +1,257 @@
+use super::{RateLimitDecision, RateLimitPolicy, RateLimitStore};
+use sqlx::SqlitePool;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Keeps the counters in the `rate_limit_windows` table, so that every server using
+/// the same database shares them.
+///
+/// Each key has a sliding window counter: the requests counted in the current fixed
+/// window of the policy and in the one before it, which is weighted by how much of it
+/// still overlaps the last `window`. Each request is checked and counted in a single
+/// upsert, so concurrent requests, from any server, cannot both take the last one
+/// allowed.
+pub struct SqliteStore {
+    db_pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Creates a store in the database of `db_pool`, which must be migrated.
+    pub fn new(db_pool: SqlitePool) -> Self {
+        Self { db_pool }
+    }
+}
+
+/// The current time in milliseconds since the Unix epoch, which every server agrees on
+/// unlike `Instant`.
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as i64
+}
+
+/// Describes a sliding window counter as a decision.
+///
+/// # Arguments
+///
+/// * `limit` - How many requests may be made per `window`.
+/// * `window` - The length of the windows.
+/// * `elapsed` - How much of the current window has passed.
+/// * `count` - The requests counted in the current window.
+/// * `previous_count` - The requests counted in the window before it.
+/// * `allowed` - Whether the request was allowed.
+fn decision(
+    limit: u32,
+    window: Duration,
+    elapsed: Duration,
+    count: u32,
+    previous_count: u32,
+    allowed: bool,
+) -> RateLimitDecision {
+    let window_secs = window.as_secs_f64();
+    let left = (window - elapsed.min(window)).as_secs_f64();
+    let estimate = f64::from(previous_count) * left / window_secs + f64::from(count);
+
+    let reset = match (count, previous_count) {
+        (0, 0) => 0.0,
+        (0, _) => left,
+        _ => left + window_secs,
+    };
+    let retry_after = (!allowed).then(|| {
+        let free = f64::from(limit) - 1.0 - f64::from(count);
+        if free >= 0.0 && previous_count > 0 {
+            // Enough of the previous window slides out before this one ends.
+            (left - window_secs * free / f64::from(previous_count)).max(0.0)
+        } else {
+            let free = f64::from(limit) - 1.0;
+            left + window_secs * (1.0 - free / f64::from(count.max(1))).max(0.0)
+        }
+    });
+
+    RateLimitDecision {
+        allowed,
+        limit,
+        remaining: (f64::from(limit) - estimate).max(0.0) as u32,
+        reset: Duration::from_secs_f64(reset),
+        retry_after: retry_after.map(Duration::from_secs_f64),
+    }
+}
+
+#[rocket::async_trait]
+impl RateLimitStore for SqliteStore {
+    async fn check(
+        &self,
+        name: &str,
+        policy: &RateLimitPolicy,
+        key: &str,
+        count: bool,
+    ) -> Result<RateLimitDecision, sqlx::Error> {
+        let now = now_millis();
+        let window = (policy.window.as_millis() as i64).max(1);
+        let window_start = now - now % window;
+        let elapsed = Duration::from_millis((now - window_start) as u64);
+        let limit = i64::from(policy.limit);
+
+        if !count {
+            let row = sqlx::query!(
+                "SELECT window_start, count, previous_count FROM rate_limit_windows
+                 WHERE policy = ? AND key = ?",
+                name,
+                key
+            )
+            .fetch_optional(&self.db_pool)
+            .await?;
+            let (count, previous_count) = match row {
+                Some(row) if row.window_start == window_start => (row.count, row.previous_count),
+                Some(row) if row.window_start == window_start - window => (0, row.count),
+                _ => (0, 0),
+            };
+            let estimate = previous_count as f64 * (window - (now - window_start)) as f64
+                / window as f64
+                + count as f64;
+
+            return Ok(decision(
+                policy.limit,
+                policy.window,
+                elapsed,
+                count as u32,
+                previous_count as u32,
+                estimate + 1.0 <= limit as f64,
+            ));
+        }
+
+        // Every expression in SET sees the row as it was before the update, so the
+        // counts are rolled over to the current window in each of them.
+        let row = sqlx::query!(
+            r#"INSERT INTO rate_limit_windows
+                   (policy, key, window_start, count, previous_count, allowed)
+               VALUES (?1, ?2, ?3, 1, 0, TRUE)
+               ON CONFLICT (policy, key) DO UPDATE SET
+                   window_start = ?3,
+                   previous_count = CASE window_start
+                       WHEN ?3 THEN previous_count WHEN ?3 - ?4 THEN count ELSE 0 END,
+                   count = CASE window_start WHEN ?3 THEN count ELSE 0 END
+                       + (CASE window_start
+                              WHEN ?3 THEN previous_count WHEN ?3 - ?4 THEN count ELSE 0 END
+                              * (?4 - (?5 - ?3)) * 1.0 / ?4
+                          + CASE window_start WHEN ?3 THEN count ELSE 0 END
+                          + 1 <= ?6),
+                   allowed = (CASE window_start
+                                  WHEN ?3 THEN previous_count WHEN ?3 - ?4 THEN count ELSE 0 END
+                                  * (?4 - (?5 - ?3)) * 1.0 / ?4
+                              + CASE window_start WHEN ?3 THEN count ELSE 0 END
+                              + 1 <= ?6)
+               RETURNING count, previous_count, allowed AS "allowed: bool""#,
+            name,
+            key,
+            window_start,
+            window,
+            now,
+            limit
+        )
+        .fetch_one(&self.db_pool)
+        .await?;
+
+        Ok(decision(
+            policy.limit,
+            policy.window,
+            elapsed,
+            row.count as u32,
+            row.previous_count as u32,
+            row.allowed,
+        ))
+    }
+
+    async fn evict_idle(&self, name: &str, policy: &RateLimitPolicy) -> Result<(), sqlx::Error> {
+        // A key whose last request was two windows ago has nothing left to count.
+        let idle_before = now_millis() - 2 * policy.window.as_millis() as i64;
+        sqlx::query!(
+            "DELETE FROM rate_limit_windows WHERE policy = ? AND window_start <= ?",
+            name,
+            idle_before
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::policy::KeyPart;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        let store = SqliteStore::new(db_pool);
+        let policy = RateLimitPolicy::new(2, 60, &[KeyPart::Ip]);
+
+        assert_eq!(
+            store
+                .check("auth", &policy, "a", false)
+                .await
+                .unwrap()
+                .remaining,
+            2,
+            "Peeking must not count a request."
+        );
+        for _ in 0..2 {
+            assert!(
+                store
+                    .check("auth", &policy, "a", true)
+                    .await
+                    .unwrap()
+                    .allowed
+            );
+        }
+        let refused = store.check("auth", &policy, "a", true).await.unwrap();
+        assert!(!refused.allowed);
+        assert!(refused.retry_after.is_some());
+        assert!(
+            !store
+                .check("auth", &policy, "a", false)
+                .await
+                .unwrap()
+                .allowed
+        );
+
+        assert!(
+            store
+                .check("auth", &policy, "b", true)
+                .await
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            store
+                .check("token", &policy, "a", true)
+                .await
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[test]
+    fn test_sliding_window_decision() {
+        let window = Duration::from_secs(10);
+
+        // Halfway through the window, half of the previous window still counts.
+        let allowed = decision(4, window, Duration::from_secs(5), 1, 4, true);
+        assert_eq!(allowed.remaining, 1);
+        assert_eq!(allowed.reset, Duration::from_secs(15));
+
+        let refused = decision(4, window, Duration::from_secs(5), 2, 4, false);
+        assert_eq!(refused.retry_after, Some(Duration::from_secs_f64(2.5)));
+
+        let refused = decision(2, window, Duration::from_secs(5), 2, 0, false);
+        assert_eq!(refused.retry_after, Some(Duration::from_secs(10)));
+    }
+}
@@ -0,0 +1,88 @@
+use super::{RateLimitDecision, RateLimitPolicy, RateLimiter};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Instant;
+
+/// Where the counters of the rate limit policies are kept.
+///
+/// Every policy is checked through the store, so a store shared between servers,
+/// such as [`SqliteStore`](super::SqliteStore), enforces each limit across all of them
+/// rather than once per server.
+#[rocket::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Decides whether `key` may make a request under the policy `name`, and counts it
+    /// if it may and `count` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns `sqlx::Error` if the counters cannot be read or updated.
+    async fn check(
+        &self,
+        name: &str,
+        policy: &RateLimitPolicy,
+        key: &str,
+        count: bool,
+    ) -> Result<RateLimitDecision, sqlx::Error>;
+
+    /// Forgets the keys of the policy `name` that have been idle long enough for their
+    /// limit to refill.
+    ///
+    /// # Errors
+    ///
+    /// Returns `sqlx::Error` if the counters cannot be deleted.
+    async fn evict_idle(&self, name: &str, policy: &RateLimitPolicy) -> Result<(), sqlx::Error>;
+}
+
+/// Keeps the counters in process memory, with a [`RateLimiter`] for each policy.
+///
+/// This is the default store. It is the fastest, but each server counts only the
+/// requests it serves itself.
+#[derive(Default)]
+pub struct MemoryStore {
+    limiters: RwLock<HashMap<String, RateLimiter>>,
+}
+
+impl MemoryStore {
+    /// Finds the limiter of the policy `name`, creating it the first time.
+    fn limiter(&self, name: &str, policy: &RateLimitPolicy) -> RateLimiter {
+        let limiters = self
+            .limiters
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(limiter) = limiters.get(name) {
+            return limiter.clone();
+        }
+        drop(limiters);
+
+        self.limiters
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(name.to_string())
+            .or_insert_with(|| RateLimiter::new(policy.limit, policy.window))
+            .clone()
+    }
+}
+
+#[rocket::async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn check(
+        &self,
+        name: &str,
+        policy: &RateLimitPolicy,
+        key: &str,
+        count: bool,
+    ) -> Result<RateLimitDecision, sqlx::Error> {
+        let limiter = self.limiter(name, policy);
+
+        Ok(if count {
+            limiter.check(key)
+        } else {
+            limiter.peek(key)
+        })
+    }
+
+    async fn evict_idle(&self, name: &str, policy: &RateLimitPolicy) -> Result<(), sqlx::Error> {
+        self.limiter(name, policy).evict_idle(Instant::now());
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use crate::crypto::key_pair::unix_timestamp;
+use crate::crypto::{Claims, CryptoError, Jwt, KeyCipher, KeyState, TokenError, TokenPolicy};
+use crate::db;
+use sqlx::SqlitePool;
+
+/// Validates a token and checks that it has not been revoked.
+///
+/// This is [`Jwt::verify`] against the stored keys, followed by a lookup of the
+/// token's `jti` and `sub` in the revocation tables. Every endpoint that accepts a
+/// token should validate it here, so that revocation is honoured everywhere.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `key_cipher` - The cipher used to open the stored private keys.
+/// * `policy` - The issuer and leeway to validate against.
+/// * `token` - The encoded JWT.
+/// * `audience` - The audience the token must have been issued for, if any.
+///
+/// # Errors
+///
+/// Returns `TokenError::Revoked` if the token, or every token for its subject, was
+/// revoked, or another `TokenError` if it fails verification.
+pub async fn validate_token(
+    db_pool: &SqlitePool,
+    key_cipher: &KeyCipher,
+    policy: &TokenPolicy,
+    token: &str,
+    audience: Option<&str>,
+) -> Result<Claims, TokenError> {
+    let key_pairs = db::load_key_pairs(db_pool, key_cipher).await?;
+    let claims = Jwt::verify(token, &key_pairs, policy, audience)?;
+
+    if is_revoked(db_pool, &claims).await? {
+        return Err(TokenError::Revoked);
+    }
+
+    Ok(claims)
+}
+
+/// Checks whether a token's `jti`, or its subject as of its `iat`, has been revoked.
+pub async fn is_revoked(db_pool: &SqlitePool, claims: &Claims) -> Result<bool, sqlx::Error> {
+    let iat = claims.iat as i64;
+    let revoked = sqlx::query_scalar!(
+        r#"SELECT (EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = ?)
+                OR EXISTS (SELECT 1 FROM revoked_subjects WHERE sub = ? AND revoked_before >= ?))
+           AS "revoked!: bool""#,
+        claims.jti,
+        claims.sub,
+        iat
+    )
+    .fetch_one(db_pool)
+    .await?;
+
+    Ok(revoked)
+}
+
+/// Revokes a single token by its `jti`.
+///
+/// The entry is kept until `exp` plus `leeway`, after which the token is rejected
+/// for having expired anyway. Entries for tokens that are past that point are deleted
+/// at the same time.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `jti` - The unique identifier of the token.
+/// * `exp` - The expiration time of the token as a timestamp.
+/// * `leeway` - How many seconds after `exp` tokens are still accepted, as in
+///   [`TokenPolicy::leeway`].
+pub async fn revoke_token(
+    db_pool: &SqlitePool,
+    jti: &str,
+    exp: u64,
+    leeway: u64,
+) -> Result<(), sqlx::Error> {
+    let exp = exp as i64;
+    let leeway = leeway as i64;
+    let now = unix_timestamp() as i64;
+
+    sqlx::query!("DELETE FROM revoked_tokens WHERE exp + ? < ?", leeway, now)
+        .execute(db_pool)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO revoked_tokens (jti, exp) VALUES (?, ?) ON CONFLICT (jti) DO NOTHING",
+        jti,
+        exp
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Revokes every token issued to `sub` at or before `revoked_before`.
+///
+/// Tokens issued afterwards are not affected, so the subject can log in again.
+pub async fn revoke_subject(
+    db_pool: &SqlitePool,
+    sub: &str,
+    revoked_before: u64,
+) -> Result<(), sqlx::Error> {
+    let revoked_before = revoked_before as i64;
+
+    sqlx::query!(
+        "INSERT INTO revoked_subjects (sub, revoked_before) VALUES (?, ?)
+         ON CONFLICT (sub) DO UPDATE SET revoked_before = MAX(revoked_before, excluded.revoked_before)",
+        sub,
+        revoked_before
+    )
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Revokes every token signed by the key `kid` by revoking the key itself.
+///
+/// A revoked key is withdrawn from the JWKS at once and never signs again; the
+/// rotation scheduler creates a replacement on its next run.
+///
+/// # Returns
+///
+/// `false` if there is no key with that `kid`.
+pub async fn revoke_key(db_pool: &SqlitePool, kid: &str) -> Result<bool, CryptoError> {
+    db::update_key_state(db_pool, kid, KeyState::Revoked).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::envelope::MasterKey;
+    use crate::crypto::{ClaimsBuilder, KeyPair, SigningAlgorithm};
+
+    #[tokio::test]
+    async fn test_revocation_is_honoured() {
+        let db_pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        let policy = TokenPolicy::default();
+
+        let key_pair = KeyPair::generate(SigningAlgorithm::ES256, 3600).unwrap();
+        db::insert_key_pair(&db_pool, &key_cipher, &key_pair)
+            .await
+            .unwrap();
+        let now = unix_timestamp();
+        let issue = |sub: &str| {
+            let claims = ClaimsBuilder::new(&policy, sub).build(&key_pair, now);
+            (Jwt::from(&key_pair, &claims).unwrap(), claims)
+        };
+
+        let (token, claims) = issue("1");
+        let (other_token, _) = issue("1");
+        assert!(validate_token(&db_pool, &key_cipher, &policy, &token, None)
+            .await
+            .is_ok());
+
+        revoke_token(&db_pool, &claims.jti, claims.exp, policy.leeway)
+            .await
+            .unwrap();
+        assert!(matches!(
+            validate_token(&db_pool, &key_cipher, &policy, &token, None).await,
+            Err(TokenError::Revoked)
+        ));
+        assert!(
+            validate_token(&db_pool, &key_cipher, &policy, &other_token, None)
+                .await
+                .is_ok(),
+            "Revoking one jti must not affect other tokens."
+        );
+
+        // A token that expired within the leeway is still accepted, so it must stay
+        // revoked when later revocations clear out expired entries.
+        let claims = ClaimsBuilder::new(&policy, "3").build(&key_pair, now - policy.ttl - 10);
+        let expiring_token = Jwt::from(&key_pair, &claims).unwrap();
+        revoke_token(&db_pool, &claims.jti, claims.exp, policy.leeway)
+            .await
+            .unwrap();
+        revoke_token(&db_pool, "another-jti", now + 60, policy.leeway)
+            .await
+            .unwrap();
+        assert!(matches!(
+            validate_token(&db_pool, &key_cipher, &policy, &expiring_token, None).await,
+            Err(TokenError::Revoked)
+        ));
+
+        revoke_subject(&db_pool, "1", now).await.unwrap();
+        assert!(matches!(
+            validate_token(&db_pool, &key_cipher, &policy, &other_token, None).await,
+            Err(TokenError::Revoked)
+        ));
+
+        let (third_token, _) = issue("2");
+        assert!(revoke_key(&db_pool, &key_pair.kid).await.unwrap());
+        assert!(matches!(
+            validate_token(&db_pool, &key_cipher, &policy, &third_token, None).await,
+            Err(TokenError::UnknownKey(_))
+        ));
+    }
+}
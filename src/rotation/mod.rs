@@ -0,0 +1,481 @@
+use crate::crypto::key_pair::unix_timestamp;
+use crate::crypto::{CryptoError, KeyCipher, KeyPair, KeyState, SigningAlgorithm};
+use crate::db;
+use rocket::fairing::AdHoc;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::time::Duration;
+
+/// Configures how signing keys are created, retired and purged.
+///
+/// Every key moves through the same schedule: it is published `lead_time` seconds
+/// before it starts signing, signs for `signing_lifetime` seconds, stays published for
+/// `max_token_lifetime` seconds after it stops signing, and is deleted
+/// `retention` seconds after that. Each algorithm in `algorithms` gets its own
+/// schedule.
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    /// The algorithms to keep signing keys for. The first one is the default for
+    /// tokens that do not ask for a specific algorithm.
+    pub algorithms: Vec<SigningAlgorithm>,
+    /// The number of keys per algorithm that should be able to sign at any moment.
+    pub active_keys: usize,
+    /// How long, in seconds, each key signs new tokens.
+    pub signing_lifetime: u64,
+    /// How long, in seconds, the next key is published before it starts signing.
+    pub lead_time: u64,
+    /// The longest lifetime, in seconds, of any token this server issues.
+    pub max_token_lifetime: u64,
+    /// How long, in seconds, an expired key is kept before it is deleted.
+    pub retention: u64,
+    /// How often, in seconds, the background task checks the schedule.
+    pub check_interval: u64,
+    /// A prefix for the thumbprint `kid` of every new key, such as `prod-`.
+    pub kid_prefix: String,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            algorithms: vec![SigningAlgorithm::RS256],
+            active_keys: 1,
+            signing_lifetime: 86_400,
+            lead_time: 3_600,
+            max_token_lifetime: 3_600,
+            retention: 86_400,
+            check_interval: 60,
+            kid_prefix: String::new(),
+        }
+    }
+}
+
+impl RotationPolicy {
+    /// Builds a `RotationPolicy` from the environment, falling back to the defaults for
+    /// any variable that is not set.
+    ///
+    /// The variables are `SIGNING_ALGORITHMS`, `KEY_ROTATION_ACTIVE_KEYS`,
+    /// `KEY_SIGNING_LIFETIME_SECS`, `KEY_ROTATION_LEAD_SECS`, `TOKEN_MAX_LIFETIME_SECS`,
+    /// `KEY_RETENTION_SECS`, `KEY_ROTATION_CHECK_SECS` and `KID_PREFIX`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CryptoError::ParseIntError` if a numeric variable is set but is not a
+    /// number, and `CryptoError::UnsupportedAlgorithm` if `SIGNING_ALGORITHMS` names an
+    /// unknown algorithm.
+    pub fn from_env() -> Result<Self, CryptoError> {
+        let defaults = Self::default();
+
+        let algorithms = match dotenv::var("SIGNING_ALGORITHMS") {
+            Ok(list) => SigningAlgorithm::parse_list(&list)?,
+            Err(_) => Vec::new(),
+        };
+
+        Ok(Self {
+            algorithms: if algorithms.is_empty() {
+                defaults.algorithms
+            } else {
+                algorithms
+            },
+            active_keys: env_or("KEY_ROTATION_ACTIVE_KEYS", defaults.active_keys)?,
+            signing_lifetime: env_or("KEY_SIGNING_LIFETIME_SECS", defaults.signing_lifetime)?,
+            lead_time: env_or("KEY_ROTATION_LEAD_SECS", defaults.lead_time)?,
+            max_token_lifetime: env_or("TOKEN_MAX_LIFETIME_SECS", defaults.max_token_lifetime)?,
+            retention: env_or("KEY_RETENTION_SECS", defaults.retention)?,
+            check_interval: env_or("KEY_ROTATION_CHECK_SECS", defaults.check_interval)?,
+            kid_prefix: dotenv::var("KID_PREFIX").unwrap_or(defaults.kid_prefix),
+        })
+    }
+
+    /// Returns the algorithm used when a token request does not name one.
+    pub fn default_algorithm(&self) -> SigningAlgorithm {
+        self.algorithms
+            .first()
+            .copied()
+            .unwrap_or(SigningAlgorithm::RS256)
+    }
+}
+
+/// Reads a numeric environment variable, returning `default` if it is not set.
+fn env_or<T>(name: &str, default: T) -> Result<T, CryptoError>
+where
+    T: std::str::FromStr<Err = std::num::ParseIntError>,
+{
+    match dotenv::var(name) {
+        Ok(value) => Ok(value.parse::<T>()?),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Summarises the changes made by one pass of [`rotate_keys`].
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct RotationReport {
+    /// The number of existing keys that moved to a new lifecycle state.
+    pub transitioned: usize,
+    /// The number of keys created that can sign immediately.
+    pub activated: usize,
+    /// The number of keys created ahead of time that will sign later.
+    pub scheduled: usize,
+    /// The number of expired keys that were deleted.
+    pub purged: u64,
+}
+
+/// Brings the `keys` table in line with the rotation policy at time `now`.
+///
+/// The `keys` table is the only source of truth, so this is safe to run after a
+/// restart: it only creates keys that are missing from the schedule.
+///
+/// 1. Each key moves to the lifecycle state its schedule puts it in: `pending` keys
+///    become `active` at `nbf`, `active` keys become `retiring` at `retire_at`, and
+///    `retiring` keys become `expired` at `exp`. Revoked keys stay revoked.
+/// 2. Expired and revoked keys whose expiry is more than `retention` seconds ago are
+///    deleted.
+/// 3. For each configured algorithm, if fewer than `active_keys` keys are `active`,
+///    new keys are created that sign immediately.
+/// 4. For each configured algorithm, if fewer than `active_keys` keys will still be
+///    signing by the next check after `lead_time`, the next keys are created now. Each one starts signing when the key
+///    it replaces retires, so it is published at least `lead_time` seconds early.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `key_cipher` - The cipher used to seal new private keys.
+/// * `policy` - The rotation policy to enforce.
+/// * `now` - The current time as a UNIX timestamp.
+pub async fn rotate_keys(
+    db_pool: &SqlitePool,
+    key_cipher: &KeyCipher,
+    policy: &RotationPolicy,
+    now: u64,
+) -> Result<RotationReport, CryptoError> {
+    let mut report = RotationReport::default();
+
+    let mut key_pairs = Vec::new();
+    for key_pair in db::load_key_pairs(db_pool, key_cipher).await? {
+        let state = key_pair.scheduled_state(now);
+        if state != key_pair.state {
+            db::update_key_state(db_pool, &key_pair.kid, state).await?;
+            report.transitioned += 1;
+        }
+        key_pairs.push(key_pair.with_state(state));
+    }
+
+    report.purged =
+        db::purge_keys_expired_before(db_pool, now.saturating_sub(policy.retention) as i64).await?;
+
+    for &algorithm in &policy.algorithms {
+        let key_pairs: Vec<&KeyPair> = key_pairs
+            .iter()
+            .filter(|kp| kp.algorithm == algorithm)
+            .collect();
+        schedule_algorithm(
+            db_pool,
+            key_cipher,
+            policy,
+            algorithm,
+            &key_pairs,
+            now,
+            &mut report,
+        )
+        .await?;
+    }
+
+    Ok(report)
+}
+
+/// Rotates the signing keys now, ahead of their schedule, such as when a key may
+/// have been exposed.
+///
+/// Every active key retires at once; it stays published until its tokens have
+/// expired, but signs nothing more. Keys that were published ahead of time take
+/// over immediately, and [`rotate_keys`] then creates whatever else the policy
+/// calls for.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `key_cipher` - The cipher used to seal new private keys.
+/// * `policy` - The rotation policy to enforce.
+/// * `now` - The current time as a UNIX timestamp.
+pub async fn force_rotation(
+    db_pool: &SqlitePool,
+    key_cipher: &KeyCipher,
+    policy: &RotationPolicy,
+    now: u64,
+) -> Result<RotationReport, CryptoError> {
+    let retired = db::retire_active_keys(db_pool, now as i64).await?;
+    let mut report = rotate_keys(db_pool, key_cipher, policy, now).await?;
+    report.transitioned += retired as usize;
+
+    Ok(report)
+}
+
+/// Runs steps 3 and 4 of [`rotate_keys`] for the key pairs of one algorithm.
+async fn schedule_algorithm(
+    db_pool: &SqlitePool,
+    key_cipher: &KeyCipher,
+    policy: &RotationPolicy,
+    algorithm: SigningAlgorithm,
+    key_pairs: &[&KeyPair],
+    now: u64,
+    report: &mut RotationReport,
+) -> Result<(), CryptoError> {
+    let signing_now = key_pairs
+        .iter()
+        .filter(|kp| kp.state == KeyState::Active)
+        .count();
+    let scheduled: Vec<&KeyPair> = key_pairs
+        .iter()
+        .copied()
+        .filter(|kp| matches!(kp.state, KeyState::Pending | KeyState::Active))
+        .collect();
+    let horizon = now + policy.lead_time + policy.check_interval;
+    let mut signing_after_horizon = scheduled.iter().filter(|kp| kp.retire_at > horizon).count();
+    let mut retiring_soon: Vec<u64> = scheduled
+        .iter()
+        .map(|kp| kp.retire_at)
+        .filter(|retire_at| now < *retire_at && *retire_at <= horizon)
+        .collect();
+    retiring_soon.sort_unstable();
+
+    for _ in signing_now..policy.active_keys {
+        let key_pair = create_key(db_pool, key_cipher, policy, algorithm, None, now).await?;
+        if key_pair.retire_at > horizon {
+            signing_after_horizon += 1;
+        }
+        report.activated += 1;
+    }
+
+    let mut successors = retiring_soon.into_iter();
+    for _ in signing_after_horizon..policy.active_keys {
+        let not_before = successors.next().unwrap_or(now + policy.lead_time);
+        create_key(db_pool, key_cipher, policy, algorithm, None, not_before).await?;
+        report.scheduled += 1;
+    }
+
+    Ok(())
+}
+
+/// Generates a key pair that starts signing at `not_before` and stores it.
+///
+/// The key pair is stored as `active` if `not_before` has already passed, and as
+/// `pending` otherwise. It signs for `signing_lifetime` seconds and expires
+/// `max_token_lifetime` seconds after that, like every key [`rotate_keys`] creates.
+///
+/// # Arguments
+///
+/// * `db_pool` - A connection pool to the SQLite database.
+/// * `key_cipher` - The cipher used to seal the private key.
+/// * `policy` - The rotation policy that sets the key's schedule and `kid` prefix.
+/// * `algorithm` - The algorithm the key signs with.
+/// * `key_size` - The size of an RSA key in bits, if not `KEY_SIZE`.
+/// * `not_before` - When the key starts signing, as a UNIX timestamp.
+///
+/// # Errors
+///
+/// Returns `CryptoError::UnsupportedAlgorithm` if `key_size` is given for a key that
+/// is not RSA.
+pub async fn create_key(
+    db_pool: &SqlitePool,
+    key_cipher: &KeyCipher,
+    policy: &RotationPolicy,
+    algorithm: SigningAlgorithm,
+    key_size: Option<usize>,
+    not_before: u64,
+) -> Result<KeyPair, CryptoError> {
+    let retire_at = not_before + policy.signing_lifetime;
+    let expiry = retire_at + policy.max_token_lifetime;
+    let lifetime = expiry.saturating_sub(unix_timestamp()) as i64;
+
+    // RSA key generation is CPU bound, so keep it off the async worker threads.
+    let mut key_pair = tokio::task::spawn_blocking(move || match key_size {
+        Some(key_size) => KeyPair::generate_rsa(algorithm, key_size, lifetime),
+        None => KeyPair::generate(algorithm, lifetime),
+    })
+    .await
+    .map_err(|_| CryptoError::InvalidKey("key generation task failed"))??;
+    key_pair.expiry = expiry;
+    key_pair = key_pair.with_signing_window(not_before, retire_at);
+    key_pair.state = key_pair.scheduled_state(unix_timestamp());
+
+    key_pair = key_pair.with_kid_prefix(&policy.kid_prefix);
+
+    db::insert_key_pair(db_pool, key_cipher, &key_pair).await?;
+    Ok(key_pair)
+}
+
+/// Creates a fairing that runs [`rotate_keys`] in a background task once Rocket has
+/// launched.
+///
+/// The task reads the `SqlitePool`, `KeyCipher` and `RotationPolicy` from Rocket's
+/// managed state and repeats every `check_interval` seconds. Failures are logged and
+/// retried on the next tick rather than stopping the task.
+pub fn scheduler() -> AdHoc {
+    AdHoc::on_liftoff("Key Rotation Scheduler", |rocket| {
+        Box::pin(async move {
+            let (Some(db_pool), Some(key_cipher), Some(policy)) = (
+                rocket.state::<SqlitePool>().cloned(),
+                rocket.state::<KeyCipher>().cloned(),
+                rocket.state::<RotationPolicy>().cloned(),
+            ) else {
+                error!("Key rotation scheduler is missing managed state; not starting");
+                return;
+            };
+
+            tokio::spawn(async move {
+                let mut interval =
+                    tokio::time::interval(Duration::from_secs(policy.check_interval.max(1)));
+                loop {
+                    interval.tick().await;
+                    match rotate_keys(&db_pool, &key_cipher, &policy, unix_timestamp()).await {
+                        Ok(report) if report != RotationReport::default() => {
+                            info!("Key rotation: {:?}", report)
+                        }
+                        Ok(_) => {}
+                        Err(err) => error!("Key rotation failed: {}", err),
+                    }
+                }
+            });
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::envelope::MasterKey;
+
+    async fn setup_db() -> SqlitePool {
+        let pool = SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!().run(&pool).await.unwrap();
+        pool
+    }
+
+    fn test_policy() -> RotationPolicy {
+        RotationPolicy {
+            algorithms: vec![SigningAlgorithm::RS256],
+            active_keys: 2,
+            signing_lifetime: 1_000,
+            lead_time: 100,
+            max_token_lifetime: 50,
+            retention: 10,
+            check_interval: 1,
+            kid_prefix: "test-".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotate_keys_follows_schedule() {
+        let db_pool = setup_db().await;
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        let policy = test_policy();
+        let start = unix_timestamp();
+
+        let report = rotate_keys(&db_pool, &key_cipher, &policy, start)
+            .await
+            .unwrap();
+        assert_eq!(report.activated, 2, "Empty table should get active keys.");
+        assert_eq!(report.scheduled, 0);
+
+        let report = rotate_keys(&db_pool, &key_cipher, &policy, start + 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            RotationReport::default(),
+            "Rotation should be idempotent."
+        );
+
+        let near_retirement = start + policy.signing_lifetime - policy.lead_time;
+        let report = rotate_keys(&db_pool, &key_cipher, &policy, near_retirement)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.scheduled, 2,
+            "Next keys should be created ahead of time."
+        );
+
+        let key_pairs = db::load_key_pairs(&db_pool, &key_cipher).await.unwrap();
+        assert_eq!(key_pairs.len(), 4);
+        assert!(
+            key_pairs
+                .iter()
+                .all(|kp| kp.kid == format!("test-{}", kp.public_key.thumbprint())),
+            "Keys should be identified by their prefixed thumbprint."
+        );
+        assert_eq!(
+            key_pairs
+                .iter()
+                .filter(|kp| kp.state == KeyState::Pending)
+                .count(),
+            2,
+            "Next keys should be published as pending."
+        );
+        assert!(
+            key_pairs
+                .iter()
+                .filter(|kp| kp.not_before > near_retirement)
+                .all(|kp| kp.not_before == start + policy.signing_lifetime),
+            "Next keys should start signing as soon as the current keys retire."
+        );
+
+        let after_purge = start + policy.signing_lifetime + policy.max_token_lifetime + 11;
+        let report = rotate_keys(&db_pool, &key_cipher, &policy, after_purge)
+            .await
+            .unwrap();
+        assert_eq!(report.purged, 2, "Expired keys should be purged.");
+    }
+
+    #[tokio::test]
+    async fn test_rotate_keys_replaces_revoked_key() {
+        let db_pool = setup_db().await;
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        let policy = RotationPolicy {
+            active_keys: 1,
+            ..test_policy()
+        };
+        let now = unix_timestamp();
+
+        rotate_keys(&db_pool, &key_cipher, &policy, now)
+            .await
+            .unwrap();
+        let key_pairs = db::load_key_pairs(&db_pool, &key_cipher).await.unwrap();
+        db::update_key_state(&db_pool, &key_pairs[0].kid, KeyState::Revoked)
+            .await
+            .unwrap();
+
+        let report = rotate_keys(&db_pool, &key_cipher, &policy, now)
+            .await
+            .unwrap();
+        assert_eq!(report.transitioned, 0, "Revoked keys should stay revoked.");
+        assert_eq!(report.activated, 1, "A revoked key should be replaced.");
+    }
+
+    #[tokio::test]
+    async fn test_rotate_keys_per_algorithm() {
+        let db_pool = setup_db().await;
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        let policy = RotationPolicy {
+            algorithms: vec![SigningAlgorithm::ES256, SigningAlgorithm::EdDSA],
+            active_keys: 1,
+            ..test_policy()
+        };
+
+        let report = rotate_keys(&db_pool, &key_cipher, &policy, unix_timestamp())
+            .await
+            .unwrap();
+        assert_eq!(report.activated, 2, "Each algorithm should get a key.");
+
+        let mut algorithms: Vec<SigningAlgorithm> = db::load_key_pairs(&db_pool, &key_cipher)
+            .await
+            .unwrap()
+            .iter()
+            .map(|kp| kp.algorithm)
+            .collect();
+        algorithms.sort_by_key(|alg| alg.as_str());
+        assert_eq!(
+            algorithms,
+            vec![SigningAlgorithm::ES256, SigningAlgorithm::EdDSA],
+            "Keys should be stored and loaded with their algorithm."
+        );
+    }
+}
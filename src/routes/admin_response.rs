@@ -0,0 +1,799 @@
+use crate::auth::client::{
+    count_clients, create_client, delete_client, find_client, is_valid_client_id, list_clients,
+    Client, ClientRegistration,
+};
+use crate::auth::roles::{list_roles, set_user_roles, user_roles};
+use crate::auth::{
+    count_users, create_user, delete_user, find_user_by_id, generate_token, list_users, lockout,
+    reset_password, set_user_disabled, Admin, AuthError, PasswordDTO, User,
+};
+use crate::crypto::key_pair::unix_timestamp;
+use crate::crypto::{CryptoError, KeyCipher};
+use crate::db::{count_keys, list_keys, KeyInfo};
+use crate::revocation::revoke_key;
+use crate::rotation::{force_rotation, RotationPolicy, RotationReport};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{json, Json, Value};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+/// How many items a page holds unless the request asks otherwise.
+const DEFAULT_PER_PAGE: u32 = 50;
+
+/// The most items a page can hold.
+const MAX_PER_PAGE: u32 = 200;
+
+/// The paging parameters of a list request, such as `?page=2&per_page=20`.
+#[derive(Debug, FromForm, Default)]
+pub struct PageRequest {
+    /// The page to return, counting from 1.
+    pub page: Option<u32>,
+    /// How many items to return per page, up to `MAX_PER_PAGE`.
+    pub per_page: Option<u32>,
+}
+
+impl PageRequest {
+    /// The page to return, counting from 1.
+    fn page(&self) -> u32 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    /// How many items to return per page.
+    fn per_page(&self) -> u32 {
+        self.per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE)
+    }
+
+    /// The `LIMIT` and `OFFSET` for the page.
+    fn limit_offset(&self) -> (i64, i64) {
+        let per_page = i64::from(self.per_page());
+        (per_page, i64::from(self.page() - 1) * per_page)
+    }
+
+    /// Wraps the items of the page in a response.
+    fn respond<T>(&self, items: Vec<T>, total: i64) -> Json<Page<T>> {
+        Json(Page {
+            items,
+            page: self.page(),
+            per_page: self.per_page(),
+            total,
+        })
+    }
+}
+
+/// A page of a list response.
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    /// The items on this page.
+    pub items: Vec<T>,
+    /// The page number, counting from 1.
+    pub page: u32,
+    /// The most items a page holds.
+    pub per_page: u32,
+    /// How many items there are across every page.
+    pub total: i64,
+}
+
+/// The errors of the `/admin` endpoints, each with a JSON body such as
+/// `{"error": "user not found"}`.
+#[derive(Responder, Debug)]
+pub enum AdminError {
+    #[response(status = 400)]
+    BadRequest(Json<Value>),
+    #[response(status = 404)]
+    NotFound(Json<Value>),
+    #[response(status = 409)]
+    Conflict(Json<Value>),
+    ServerError(AuthError),
+}
+
+impl AdminError {
+    fn bad_request(message: &str) -> Self {
+        AdminError::BadRequest(Json(json!({ "error": message })))
+    }
+
+    fn not_found(message: &str) -> Self {
+        AdminError::NotFound(Json(json!({ "error": message })))
+    }
+}
+
+/// Allows conversion from `sqlx::Error` to `AdminError`, reporting unique constraint
+/// violations, such as a taken username, as conflicts.
+impl From<sqlx::Error> for AdminError {
+    fn from(err: sqlx::Error) -> AdminError {
+        match err.as_database_error() {
+            Some(db_err) if db_err.is_unique_violation() => {
+                AdminError::Conflict(Json(json!({ "error": "already exists" })))
+            }
+            _ => AdminError::ServerError(AuthError::DatabaseError(err)),
+        }
+    }
+}
+
+/// Allows conversion from `CryptoError` to `AdminError`.
+impl From<CryptoError> for AdminError {
+    fn from(err: CryptoError) -> AdminError {
+        AdminError::ServerError(AuthError::CryptoError(err))
+    }
+}
+
+/// Allows conversion from `AuthError` to `AdminError`.
+impl From<AuthError> for AdminError {
+    fn from(err: AuthError) -> AdminError {
+        AdminError::ServerError(err)
+    }
+}
+
+/// A user as the admin API shows it, without the password hash.
+#[derive(Serialize, Debug)]
+pub struct UserView {
+    pub id: i64,
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub disabled: bool,
+    /// How many times in a row logging in as the user has failed.
+    pub failed_logins: i64,
+    /// Until when, as a timestamp, the user's logins are refused after those failures.
+    pub locked_until: Option<i64>,
+    /// When the user's profile last changed, as a timestamp.
+    pub updated_at: Option<i64>,
+    /// Every role the user holds, including the default roles.
+    pub roles: Vec<String>,
+}
+
+impl UserView {
+    /// Looks up the roles of `user` to describe them.
+    async fn new(db_pool: &SqlitePool, user: User) -> Result<Self, sqlx::Error> {
+        let id = user.id.unwrap_or_default();
+
+        Ok(Self {
+            id,
+            roles: user_roles(db_pool, id).await?,
+            username: user.username,
+            email: user.email,
+            email_verified: user.email_verified,
+            disabled: user.disabled,
+            failed_logins: user.failed_logins,
+            locked_until: user.locked_until,
+            updated_at: user.updated_at,
+        })
+    }
+}
+
+/// A request to create a user.
+#[derive(Deserialize, Debug)]
+pub struct NewUserDTO {
+    pub username: String,
+    pub email: String,
+    /// The user's password; one is generated if it is left out.
+    pub password: Option<String>,
+    /// Roles to assign, besides the default roles.
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// A newly created user, with their password if it was generated.
+#[derive(Serialize, Debug)]
+pub struct CreatedUser {
+    #[serde(flatten)]
+    pub user: UserView,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/// A request to replace the roles assigned to a user.
+#[derive(Deserialize, Debug)]
+pub struct RolesDTO {
+    pub roles: Vec<String>,
+}
+
+/// A client as the admin API shows it, without the secret hash.
+#[derive(Serialize, Debug)]
+pub struct ClientView {
+    pub client_id: String,
+    /// Whether the client is public, with no secret.
+    pub public: bool,
+    pub scopes: Vec<String>,
+    pub audiences: Vec<String>,
+    pub token_ttl: Option<i64>,
+    pub redirect_uris: Vec<String>,
+    /// The client's secret, only when the server has just generated it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+impl From<Client> for ClientView {
+    fn from(client: Client) -> Self {
+        let list = |value: &str| value.split_whitespace().map(str::to_string).collect();
+
+        Self {
+            public: client.is_public(),
+            scopes: list(&client.scopes),
+            audiences: list(&client.audiences),
+            redirect_uris: list(&client.redirect_uris),
+            token_ttl: client.token_ttl,
+            client_id: client.client_id,
+            client_secret: None,
+        }
+    }
+}
+
+/// A request to register or replace a client.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ClientDTO {
+    /// Registers a public client, which has no secret.
+    pub public: bool,
+    /// The client's secret; one is generated for confidential clients if it is left out.
+    pub secret: Option<String>,
+    pub scopes: Vec<String>,
+    pub audiences: Vec<String>,
+    pub token_ttl: Option<u64>,
+    pub redirect_uris: Vec<String>,
+}
+
+/// Lists users, a page at a time.
+///
+/// # Errors
+///
+/// Responds with `401 Unauthorized` or `403 Forbidden` without admin access.
+#[get("/admin/users?<page..>")]
+pub async fn admin_list_users(
+    db_pool: &rocket::State<SqlitePool>,
+    _admin: Admin,
+    page: PageRequest,
+) -> Result<Json<Page<UserView>>, AdminError> {
+    let (limit, offset) = page.limit_offset();
+    let mut items = Vec::new();
+    for user in list_users(db_pool, limit, offset).await? {
+        items.push(UserView::new(db_pool, user).await?);
+    }
+
+    Ok(page.respond(items, count_users(db_pool).await?))
+}
+
+/// Creates a user, with the same checks and hashing as `/register`.
+///
+/// If no password is given, one is generated and returned once in the response.
+///
+/// # Errors
+///
+/// Responds with `400 Bad Request` for an undefined role and `409 Conflict` if the
+/// username or email is taken.
+#[post("/admin/users", data = "<request>")]
+pub async fn admin_create_user(
+    db_pool: &rocket::State<SqlitePool>,
+    admin: Admin,
+    request: Json<NewUserDTO>,
+) -> Result<status::Custom<Json<CreatedUser>>, AdminError> {
+    let defined = list_roles(db_pool).await?;
+    if let Some(role) = request.roles.iter().find(|role| !defined.contains(role)) {
+        return Err(AdminError::bad_request(&format!("unknown role: {role}")));
+    }
+
+    let generated = request
+        .password
+        .is_none()
+        .then(|| Uuid::new_v4().to_string());
+    let password = request
+        .password
+        .as_deref()
+        .or(generated.as_deref())
+        .unwrap_or_default();
+
+    let user = create_user(db_pool, &request.username, &request.email, password).await?;
+    let user_id = user.id.unwrap_or_default();
+    set_user_roles(db_pool, user_id, &request.roles).await?;
+    info!(
+        "{} created user '{}' ({})",
+        admin.actor, request.username, user_id
+    );
+
+    Ok(status::Custom(
+        Status::Created,
+        Json(CreatedUser {
+            user: UserView::new(db_pool, user).await?,
+            password: generated,
+        }),
+    ))
+}
+
+/// Shows a user.
+///
+/// # Errors
+///
+/// Responds with `404 Not Found` for an unknown user.
+#[get("/admin/users/<user_id>")]
+pub async fn admin_get_user(
+    db_pool: &rocket::State<SqlitePool>,
+    _admin: Admin,
+    user_id: i64,
+) -> Result<Json<UserView>, AdminError> {
+    let user = find_user_by_id(db_pool, user_id)
+        .await?
+        .ok_or_else(|| AdminError::not_found("user not found"))?;
+
+    Ok(Json(UserView::new(db_pool, user).await?))
+}
+
+/// Deletes a user, and revokes every token issued to them.
+///
+/// # Errors
+///
+/// Responds with `404 Not Found` for an unknown user.
+#[delete("/admin/users/<user_id>")]
+pub async fn admin_delete_user(
+    db_pool: &rocket::State<SqlitePool>,
+    admin: Admin,
+    user_id: i64,
+) -> Result<Status, AdminError> {
+    if !delete_user(db_pool, user_id).await? {
+        return Err(AdminError::not_found("user not found"));
+    }
+    info!("{} deleted user {}", admin.actor, user_id);
+
+    Ok(Status::NoContent)
+}
+
+/// Disables a user, who can then no longer log in, and revokes every token issued
+/// to them.
+///
+/// # Errors
+///
+/// Responds with `404 Not Found` for an unknown user.
+#[post("/admin/users/<user_id>/disable")]
+pub async fn admin_disable_user(
+    db_pool: &rocket::State<SqlitePool>,
+    admin: Admin,
+    user_id: i64,
+) -> Result<Status, AdminError> {
+    if !set_user_disabled(db_pool, user_id, true).await? {
+        return Err(AdminError::not_found("user not found"));
+    }
+    info!("{} disabled user {}", admin.actor, user_id);
+
+    Ok(Status::NoContent)
+}
+
+/// Lets a disabled user log in again.
+///
+/// # Errors
+///
+/// Responds with `404 Not Found` for an unknown user.
+#[post("/admin/users/<user_id>/enable")]
+pub async fn admin_enable_user(
+    db_pool: &rocket::State<SqlitePool>,
+    admin: Admin,
+    user_id: i64,
+) -> Result<Status, AdminError> {
+    if !set_user_disabled(db_pool, user_id, false).await? {
+        return Err(AdminError::not_found("user not found"));
+    }
+    info!("{} enabled user {}", admin.actor, user_id);
+
+    Ok(Status::NoContent)
+}
+
+/// Clears a user's failed logins, unlocking their account if it was locked.
+///
+/// # Errors
+///
+/// Responds with `404 Not Found` for an unknown user.
+#[post("/admin/users/<user_id>/unlock")]
+pub async fn admin_unlock_user(
+    db_pool: &rocket::State<SqlitePool>,
+    admin: Admin,
+    user_id: i64,
+) -> Result<Status, AdminError> {
+    if !lockout::unlock_user(db_pool, user_id).await? {
+        return Err(AdminError::not_found("user not found"));
+    }
+    info!("{} unlocked user {}", admin.actor, user_id);
+
+    Ok(Status::NoContent)
+}
+
+/// Replaces a user's password with a generated one, which is returned once, and
+/// revokes every token issued to them.
+///
+/// # Errors
+///
+/// Responds with `404 Not Found` for an unknown user.
+#[post("/admin/users/<user_id>/password")]
+pub async fn admin_reset_password(
+    db_pool: &rocket::State<SqlitePool>,
+    admin: Admin,
+    user_id: i64,
+) -> Result<Json<PasswordDTO>, AdminError> {
+    let password = Uuid::new_v4().to_string();
+    if !reset_password(db_pool, user_id, &password).await? {
+        return Err(AdminError::not_found("user not found"));
+    }
+    info!("{} reset the password of user {}", admin.actor, user_id);
+
+    Ok(Json(PasswordDTO::new(&password)))
+}
+
+/// Replaces the roles assigned to a user. Tokens already issued keep their roles
+/// and scopes until they are refreshed.
+///
+/// # Errors
+///
+/// Responds with `400 Bad Request` for an undefined role and `404 Not Found` for an
+/// unknown user.
+#[put("/admin/users/<user_id>/roles", data = "<request>")]
+pub async fn admin_set_roles(
+    db_pool: &rocket::State<SqlitePool>,
+    admin: Admin,
+    user_id: i64,
+    request: Json<RolesDTO>,
+) -> Result<Json<UserView>, AdminError> {
+    let user = find_user_by_id(db_pool, user_id)
+        .await?
+        .ok_or_else(|| AdminError::not_found("user not found"))?;
+    if !set_user_roles(db_pool, user_id, &request.roles).await? {
+        return Err(AdminError::bad_request("unknown role"));
+    }
+    info!(
+        "{} set the roles of user {} to {:?}",
+        admin.actor, user_id, request.roles
+    );
+
+    Ok(Json(UserView::new(db_pool, user).await?))
+}
+
+/// Lists the signing keys, newest first, a page at a time. Private keys are never
+/// shown.
+#[get("/admin/keys?<page..>")]
+pub async fn admin_list_keys(
+    db_pool: &rocket::State<SqlitePool>,
+    _admin: Admin,
+    page: PageRequest,
+) -> Result<Json<Page<KeyInfo>>, AdminError> {
+    let (limit, offset) = page.limit_offset();
+    let keys = list_keys(db_pool, limit, offset).await?;
+
+    Ok(page.respond(keys, count_keys(db_pool).await?))
+}
+
+/// Rotates the signing keys now, ahead of their schedule.
+///
+/// Active keys stop signing at once but stay published until their tokens expire,
+/// and keys published ahead of time start signing.
+#[post("/admin/keys/rotate")]
+pub async fn admin_rotate_keys(
+    db_pool: &rocket::State<SqlitePool>,
+    key_cipher: &rocket::State<KeyCipher>,
+    rotation_policy: &rocket::State<RotationPolicy>,
+    admin: Admin,
+) -> Result<Json<RotationReport>, AdminError> {
+    let report = force_rotation(db_pool, key_cipher, rotation_policy, unix_timestamp()).await?;
+    info!("{} forced a key rotation: {:?}", admin.actor, report);
+
+    Ok(Json(report))
+}
+
+/// Revokes a signing key, and with it every token it signed.
+///
+/// # Errors
+///
+/// Responds with `404 Not Found` for an unknown `kid`.
+#[post("/admin/keys/<kid>/revoke")]
+pub async fn admin_revoke_key(
+    db_pool: &rocket::State<SqlitePool>,
+    admin: Admin,
+    kid: &str,
+) -> Result<Status, AdminError> {
+    if !revoke_key(db_pool, kid).await? {
+        return Err(AdminError::not_found("key not found"));
+    }
+    info!("{} revoked key '{}'", admin.actor, kid);
+
+    Ok(Status::NoContent)
+}
+
+/// Lists the registered clients, a page at a time.
+#[get("/admin/clients?<page..>")]
+pub async fn admin_list_clients(
+    db_pool: &rocket::State<SqlitePool>,
+    _admin: Admin,
+    page: PageRequest,
+) -> Result<Json<Page<ClientView>>, AdminError> {
+    let (limit, offset) = page.limit_offset();
+    let clients = list_clients(db_pool, limit, offset).await?;
+
+    Ok(page.respond(
+        clients.into_iter().map(ClientView::from).collect(),
+        count_clients(db_pool).await?,
+    ))
+}
+
+/// Shows a registered client.
+///
+/// # Errors
+///
+/// Responds with `404 Not Found` for an unknown client.
+#[get("/admin/clients/<client_id>")]
+pub async fn admin_get_client(
+    db_pool: &rocket::State<SqlitePool>,
+    _admin: Admin,
+    client_id: &str,
+) -> Result<Json<ClientView>, AdminError> {
+    let client = find_client(db_pool, client_id)
+        .await?
+        .ok_or_else(|| AdminError::not_found("client not found"))?;
+
+    Ok(Json(client.into()))
+}
+
+/// Registers a client, or replaces its secret and settings if it already exists,
+/// as `OAUTH_CLIENTS` does on startup.
+///
+/// A confidential client registered without a `secret` gets a generated one, which
+/// is returned once as `client_secret`.
+///
+/// # Errors
+///
+/// Responds with `400 Bad Request` if a public client is given a secret, or if the
+/// client id is a number, which would be mistaken for a user's id.
+#[put("/admin/clients/<client_id>", data = "<request>")]
+pub async fn admin_put_client(
+    db_pool: &rocket::State<SqlitePool>,
+    admin: Admin,
+    client_id: &str,
+    request: Json<ClientDTO>,
+) -> Result<Json<ClientView>, AdminError> {
+    if !is_valid_client_id(client_id) {
+        return Err(AdminError::bad_request("client_id must not be a number"));
+    }
+    let request = request.into_inner();
+    let generated = match (request.public, request.secret.as_deref()) {
+        (true, Some(_)) => return Err(AdminError::bad_request("public clients have no secret")),
+        (false, None) => Some(generate_token()),
+        _ => None,
+    };
+    let secret = request.secret.or(generated.clone()).unwrap_or_default();
+
+    let client = create_client(
+        db_pool,
+        &ClientRegistration {
+            scopes: request.scopes,
+            audiences: request.audiences,
+            token_ttl: request.token_ttl,
+            redirect_uris: request.redirect_uris,
+            ..ClientRegistration::new(client_id, &secret)
+        },
+    )
+    .await?;
+    info!("{} registered client '{}'", admin.actor, client_id);
+
+    Ok(Json(ClientView {
+        client_secret: generated,
+        ..client.into()
+    }))
+}
+
+/// Deletes a registered client and revokes the refresh tokens issued to it.
+///
+/// # Errors
+///
+/// Responds with `404 Not Found` for an unknown client.
+#[delete("/admin/clients/<client_id>")]
+pub async fn admin_delete_client(
+    db_pool: &rocket::State<SqlitePool>,
+    admin: Admin,
+    client_id: &str,
+) -> Result<Status, AdminError> {
+    if !delete_client(db_pool, client_id).await? {
+        return Err(AdminError::not_found("client not found"));
+    }
+    info!("{} deleted client '{}'", admin.actor, client_id);
+
+    Ok(Status::NoContent)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{AdminApiKey, LockoutPolicy};
+    use crate::crypto::envelope::MasterKey;
+    use crate::crypto::key_pair::unix_timestamp;
+    use crate::crypto::{KeyCipher, SigningAlgorithm, TokenPolicy};
+    use crate::net::TrustedProxies;
+    use crate::rate_limit::RateLimits;
+    use crate::rotation::{rotate_keys, RotationPolicy};
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use serde_json::{json, Value};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[tokio::test]
+    async fn test_admin_api() {
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        let rotation_policy = RotationPolicy {
+            algorithms: vec![SigningAlgorithm::ES256],
+            ..Default::default()
+        };
+        rotate_keys(&db_pool, &key_cipher, &rotation_policy, unix_timestamp())
+            .await
+            .unwrap();
+        let rocket = crate::build_rocket(
+            db_pool,
+            key_cipher,
+            rotation_policy,
+            TokenPolicy::default(),
+            LockoutPolicy {
+                threshold: 1,
+                ..Default::default()
+            },
+            AdminApiKey::new("letmein"),
+            RateLimits::default(),
+            TrustedProxies::default(),
+        );
+        let client = Client::tracked(rocket).await.unwrap();
+        let remote = "127.0.0.1:8000".parse().unwrap();
+        let bearer = |token: &str| Header::new("Authorization", format!("Bearer {token}"));
+        let login = |username: &'static str, password: String| {
+            client
+                .post("/auth")
+                .remote(remote)
+                .header(ContentType::JSON)
+                .body(json!({ "username": username, "password": password }).to_string())
+                .dispatch()
+        };
+
+        let response = client.get("/admin/users").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .post("/admin/users")
+            .header(bearer("letmein"))
+            .header(ContentType::JSON)
+            .body(r#"{"username": "root", "email": "root@example.com", "roles": ["admin"]}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let root: Value = response.into_json().await.unwrap();
+        assert_eq!(root["roles"], json!(["admin", "user"]));
+        let root_password = root["password"].as_str().unwrap().to_string();
+
+        let response = client
+            .post("/admin/users")
+            .header(bearer("letmein"))
+            .header(ContentType::JSON)
+            .body(r#"{"username": "bob", "email": "bob@example.com", "password": "hunter22"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let bob_id = response.into_json::<Value>().await.unwrap()["id"].clone();
+
+        let default_token = login("root", root_password.clone())
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let response = client
+            .get("/admin/users")
+            .header(bearer(&default_token))
+            .dispatch()
+            .await;
+        assert_eq!(
+            response.status(),
+            Status::Forbidden,
+            "The admin scope must only be granted when asked for."
+        );
+
+        let admin_token = client
+            .post("/auth")
+            .remote(remote)
+            .header(ContentType::JSON)
+            .body(
+                json!({ "username": "root", "password": root_password, "scope": "admin" })
+                    .to_string(),
+            )
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let response = client
+            .get("/admin/users?page=2&per_page=1")
+            .header(bearer(&admin_token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let page: Value = response.into_json().await.unwrap();
+        assert_eq!(page["total"], 2);
+        assert_eq!(page["items"][0]["username"], "bob");
+
+        let bob_token = login("bob", "hunter22".to_string())
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let response = client
+            .get("/admin/users")
+            .header(bearer(&bob_token))
+            .dispatch()
+            .await;
+        assert_eq!(
+            response.status(),
+            Status::Forbidden,
+            "Tokens without the admin scope must be refused."
+        );
+
+        let response = client
+            .post(format!("/admin/users/{bob_id}/disable"))
+            .header(bearer(&admin_token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+        let response = login("bob", "hunter22".to_string()).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        client
+            .post(format!("/admin/users/{bob_id}/enable"))
+            .header(bearer(&admin_token))
+            .dispatch()
+            .await;
+        let response = login("bob", "wrong".to_string()).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = login("bob", "hunter22".to_string()).await;
+        assert_eq!(
+            response.status(),
+            Status::Unauthorized,
+            "A locked account must be refused even with the right password."
+        );
+        let response = client
+            .post(format!("/admin/users/{bob_id}/unlock"))
+            .header(bearer(&admin_token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client
+            .post(format!("/admin/users/{bob_id}/password"))
+            .header(bearer(&admin_token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let password: Value = response.into_json().await.unwrap();
+        let response = login("bob", password["password"].as_str().unwrap().to_string()).await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .post("/admin/keys/rotate")
+            .header(bearer(&admin_token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get("/admin/keys")
+            .header(bearer(&admin_token))
+            .dispatch()
+            .await;
+        let keys: Value = response.into_json().await.unwrap();
+        assert_eq!(keys["items"][0]["state"], "active");
+
+        let response = client
+            .put(format!("/admin/clients/{bob_id}"))
+            .header(bearer(&admin_token))
+            .header(ContentType::JSON)
+            .body(r#"{"public": true}"#)
+            .dispatch()
+            .await;
+        assert_eq!(
+            response.status(),
+            Status::BadRequest,
+            "A client must not be able to take a user's id as its subject."
+        );
+    }
+}
@@ -1,6 +1,16 @@
-use crate::auth::{create_user, ClientIp, PasswordDTO, RateLimited, RegisterDTO};
-use crate::crypto::{CryptoError, Jwks, Jwt, KeyPair};
-use crate::db::KeysTable;
+use crate::auth::client::find_client;
+use crate::auth::refresh::issue_refresh_token;
+use crate::auth::roles::grant_user_scopes;
+use crate::auth::{
+    authenticate_user, create_user, log_token_request, record_login, user_claims, AuthError,
+    ClientIp, LockoutPolicy, LoginDTO, PasswordDTO, RegisterDTO,
+};
+use crate::crypto::key_pair::unix_timestamp;
+use crate::crypto::{CryptoError, Jwks, Jwt, KeyCipher, KeyState, SigningAlgorithm, TokenPolicy};
+use crate::db::load_key_pairs;
+use crate::rate_limit::{RateLimited, RateLimitedBody};
+use crate::rotation::RotationPolicy;
+use crate::routes::TokenResponse;
 use rocket::http::Status;
 use rocket::response::status;
 use rocket::serde::json::Json;
@@ -9,74 +19,188 @@ use uuid::Uuid;
 
 /// Provides the public keys in JWKS (JSON Web Key Set) format.
 ///
-/// This endpoint serves public keys that are currently valid and have not expired,
+/// This endpoint serves the public keys of pending, active and retiring keys,
 /// allowing clients to verify the authenticity of JWTs issued by this server.
+/// Expired and revoked keys are never published.
+///
+/// # Errors
+///
+/// Responds with `500 Internal Server Error` if the stored keys cannot be loaded or
+/// decrypted, and with `429 Too Many Requests` if the `jwks` rate limit policy refuses
+/// the request.
 #[get("/.well-known/jwks.json")]
-pub async fn get_jwks(db_pool: &rocket::State<SqlitePool>) -> Json<Jwks> {
-    let private_keys: Vec<KeysTable> = sqlx::query_as!(KeysTable, "SELECT * FROM keys")
-        .fetch_all(&**db_pool)
-        .await
-        .expect("");
+pub async fn get_jwks(
+    db_pool: &rocket::State<SqlitePool>,
+    key_cipher: &rocket::State<KeyCipher>,
+    _rate_limited: RateLimited<'_>,
+) -> Result<Json<Jwks>, CryptoError> {
+    let key_pairs = load_key_pairs(db_pool, key_cipher).await?;
+    Ok(Json(Jwks::from_valid_pairs(key_pairs)))
+}
 
-    let key_pairs: Vec<KeyPair> = private_keys
-        .iter()
-        .map(|pk| KeyPair::from_private_key(pk.kid, &pk.key, pk.exp).unwrap())
-        .filter(|kp| !kp.is_expired())
-        .collect();
-    Json(Jwks::from_valid_pairs(key_pairs))
+/// The response of `/auth`: a bare JWT, or a token response when a refresh token
+/// was requested too.
+#[derive(Responder, Debug)]
+pub enum AuthResponse {
+    Jwt(String),
+    Tokens(Json<TokenResponse>),
 }
 
 /// Authenticates a user and returns a JWT.
 ///
-/// This endpoint issues JWTs for authenticated users. Clients can request an expired JWT
-/// for testing purposes by setting the `expired` query parameter to `true`.
+/// This endpoint verifies the supplied username and password against the `users`
+/// table and issues a JWT, signed by an active key, whose `sub` claim is the user's id.
+/// The token also carries `iss` and a lifetime from the `TokenPolicy`, an `aud` claim
+/// naming the `client_id` from the request if one was given, which must be a
+/// registered client, and the user's `email`.
+/// It also carries the user's `roles`, and in `scope` the requested scopes that those
+/// roles allow, or if no `scope` was requested all of them but the privileged ones,
+/// such as `admin`, which must be asked for. Credentials are accepted as JSON or as a
+/// form-encoded body. Clients can request an expired JWT for testing
+/// purposes by setting the `expired` query parameter to `true`. It is signed with an
+/// expired key if one is still kept, or else with an active key, and its `exp` is in
+/// the past either way.
+///
+/// The token is signed with the first algorithm in `SIGNING_ALGORITHMS` unless the
+/// `alg` query parameter asks for another configured algorithm, such as `ES256` or
+/// `EdDSA`.
+///
+/// Setting the `refresh` query parameter to `true` also issues a refresh token, which
+/// can be exchanged at `/token` for a new access token once this one expires. The
+/// response is then an RFC 6749 token response in JSON rather than the bare JWT. No
+/// `id_token` is issued here, since the client named by `client_id` takes no part in
+/// the login; OpenID Connect clients use `/authorize` instead.
 ///
 /// # Arguments
 ///
 /// * `expired` - An optional query parameter that dictates whether the issued JWT should be expired.
-#[post("/auth?<expired>")]
+/// * `alg` - An optional query parameter naming the algorithm to sign with.
+/// * `refresh` - An optional query parameter asking for a refresh token as well.
+/// * `creds` - The username and password of the user logging in.
+///
+/// # Errors
+///
+/// Responds with `400 Bad Request` if `alg` is not a configured algorithm, with
+/// `401 Unauthorized` if `client_id` is not a registered client, or if the credentials
+/// do not match a registered user or the account is disabled or locked after failed
+/// logins, and with `429 Too Many Requests` if the
+/// `auth` rate limit policy refuses the request or too many logins have failed lately.
+#[allow(clippy::too_many_arguments)]
+#[post("/auth?<expired>&<alg>&<refresh>", data = "<creds>")]
 pub async fn auth(
     db_pool: &rocket::State<SqlitePool>,
+    key_cipher: &rocket::State<KeyCipher>,
+    rotation_policy: &rocket::State<RotationPolicy>,
+    token_policy: &rocket::State<TokenPolicy>,
+    lockout_policy: &rocket::State<LockoutPolicy>,
     request_ip: ClientIp,
-    _rate_limited: RateLimited,
+    rate_limited: RateLimited<'_>,
     expired: Option<bool>,
-) -> Result<String, CryptoError> {
-    let find_expired = expired.unwrap_or(false);
-    let private_keys: Vec<KeysTable> = sqlx::query_as!(KeysTable, "SELECT * FROM keys")
-        .fetch_all(&**db_pool)
-        .await
-        .expect("Unable to get keys from keys table");
-
-    let request_ip = request_ip.0;
-    let user_id = 1;
-
-    sqlx::query!(
-        "INSERT INTO auth_logs (request_ip, user_id) VALUES (?, ?)",
-        request_ip,
-        user_id
-    )
-    .execute(&**db_pool)
-    .await
-    .expect("");
+    alg: Option<&str>,
+    refresh: Option<bool>,
+    creds: RateLimitedBody<LoginDTO>,
+) -> Result<AuthResponse, AuthError> {
+    rate_limited.check_login(&creds.username).await?;
 
-    let key_pairs: Vec<KeyPair> = private_keys
-        .iter()
-        .map(|pk| KeyPair::from_private_key(pk.kid, &pk.key, pk.exp).unwrap())
-        .collect();
+    if let Some(client_id) = creds.client_id.as_deref() {
+        if find_client(db_pool, client_id).await?.is_none() {
+            return Err(AuthError::InvalidClient);
+        }
+    }
+
+    let algorithm = match alg {
+        Some(alg) => alg.parse::<SigningAlgorithm>()?,
+        None => rotation_policy.default_algorithm(),
+    };
+    if !rotation_policy.algorithms.contains(&algorithm) {
+        return Err(CryptoError::UnsupportedAlgorithm(algorithm.to_string()).into());
+    }
+
+    let user = match authenticate_user(db_pool, lockout_policy, &creds, &request_ip.0).await {
+        Err(AuthError::InvalidCredentials) => {
+            rate_limited.login_failed(&creds.username).await;
+            return Err(AuthError::InvalidCredentials);
+        }
+        result => result?,
+    };
+    let user_id = user.id.ok_or(AuthError::InvalidCredentials)?;
+
+    let find_expired = expired.unwrap_or(false);
+    let key_pairs = load_key_pairs(db_pool, key_cipher).await?;
 
+    // An expired key is only kept for `KEY_RETENTION_SECS`, and there is none yet on a
+    // new database, so an expired token falls back to a signing key.
     let key_pair = key_pairs
         .iter()
-        .find(|kp| find_expired == kp.is_expired())
+        .filter(|kp| kp.algorithm == algorithm)
+        .find(|kp| find_expired && kp.state == KeyState::Expired)
+        .or_else(|| {
+            key_pairs
+                .iter()
+                .find(|kp| kp.algorithm == algorithm && kp.state.can_sign())
+        })
         .ok_or(CryptoError::TokenCreationError)?;
 
-    Ok(Jwt::from(&key_pair)?)
+    log_token_request(
+        db_pool,
+        &request_ip.0,
+        Some(user_id),
+        creds.client_id.as_deref(),
+    )
+    .await?;
+
+    record_login(db_pool, user_id).await?;
+    info!("Issuing token for user '{}' ({})", user.username, user_id);
+
+    let grant = grant_user_scopes(db_pool, user_id, creds.scope.as_deref()).await?;
+    let now = unix_timestamp();
+    // An expired token is issued as if a whole lifetime, and the leeway, ago.
+    let issued_at = if find_expired {
+        now.saturating_sub(token_policy.ttl.saturating_add(token_policy.leeway) + 1)
+    } else {
+        now
+    };
+    let claims = user_claims(
+        token_policy,
+        user_id,
+        &user,
+        creds.client_id.as_deref(),
+        &grant,
+    )
+    .build(key_pair, issued_at);
+    let access_token = Jwt::from(key_pair, &claims)?;
+
+    if !refresh.unwrap_or(false) {
+        return Ok(AuthResponse::Jwt(access_token));
+    }
+
+    let refresh_token = issue_refresh_token(
+        db_pool,
+        user_id,
+        creds.client_id.as_deref(),
+        grant.scope(),
+        now,
+        None,
+        now.saturating_add(token_policy.refresh_ttl),
+    )
+    .await?;
+
+    Ok(AuthResponse::Tokens(Json(
+        TokenResponse::bearer(access_token, &claims, now).with_refresh_token(refresh_token),
+    )))
 }
 
+/// Registers a user with a generated password, which is returned once.
+///
+/// # Errors
+///
+/// Responds with `429 Too Many Requests` if the `register` rate limit policy refuses
+/// the request, and with `500 Internal Server Error` if the user cannot be created.
 #[post("/register", data = "<creds>")]
 pub async fn register(
     db_pool: &rocket::State<SqlitePool>,
-    creds: Json<RegisterDTO>,
-) -> Result<status::Custom<Json<PasswordDTO>>, Json<String>> {
+    creds: RateLimitedBody<Json<RegisterDTO>>,
+) -> Result<status::Custom<Json<PasswordDTO>>, AuthError> {
     let new_generated_password = Uuid::new_v4().to_string();
 
     create_user(
@@ -85,11 +209,134 @@ pub async fn register(
         &creds.email,
         &new_generated_password,
     )
-    .await
-    .expect("failed to create new user");
+    .await?;
 
     Ok(status::Custom(
         Status::Created,
         Json(PasswordDTO::new(&new_generated_password)),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::client::{create_client, ClientRegistration};
+    use crate::auth::{create_user, AdminApiKey, LockoutPolicy};
+    use crate::crypto::envelope::MasterKey;
+    use crate::crypto::key_pair::unix_timestamp;
+    use crate::crypto::{KeyCipher, SigningAlgorithm, TokenPolicy};
+    use crate::net::TrustedProxies;
+    use crate::rate_limit::RateLimits;
+    use crate::rotation::{rotate_keys, RotationPolicy};
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::{Client, LocalResponse};
+    use serde_json::{json, Value};
+    use sqlx::sqlite::SqlitePoolOptions;
+    use std::net::SocketAddr;
+
+    const REMOTE: &str = "127.0.0.1:8000";
+
+    /// A server with the user `alice` and the client `app`, and keys rotated in.
+    async fn setup_client() -> Client {
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        create_user(&db_pool, "alice", "alice@example.com", "password123")
+            .await
+            .unwrap();
+        create_client(&db_pool, &ClientRegistration::new("app", ""))
+            .await
+            .unwrap();
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        let rotation_policy = RotationPolicy {
+            algorithms: vec![SigningAlgorithm::ES256],
+            ..Default::default()
+        };
+        rotate_keys(&db_pool, &key_cipher, &rotation_policy, unix_timestamp())
+            .await
+            .unwrap();
+        let rocket = crate::build_rocket(
+            db_pool,
+            key_cipher,
+            rotation_policy,
+            TokenPolicy::default(),
+            LockoutPolicy::default(),
+            AdminApiKey::default(),
+            RateLimits::default(),
+            TrustedProxies::default(),
+        );
+
+        Client::tracked(rocket).await.unwrap()
+    }
+
+    async fn login<'c>(client: &'c Client, uri: &'c str, body: Value) -> LocalResponse<'c> {
+        client
+            .post(uri)
+            .remote(REMOTE.parse::<SocketAddr>().unwrap())
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch()
+            .await
+    }
+
+    async fn verify(client: &Client, token: String) -> LocalResponse<'_> {
+        client
+            .post("/verify")
+            .remote(REMOTE.parse::<SocketAddr>().unwrap())
+            .json(&json!({ "token": token }))
+            .dispatch()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_auth_expired_token() {
+        let client = setup_client().await;
+        let alice = json!({ "username": "alice", "password": "password123" });
+
+        let response = login(&client, "/auth", alice.clone()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = verify(&client, response.into_string().await.unwrap()).await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = login(&client, "/auth?expired=true", alice).await;
+        assert_eq!(
+            response.status(),
+            Status::Ok,
+            "A new database has no expired key, but can still issue an expired token."
+        );
+        let response = verify(&client, response.into_string().await.unwrap()).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let error: Value = response.into_json().await.unwrap();
+        assert_eq!(error["error"], "token has expired");
+    }
+
+    #[tokio::test]
+    async fn test_auth_client_id() {
+        let client = setup_client().await;
+        let alice = |client_id: &str| json!({ "username": "alice", "password": "password123", "client_id": client_id });
+
+        let response = login(&client, "/auth?refresh=true", alice("stranger")).await;
+        assert_eq!(
+            response.status(),
+            Status::Unauthorized,
+            "Tokens must not be issued for unregistered clients."
+        );
+
+        let response = login(&client, "/auth?refresh=true", alice("app")).await;
+        assert_eq!(response.status(), Status::Ok);
+        let tokens: Value = response.into_json().await.unwrap();
+        assert!(
+            tokens.get("id_token").is_none(),
+            "ID tokens are only issued to clients that take part in the login."
+        );
+        let access_token = tokens["access_token"].as_str().unwrap().to_string();
+        let claims: Value = verify(&client, access_token)
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(claims["aud"], "app");
+    }
+}
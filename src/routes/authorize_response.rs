@@ -0,0 +1,747 @@
+use crate::auth::client::{find_client, Client};
+use crate::auth::code::{issue_authorization_code, AuthorizationCode};
+use crate::auth::roles::{grant_user_scopes, is_privileged_scope};
+use crate::auth::{
+    authenticate_user, generate_token, record_login, AuthError, ClientIp, LockoutPolicy, LoginDTO,
+};
+use crate::crypto::key_pair::unix_timestamp;
+use crate::rate_limit::{RateLimitSubject, RateLimited, RateLimitedBody};
+use rocket::form::Form;
+use rocket::http::uri::Origin;
+use rocket::http::{Cookie, CookieJar, RawStr, SameSite};
+use rocket::request::Request;
+use rocket::response::content::RawHtml;
+use rocket::response::Redirect;
+use sqlx::SqlitePool;
+use subtle::ConstantTimeEq;
+
+/// The cookie holding the anti-CSRF token of the login form.
+const CSRF_COOKIE: &str = "authorize_csrf";
+
+/// The parameters of an authorization request, as described in RFC 6749 section 4.1.1
+/// and RFC 7636 section 4.3.
+///
+/// Every parameter is optional here so that missing ones can be reported as OAuth
+/// errors rather than rejected by Rocket.
+#[derive(Debug, FromForm, Default)]
+pub struct AuthorizeRequest {
+    /// Must be `code`.
+    pub response_type: Option<String>,
+    /// The client asking for authorization.
+    pub client_id: Option<String>,
+    /// Where to send the user back to; optional if the client has only one.
+    pub redirect_uri: Option<String>,
+    /// The space separated scopes requested.
+    pub scope: Option<String>,
+    /// An opaque value the client gets back with the code.
+    pub state: Option<String>,
+    /// The PKCE challenge, the S256 hash of a verifier only the client knows.
+    pub code_challenge: Option<String>,
+    /// Must be `S256`.
+    pub code_challenge_method: Option<String>,
+    /// An OpenID Connect nonce, which is copied into the ID token.
+    pub nonce: Option<String>,
+}
+
+/// The login form posted to `/authorize`.
+#[derive(Debug, FromForm)]
+pub struct AuthorizeLogin {
+    pub username: String,
+    pub password: String,
+    /// The anti-CSRF token the form was rendered with.
+    pub csrf_token: Option<String>,
+}
+
+impl RateLimitSubject for AuthorizeLogin {
+    fn username(&self) -> Option<&str> {
+        Some(&self.username)
+    }
+
+    /// The `client_id` of the authorization request, from the query.
+    fn client_id(&self, request: &Request<'_>) -> Option<String> {
+        request
+            .query_value::<String>("client_id")
+            .and_then(Result::ok)
+    }
+}
+
+/// The responses of `/authorize`.
+#[derive(Responder, Debug)]
+pub enum AuthorizeResponse {
+    /// The login form.
+    Form(RawHtml<String>),
+    /// A redirect back to the client, with a code or an error.
+    Redirect(Box<Redirect>),
+    /// The login form again, after a failed login.
+    #[response(status = 401)]
+    LoginFailed(RawHtml<String>),
+    /// The login form again, after a login posted without the form's anti-CSRF token.
+    #[response(status = 403)]
+    Forbidden(RawHtml<String>),
+    /// An error page, for requests that cannot safely be redirected back to the client.
+    #[response(status = 400)]
+    BadRequest(RawHtml<String>),
+}
+
+/// An authorization request that has passed validation.
+struct Authorization {
+    client: Client,
+    redirect_uri: String,
+    redirect_uri_sent: bool,
+    scope: String,
+    state: Option<String>,
+    code_challenge: String,
+    nonce: Option<String>,
+}
+
+/// Builds a redirect to `redirect_uri` with `params` added to its query.
+fn redirect_with(redirect_uri: &str, params: &[(&str, Option<&str>)]) -> Box<Redirect> {
+    let mut uri = redirect_uri.to_string();
+    let mut separator = if uri.contains('?') { '&' } else { '?' };
+
+    for (name, value) in params {
+        if let Some(value) = value {
+            uri.push(separator);
+            uri.push_str(name);
+            uri.push('=');
+            uri.push_str(RawStr::new(value).percent_encode().as_str());
+            separator = '&';
+        }
+    }
+
+    Box::new(Redirect::to(uri))
+}
+
+/// Renders a minimal HTML page.
+fn page(title: &str, body: &str) -> RawHtml<String> {
+    RawHtml(format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+         <body>\n<h1>{title}</h1>\n{body}\n</body>\n</html>\n"
+    ))
+}
+
+/// Issues an anti-CSRF token for the login form.
+///
+/// The token is set in a cookie as well as returned for the form, and a login is only
+/// accepted if the two match, which a page on another site posting to `/authorize`
+/// cannot arrange.
+fn issue_csrf_token(cookies: &CookieJar<'_>) -> String {
+    let token = generate_token();
+    cookies.add(
+        Cookie::build((CSRF_COOKIE, token.clone()))
+            .path("/authorize")
+            .http_only(true)
+            .same_site(SameSite::Strict),
+    );
+
+    token
+}
+
+/// Checks the anti-CSRF token posted with the login form against the cookie.
+fn verify_csrf_token(cookies: &CookieJar<'_>, csrf_token: Option<&str>) -> bool {
+    match (cookies.get(CSRF_COOKIE), csrf_token) {
+        (Some(cookie), Some(csrf_token)) => cookie
+            .value()
+            .as_bytes()
+            .ct_eq(csrf_token.as_bytes())
+            .into(),
+        _ => false,
+    }
+}
+
+/// Renders the login form, which posts back to `/authorize` with the same query.
+fn login_page(
+    origin: &Origin<'_>,
+    client_id: &str,
+    csrf_token: &str,
+    error: Option<&str>,
+) -> RawHtml<String> {
+    let action = RawStr::new(&origin.to_string()).html_escape().into_owned();
+    let error = error
+        .map(|error| {
+            format!(
+                "<p role=\"alert\">{}</p>\n",
+                RawStr::new(error).html_escape()
+            )
+        })
+        .unwrap_or_default();
+
+    page(
+        "Sign in",
+        &format!(
+            "<p>Sign in to continue to <strong>{}</strong>.</p>\n{error}\
+             <form method=\"post\" action=\"{action}\">\n\
+             <input type=\"hidden\" name=\"csrf_token\" value=\"{}\">\n\
+             <label>Username <input name=\"username\" autocomplete=\"username\" required></label>\n\
+             <label>Password <input name=\"password\" type=\"password\" autocomplete=\"current-password\" required></label>\n\
+             <button type=\"submit\">Sign in</button>\n\
+             </form>",
+            RawStr::new(client_id).html_escape(),
+            RawStr::new(csrf_token).html_escape()
+        ),
+    )
+}
+
+/// Validates an authorization request.
+///
+/// Following RFC 6749 section 4.1.2.1, problems with the client or redirect URI are
+/// shown to the user, since the redirect URI cannot be trusted, while every other
+/// problem is reported to the client by redirecting with an `error` parameter.
+async fn validate(
+    db_pool: &SqlitePool,
+    request: &AuthorizeRequest,
+) -> Result<Authorization, AuthorizeResponse> {
+    let bad_request = |message: &str| {
+        AuthorizeResponse::BadRequest(page("Invalid request", &format!("<p>{message}</p>")))
+    };
+
+    let Some(client_id) = request.client_id.as_deref() else {
+        return Err(bad_request("The request is missing a client_id."));
+    };
+    let client = match find_client(db_pool, client_id).await {
+        Ok(Some(client)) => client,
+        Ok(None) => return Err(bad_request("The client is not registered.")),
+        Err(err) => {
+            error!("Failed to look up client: {}", err);
+            return Err(bad_request("The client could not be looked up."));
+        }
+    };
+    let Some(redirect_uri) = client.redirect_uri(request.redirect_uri.as_deref()) else {
+        return Err(bad_request(
+            "The redirect_uri is not registered for this client.",
+        ));
+    };
+
+    let state = request.state.as_deref();
+    let error = |error: &str, description: &str| {
+        AuthorizeResponse::Redirect(redirect_with(
+            &redirect_uri,
+            &[
+                ("error", Some(error)),
+                ("error_description", Some(description)),
+                ("state", state),
+            ],
+        ))
+    };
+
+    if request.response_type.as_deref() != Some("code") {
+        return Err(error(
+            "unsupported_response_type",
+            "response_type must be code",
+        ));
+    }
+    let Some(code_challenge) = request.code_challenge.as_deref() else {
+        return Err(error("invalid_request", "code_challenge is required"));
+    };
+    if request.code_challenge_method.as_deref() != Some("S256") {
+        return Err(error(
+            "invalid_request",
+            "code_challenge_method must be S256",
+        ));
+    }
+    let Ok(mut scope) = client.grant_scopes(request.scope.as_deref()) else {
+        return Err(error(
+            "invalid_scope",
+            "the client may not request that scope",
+        ));
+    };
+    if request.scope.is_none() {
+        scope = scope
+            .split_whitespace()
+            .filter(|scope| !is_privileged_scope(scope))
+            .collect::<Vec<_>>()
+            .join(" ");
+    }
+
+    Ok(Authorization {
+        redirect_uri,
+        redirect_uri_sent: request.redirect_uri.is_some(),
+        scope,
+        state: request.state.clone(),
+        code_challenge: code_challenge.to_string(),
+        nonce: request.nonce.clone(),
+        client,
+    })
+}
+
+/// Shows the login form for the authorization code flow.
+///
+/// The request is validated before the form is shown; see [`authorize`].
+///
+/// # Errors
+///
+/// Responds with `400 Bad Request` if the client or redirect URI is invalid, and
+/// redirects back to the client with an `error` parameter for any other problem.
+#[get("/authorize?<request..>")]
+pub async fn authorize_form(
+    db_pool: &rocket::State<SqlitePool>,
+    origin: &Origin<'_>,
+    cookies: &CookieJar<'_>,
+    request: AuthorizeRequest,
+) -> AuthorizeResponse {
+    match validate(db_pool, &request).await {
+        Ok(authorization) => AuthorizeResponse::Form(login_page(
+            origin,
+            &authorization.client.client_id,
+            &issue_csrf_token(cookies),
+            None,
+        )),
+        Err(response) => response,
+    }
+}
+
+/// Logs the user in and sends them back to the client with an authorization code,
+/// implementing the authorization code grant of RFC 6749 with PKCE.
+///
+/// The client must be registered with the redirect URI, and must send an S256
+/// `code_challenge`, as RFC 7636 describes; plain challenges are not accepted. The
+/// username and password are checked against the `users` table, and the scopes
+/// granted are those requested that both the client and the user's roles allow.
+/// Without a `scope`, the privileged scopes, such as `admin`, are left out of the
+/// client's default scopes, since they must be asked for. On success the user is
+/// redirected to the client's redirect URI with a single use `code`, valid for
+/// `AUTHORIZATION_CODE_TTL` seconds, and the client's `state`. The client exchanges
+/// the code at `/token` with `grant_type=authorization_code` and its verifier. A
+/// `nonce` sent by an OpenID Connect client is copied into the ID token it then gets.
+///
+/// The form carries an anti-CSRF token, also set in a cookie, and a login posted
+/// without it is refused, so that another site cannot log the user in to an account
+/// of its choosing.
+///
+/// # Errors
+///
+/// Responds with `400 Bad Request` if the client or redirect URI is invalid, redirects
+/// back to the client with an `error` parameter for any other problem with the
+/// request, and shows the form again with `401 Unauthorized` if the login fails, for
+/// whatever reason, including the account being locked after failed logins. Shows
+/// the form again with `403 Forbidden` if the anti-CSRF token is missing or wrong.
+/// Responds with `429 Too Many Requests` if the `authorize` rate limit policy refuses
+/// the request or too many logins have failed lately.
+#[allow(clippy::too_many_arguments)]
+#[post("/authorize?<request..>", data = "<login>")]
+pub async fn authorize(
+    db_pool: &rocket::State<SqlitePool>,
+    lockout_policy: &rocket::State<LockoutPolicy>,
+    origin: &Origin<'_>,
+    cookies: &CookieJar<'_>,
+    request_ip: ClientIp,
+    rate_limited: RateLimited<'_>,
+    request: AuthorizeRequest,
+    login: RateLimitedBody<Form<AuthorizeLogin>>,
+) -> Result<AuthorizeResponse, AuthError> {
+    let authorization = match validate(db_pool, &request).await {
+        Ok(authorization) => authorization,
+        Err(response) => return Ok(response),
+    };
+    let client_id = authorization.client.client_id.as_str();
+    if !verify_csrf_token(cookies, login.csrf_token.as_deref()) {
+        warn!(
+            "Refusing a login to client '{}' without its form",
+            client_id
+        );
+        return Ok(AuthorizeResponse::Forbidden(login_page(
+            origin,
+            client_id,
+            &issue_csrf_token(cookies),
+            Some("The sign in form has expired. Please sign in again."),
+        )));
+    }
+    rate_limited.check_login(&login.username).await?;
+
+    let creds = LoginDTO {
+        username: login.username.clone(),
+        password: login.password.clone(),
+        client_id: Some(client_id.to_string()),
+        scope: None,
+    };
+    let user = match authenticate_user(db_pool, lockout_policy, &creds, &request_ip.0).await {
+        Ok(user) => user,
+        Err(AuthError::InvalidCredentials) => {
+            rate_limited.login_failed(&login.username).await;
+            return Ok(AuthorizeResponse::LoginFailed(login_page(
+                origin,
+                client_id,
+                &issue_csrf_token(cookies),
+                Some("Invalid username or password."),
+            )));
+        }
+        Err(err) => return Err(err),
+    };
+    let user_id = user.id.ok_or(AuthError::InvalidCredentials)?;
+
+    record_login(db_pool, user_id).await?;
+    info!(
+        "Issuing authorization code for user '{}' ({}) to client '{}'",
+        user.username, user_id, client_id
+    );
+
+    let grant = grant_user_scopes(db_pool, user_id, Some(&authorization.scope)).await?;
+    let now = unix_timestamp();
+    let code = AuthorizationCode {
+        redirect_uri_sent: authorization.redirect_uri_sent,
+        ..AuthorizationCode::new(
+            client_id,
+            user_id,
+            &authorization.redirect_uri,
+            grant.scope(),
+            &authorization.code_challenge,
+            authorization.nonce.as_deref(),
+            now,
+        )
+    };
+    let code = issue_authorization_code(db_pool, &code, now).await?;
+
+    Ok(AuthorizeResponse::Redirect(redirect_with(
+        &authorization.redirect_uri,
+        &[
+            ("code", Some(&code)),
+            ("state", authorization.state.as_deref()),
+        ],
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::client::{create_client, ClientRegistration};
+    use crate::auth::{create_user, AdminApiKey, LockoutPolicy};
+    use crate::crypto::envelope::MasterKey;
+    use crate::crypto::key_pair::unix_timestamp;
+    use crate::crypto::{KeyCipher, SigningAlgorithm, TokenPolicy};
+    use crate::net::TrustedProxies;
+    use crate::rate_limit::policy::{self, KeyPart};
+    use crate::rate_limit::{RateLimitPolicy, RateLimits};
+    use crate::rotation::{rotate_keys, RotationPolicy};
+    use base64::engine::general_purpose;
+    use base64::Engine;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::{Client, LocalResponse};
+    use serde_json::Value;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    // The example from RFC 7636 appendix B.
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+    const REMOTE: &str = "127.0.0.1:8000";
+
+    /// A server with the user `alice` and the public client `app`, which redirects to
+    /// `https://app.test/callback`.
+    async fn setup_client() -> Client {
+        setup_client_with(RateLimits::default()).await
+    }
+
+    async fn setup_client_with(rate_limits: RateLimits) -> Client {
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        create_user(&db_pool, "alice", "alice@example.com", "password123")
+            .await
+            .unwrap();
+        create_client(
+            &db_pool,
+            &ClientRegistration {
+                scopes: vec!["openid".to_string(), "profile".to_string()],
+                redirect_uris: vec!["https://app.test/callback".to_string()],
+                ..ClientRegistration::new("app", "")
+            },
+        )
+        .await
+        .unwrap();
+
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        let rotation_policy = RotationPolicy {
+            algorithms: vec![SigningAlgorithm::ES256],
+            ..Default::default()
+        };
+        rotate_keys(&db_pool, &key_cipher, &rotation_policy, unix_timestamp())
+            .await
+            .unwrap();
+        let rocket = crate::build_rocket(
+            db_pool,
+            key_cipher,
+            rotation_policy,
+            TokenPolicy::default(),
+            // Without back-off, so that a wrong password does not hold up the right one.
+            LockoutPolicy {
+                backoff: 0,
+                ..Default::default()
+            },
+            AdminApiKey::default(),
+            rate_limits,
+            TrustedProxies::default(),
+        );
+
+        Client::tracked(rocket).await.unwrap()
+    }
+
+    /// Shows the login form for `authorize` and returns its anti-CSRF token.
+    async fn login_form(client: &Client, authorize: &str) -> String {
+        let response = client.get(authorize).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let html = response.into_string().await.unwrap();
+        let (_, rest) = html
+            .split_once("name=\"csrf_token\" value=\"")
+            .expect("The login form must carry an anti-CSRF token.");
+
+        rest.split('"').next().unwrap().to_string()
+    }
+
+    async fn login<'c>(client: &'c Client, authorize: &str, body: String) -> LocalResponse<'c> {
+        client
+            .post(authorize.to_string())
+            .remote(REMOTE.parse().unwrap())
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+            .await
+    }
+
+    /// Logs `alice` in through the form and returns the code sent to the client.
+    async fn authorization_code(client: &Client, authorize: &str) -> String {
+        let csrf_token = login_form(client, authorize).await;
+        let response = login(
+            client,
+            authorize,
+            format!("username=alice&password=password123&csrf_token={csrf_token}"),
+        )
+        .await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap();
+        let (_, query) = location
+            .split_once("?code=")
+            .expect("The code must be sent to the redirect URI.");
+
+        query.split('&').next().unwrap().to_string()
+    }
+
+    async fn exchange<'c>(client: &'c Client, params: &str) -> LocalResponse<'c> {
+        client
+            .post("/token")
+            .remote(REMOTE.parse().unwrap())
+            .header(ContentType::Form)
+            .body(format!(
+                "grant_type=authorization_code&client_id=app&code_verifier={VERIFIER}{params}"
+            ))
+            .dispatch()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_authorization_code_flow() {
+        let client = setup_client().await;
+
+        let authorize = format!(
+            "/authorize?response_type=code&client_id=app&state=xyz&nonce=n-0S6\
+             &code_challenge={CHALLENGE}&code_challenge_method=S256"
+        );
+        let csrf_token = login_form(&client, &authorize).await;
+
+        let response = login(
+            &client,
+            &authorize,
+            format!("username=alice&password=wrong&csrf_token={csrf_token}"),
+        )
+        .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let csrf_token = login_form(&client, &authorize).await;
+        let response = login(
+            &client,
+            &authorize,
+            format!("username=alice&password=password123&csrf_token={csrf_token}"),
+        )
+        .await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap();
+        let code = location
+            .strip_prefix("https://app.test/callback?code=")
+            .and_then(|query| query.strip_suffix("&state=xyz"))
+            .expect("The code and state must be sent to the redirect URI.");
+
+        let response = exchange(&client, &format!("&code={code}")).await;
+        assert_eq!(response.status(), Status::Ok);
+        let tokens: Value = response.into_json().await.unwrap();
+        assert_eq!(tokens["scope"], "openid profile");
+        assert!(tokens["refresh_token"].is_string());
+
+        let id_token = tokens["id_token"].as_str().unwrap();
+        let payload = id_token.split('.').nth(1).unwrap();
+        let id_claims: Value =
+            serde_json::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(payload).unwrap())
+                .unwrap();
+        assert_eq!(id_claims["aud"], "app");
+        assert_eq!(id_claims["nonce"], "n-0S6");
+        assert_eq!(id_claims["preferred_username"], "alice");
+        assert!(
+            id_claims.get("email").is_none(),
+            "The email claim needs the email scope."
+        );
+        assert_eq!(
+            id_claims["at_hash"],
+            SigningAlgorithm::ES256.at_hash(tokens["access_token"].as_str().unwrap())
+        );
+
+        let response = client
+            .get("/userinfo")
+            .header(Header::new(
+                "Authorization",
+                format!("Bearer {}", tokens["access_token"].as_str().unwrap()),
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let userinfo: Value = response.into_json().await.unwrap();
+        assert_eq!(userinfo["sub"], id_claims["sub"]);
+        assert_eq!(userinfo["preferred_username"], "alice");
+
+        let response = exchange(&client, &format!("&code={code}")).await;
+        assert_eq!(
+            response.status(),
+            Status::BadRequest,
+            "Authorization codes must be single use."
+        );
+
+        let response = client
+            .get("/authorize?response_type=code&client_id=app&state=xyz&code_challenge=abc")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::SeeOther);
+        assert!(response
+            .headers()
+            .get_one("Location")
+            .unwrap()
+            .contains("error=invalid_request"));
+    }
+
+    #[tokio::test]
+    async fn test_authorization_code_redirect_uri() {
+        let client = setup_client().await;
+        let authorize = format!(
+            "/authorize?response_type=code&client_id=app\
+             &redirect_uri=https%3A%2F%2Fapp.test%2Fcallback\
+             &code_challenge={CHALLENGE}&code_challenge_method=S256"
+        );
+
+        let code = authorization_code(&client, &authorize).await;
+        let response = exchange(&client, &format!("&code={code}")).await;
+        assert_eq!(
+            response.status(),
+            Status::BadRequest,
+            "A code requested with a redirect_uri must be redeemed with it."
+        );
+
+        let code = authorization_code(&client, &authorize).await;
+        let response = exchange(
+            &client,
+            &format!("&code={code}&redirect_uri=https%3A%2F%2Fapp.test%2Fother"),
+        )
+        .await;
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let code = authorization_code(&client, &authorize).await;
+        let response = exchange(
+            &client,
+            &format!("&code={code}&redirect_uri=https%3A%2F%2Fapp.test%2Fcallback"),
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_authorize_csrf() {
+        let client = setup_client().await;
+        let authorize = format!(
+            "/authorize?response_type=code&client_id=app\
+             &code_challenge={CHALLENGE}&code_challenge_method=S256"
+        );
+        let csrf_token = login_form(&client, &authorize).await;
+
+        let response = login(
+            &client,
+            &authorize,
+            "username=alice&password=password123".to_string(),
+        )
+        .await;
+        assert_eq!(
+            response.status(),
+            Status::Forbidden,
+            "A login posted without the form's token must be refused."
+        );
+
+        let response = login(
+            &client,
+            &authorize,
+            "username=alice&password=password123&csrf_token=forged".to_string(),
+        )
+        .await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        // A token from the form, posted from a browser without the form's cookie.
+        let other = setup_client().await;
+        let response = login(
+            &other,
+            &authorize,
+            format!("username=alice&password=password123&csrf_token={csrf_token}"),
+        )
+        .await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        // Each rendering of the form replaces the cookie, so use the latest token.
+        let csrf_token = login_form(&client, &authorize).await;
+        let response = login(
+            &client,
+            &authorize,
+            format!("username=alice&password=password123&csrf_token={csrf_token}"),
+        )
+        .await;
+        assert_eq!(response.status(), Status::SeeOther);
+    }
+
+    #[tokio::test]
+    async fn test_authorize_rate_limit() {
+        let client = setup_client_with(RateLimits::new(
+            [(
+                policy::AUTHORIZE,
+                RateLimitPolicy::new(2, 60, &[KeyPart::Ip]),
+            )],
+            Vec::new(),
+        ))
+        .await;
+        let authorize = format!(
+            "/authorize?response_type=code&client_id=app\
+             &code_challenge={CHALLENGE}&code_challenge_method=S256"
+        );
+        let csrf_token = login_form(&client, &authorize).await;
+        let body = format!("username=alice&password=password123&csrf_token={csrf_token}");
+
+        let response = login(&client, &authorize, body.clone()).await;
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(response.headers().get_one("RateLimit-Limit"), Some("2"));
+        assert_eq!(
+            response.headers().get_one("RateLimit-Remaining"),
+            Some("1"),
+            "A request must be counted once."
+        );
+        assert_eq!(
+            login(&client, &authorize, body.clone()).await.status(),
+            Status::SeeOther
+        );
+
+        let response = login(&client, &authorize, body).await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert_eq!(response.headers().get_one("RateLimit-Remaining"), Some("0"));
+        let retry_after: u64 = response
+            .headers()
+            .get_one("Retry-After")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(
+            (1..=30).contains(&retry_after),
+            "One request is refilled every 30 seconds."
+        );
+        assert!(response.headers().get_one("RateLimit-Reset").is_some());
+    }
+}
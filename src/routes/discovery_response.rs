@@ -0,0 +1,182 @@
+use crate::auth::client::registered_scopes;
+use crate::auth::AuthError;
+use crate::crypto::TokenPolicy;
+use crate::rotation::RotationPolicy;
+use rocket::serde::json::Json;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// The scopes defined by OpenID Connect that this server understands.
+const OPENID_SCOPES: [&str; 3] = ["openid", "profile", "email"];
+
+/// The claims that tokens issued by this server can carry.
+const CLAIMS_SUPPORTED: [&str; 16] = [
+    "iss",
+    "sub",
+    "aud",
+    "exp",
+    "nbf",
+    "iat",
+    "jti",
+    "auth_time",
+    "nonce",
+    "at_hash",
+    "preferred_username",
+    "updated_at",
+    "email",
+    "email_verified",
+    "scope",
+    "roles",
+];
+
+/// An OpenID Connect discovery document, as described in OpenID Connect Discovery 1.0
+/// section 3 and RFC 8414.
+#[derive(Serialize, Debug)]
+pub struct OpenIdConfiguration {
+    /// The `iss` claim of every token this server issues.
+    pub issuer: String,
+    /// The URL of `/authorize`.
+    pub authorization_endpoint: String,
+    /// The URL of `/token`.
+    pub token_endpoint: String,
+    /// The URL of `/userinfo`.
+    pub userinfo_endpoint: String,
+    /// The URL of `/introspect`.
+    pub introspection_endpoint: String,
+    /// The URL of `/revoke`.
+    pub revocation_endpoint: String,
+    /// The URL of the JWKS with the keys that sign every token.
+    pub jwks_uri: String,
+    /// Only the authorization code flow is supported.
+    pub response_types_supported: Vec<&'static str>,
+    /// The grants accepted at `/token`.
+    pub grant_types_supported: Vec<&'static str>,
+    /// Every client sees the same `sub` for a user.
+    pub subject_types_supported: Vec<&'static str>,
+    /// The algorithms in `SIGNING_ALGORITHMS`, which sign ID tokens as well as access
+    /// tokens.
+    pub id_token_signing_alg_values_supported: Vec<&'static str>,
+    /// PKCE is mandatory, and only with S256.
+    pub code_challenge_methods_supported: Vec<&'static str>,
+    /// How clients can authenticate at `/token`; public clients use `none`.
+    pub token_endpoint_auth_methods_supported: Vec<&'static str>,
+    /// The OpenID Connect scopes, and every scope a registered client may request.
+    pub scopes_supported: Vec<String>,
+    /// The claims that tokens can carry.
+    pub claims_supported: Vec<&'static str>,
+}
+
+impl OpenIdConfiguration {
+    /// Describes a server with the given configuration and registered scopes.
+    pub fn new(
+        token_policy: &TokenPolicy,
+        rotation_policy: &RotationPolicy,
+        client_scopes: Vec<String>,
+    ) -> Self {
+        let issuer = token_policy.issuer.trim_end_matches('/');
+        let endpoint = |path: &str| format!("{issuer}{path}");
+
+        let mut scopes_supported: Vec<String> = OPENID_SCOPES
+            .iter()
+            .map(|scope| scope.to_string())
+            .collect();
+        for scope in client_scopes {
+            if !scopes_supported.contains(&scope) {
+                scopes_supported.push(scope);
+            }
+        }
+
+        Self {
+            issuer: token_policy.issuer.clone(),
+            authorization_endpoint: endpoint("/authorize"),
+            token_endpoint: endpoint("/token"),
+            userinfo_endpoint: endpoint("/userinfo"),
+            introspection_endpoint: endpoint("/introspect"),
+            revocation_endpoint: endpoint("/revoke"),
+            jwks_uri: endpoint("/.well-known/jwks.json"),
+            response_types_supported: vec!["code"],
+            grant_types_supported: vec![
+                "authorization_code",
+                "refresh_token",
+                "client_credentials",
+            ],
+            subject_types_supported: vec!["public"],
+            id_token_signing_alg_values_supported: rotation_policy
+                .algorithms
+                .iter()
+                .map(|alg| alg.as_str())
+                .collect(),
+            code_challenge_methods_supported: vec!["S256"],
+            token_endpoint_auth_methods_supported: vec![
+                "client_secret_basic",
+                "client_secret_post",
+                "none",
+            ],
+            scopes_supported,
+            claims_supported: CLAIMS_SUPPORTED.to_vec(),
+        }
+    }
+}
+
+/// Provides the OpenID Connect discovery document.
+///
+/// The document is built on every request from the `TokenPolicy`, the configured
+/// signing algorithms and the registered clients, so it always describes what the
+/// server currently supports. Endpoint URLs are relative to `ISSUER`, which must
+/// therefore be the public URL of this server.
+///
+/// # Errors
+///
+/// Responds with `500 Internal Server Error` if the registered clients cannot be read.
+#[get("/.well-known/openid-configuration")]
+pub async fn openid_configuration(
+    db_pool: &rocket::State<SqlitePool>,
+    token_policy: &rocket::State<TokenPolicy>,
+    rotation_policy: &rocket::State<RotationPolicy>,
+) -> Result<Json<OpenIdConfiguration>, AuthError> {
+    let client_scopes = registered_scopes(db_pool).await?;
+
+    Ok(Json(OpenIdConfiguration::new(
+        token_policy,
+        rotation_policy,
+        client_scopes,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SigningAlgorithm;
+
+    #[test]
+    fn test_openid_configuration() {
+        let token_policy = TokenPolicy {
+            issuer: "https://auth.example.com/".to_string(),
+            ..Default::default()
+        };
+        let rotation_policy = RotationPolicy {
+            algorithms: vec![SigningAlgorithm::ES256, SigningAlgorithm::EdDSA],
+            ..Default::default()
+        };
+
+        let config = OpenIdConfiguration::new(
+            &token_policy,
+            &rotation_policy,
+            vec!["email".to_string(), "read:keys".to_string()],
+        );
+
+        assert_eq!(config.issuer, "https://auth.example.com/");
+        assert_eq!(
+            config.jwks_uri,
+            "https://auth.example.com/.well-known/jwks.json"
+        );
+        assert_eq!(
+            config.id_token_signing_alg_values_supported,
+            vec!["ES256", "EdDSA"]
+        );
+        assert_eq!(
+            config.scopes_supported,
+            vec!["openid", "profile", "email", "read:keys"]
+        );
+    }
+}
@@ -1,5 +1,6 @@
-use rocket::http::Status;
+use rocket::http::{Header, Status};
 use rocket::response::status;
+use rocket::serde::json::{json, Json, Value};
 
 #[doc(hidden)]
 #[catch(404)]
@@ -35,3 +36,66 @@ pub fn not_found() -> status::NotFound<&'static str> {
 pub fn method_not_allowed() -> status::Custom<&'static str> {
     status::Custom(Status::MethodNotAllowed, "405: METHOD NOT ALLOWED")
 }
+
+/// Catcher for 401 Unauthorized errors.
+///
+/// This catcher is triggered when a request guard rejects the caller's credentials,
+/// such as a missing or wrong admin API key.
+///
+/// # Returns
+///
+/// Returns a `status::Custom` response with a "401: UNAUTHORIZED" message.
+#[catch(401)]
+pub fn unauthorized() -> status::Custom<&'static str> {
+    status::Custom(Status::Unauthorized, "401: UNAUTHORIZED")
+}
+
+/// Catcher for 403 Forbidden errors.
+///
+/// This catcher is triggered when the caller's credentials are valid but do not grant
+/// access, such as an access token without the `admin` scope.
+///
+/// # Returns
+///
+/// Returns a `status::Custom` response with a "403: FORBIDDEN" message.
+#[catch(403)]
+pub fn forbidden() -> status::Custom<&'static str> {
+    status::Custom(Status::Forbidden, "403: FORBIDDEN")
+}
+
+/// Catcher for 429 Too Many Requests errors.
+///
+/// This catcher is triggered when a client exceeds its rate limit. The `Retry-After`
+/// and `RateLimit-*` headers added by the rate limiter tell it when to try again.
+///
+/// # Returns
+///
+/// Returns a `status::Custom` response with a "429: TOO MANY REQUESTS" message.
+#[catch(429)]
+pub fn too_many_requests() -> status::Custom<&'static str> {
+    status::Custom(Status::TooManyRequests, "429: TOO MANY REQUESTS")
+}
+
+/// A `401 Unauthorized` response asking the client to authenticate with HTTP Basic.
+#[derive(Responder)]
+#[response(status = 401)]
+pub struct ClientChallenge {
+    body: Json<Value>,
+    challenge: Header<'static>,
+}
+
+/// Catcher for 401 Unauthorized errors on endpoints that require client credentials.
+///
+/// Follows RFC 6749 section 5.2: the body is `{"error": "invalid_client"}` and the
+/// `WWW-Authenticate` header tells the client to use HTTP Basic.
+///
+/// # Returns
+///
+/// Returns a `ClientChallenge` response.
+#[catch(401)]
+pub fn invalid_client() -> ClientChallenge {
+    ClientChallenge {
+        body: Json(json!({ "error": "invalid_client" })),
+        challenge: Header::new("WWW-Authenticate", "Basic realm=\"jwks_server\""),
+    }
+}
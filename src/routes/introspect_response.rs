@@ -0,0 +1,160 @@
+use crate::auth::ClientCredentials;
+use crate::crypto::{Claims, KeyCipher, TokenError, TokenPolicy};
+use crate::revocation::validate_token;
+use rocket::form::Form;
+use rocket::serde::json::Json;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// An RFC 7662 introspection request.
+///
+/// Any `token_type_hint` is ignored, since every token this server issues is a JWT.
+#[derive(Debug, FromForm)]
+pub struct IntrospectDTO {
+    /// The token to introspect.
+    pub token: String,
+}
+
+/// An RFC 7662 introspection response.
+///
+/// Inactive tokens are described only by `"active": false`, so that callers learn
+/// nothing about why a token was rejected.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct IntrospectionResponse {
+    /// Whether the token is currently active.
+    pub active: bool,
+    /// The space separated scopes granted to the token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// The client the token was issued to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// The type of the token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    /// The expiration time of the token as a timestamp.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    /// The time at which the token was issued, as a timestamp.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    /// The time before which the token is not valid, as a timestamp.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    /// The subject of the token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    /// The intended audience of the token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// The issuer of the token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// The unique identifier of the token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+}
+
+impl IntrospectionResponse {
+    /// The response for a token that is not active.
+    pub fn inactive() -> Self {
+        Self::default()
+    }
+
+    /// The response for an active token with the given claims.
+    ///
+    /// `scope` and `client_id` are read from the claims of the same name; tokens
+    /// without a `client_id` claim report their audience as the client.
+    pub fn active(claims: Claims) -> Self {
+        let extra_string = |name: &str| {
+            claims
+                .extra
+                .get(name)
+                .and_then(|value| value.as_str())
+                .map(str::to_string)
+        };
+
+        Self {
+            active: true,
+            scope: extra_string("scope"),
+            client_id: extra_string("client_id").or_else(|| claims.aud.clone()),
+            token_type: Some("Bearer".to_string()),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            nbf: Some(claims.nbf),
+            sub: Some(claims.sub),
+            aud: claims.aud,
+            iss: Some(claims.iss),
+            jti: Some(claims.jti),
+        }
+    }
+}
+
+/// Introspects a token for a resource server, as described in RFC 7662.
+///
+/// The caller must authenticate as a registered client with HTTP Basic. The token is
+/// active if it was signed by a published key of this server with that key's
+/// algorithm, is within its `nbf`/`exp` window, was issued by `ISSUER`, and has not
+/// been revoked.
+///
+/// # Errors
+///
+/// Responds with `401 Unauthorized` if the client credentials are missing or wrong,
+/// and with `500 Internal Server Error` if the keys cannot be loaded. Tokens that
+/// fail validation are not errors; they are reported as `{"active": false}`.
+#[post("/introspect", data = "<request>")]
+pub async fn introspect(
+    db_pool: &rocket::State<SqlitePool>,
+    key_cipher: &rocket::State<KeyCipher>,
+    token_policy: &rocket::State<TokenPolicy>,
+    client: ClientCredentials,
+    request: Form<IntrospectDTO>,
+) -> Result<Json<IntrospectionResponse>, TokenError> {
+    let response =
+        match validate_token(db_pool, key_cipher, token_policy, &request.token, None).await {
+            Ok(claims) => IntrospectionResponse::active(claims),
+            Err(TokenError::KeyError(err)) => return Err(err.into()),
+            Err(err) => {
+                info!(
+                    "Client '{}' introspected an inactive token: {}",
+                    client.0.client_id, err
+                );
+                IntrospectionResponse::inactive()
+            }
+        };
+
+    Ok(Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_introspection_response_shape() {
+        assert_eq!(
+            serde_json::to_value(IntrospectionResponse::inactive()).unwrap(),
+            json!({ "active": false })
+        );
+
+        let claims: Claims = serde_json::from_value(json!({
+            "iss": "http://localhost:8080",
+            "sub": "42",
+            "aud": "client-a",
+            "exp": 2_000,
+            "nbf": 1_000,
+            "iat": 1_000,
+            "jti": "abc",
+            "scope": "read write",
+        }))
+        .unwrap();
+        let response = serde_json::to_value(IntrospectionResponse::active(claims)).unwrap();
+
+        assert_eq!(response["active"], true);
+        assert_eq!(response["scope"], "read write");
+        assert_eq!(response["client_id"], "client-a");
+        assert_eq!(response["token_type"], "Bearer");
+        assert_eq!(response["sub"], "42");
+    }
+}
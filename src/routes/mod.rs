@@ -1,8 +1,40 @@
+pub mod admin_response;
+pub use admin_response::{
+    admin_create_user, admin_delete_client, admin_delete_user, admin_disable_user,
+    admin_enable_user, admin_get_client, admin_get_user, admin_list_clients, admin_list_keys,
+    admin_list_users, admin_put_client, admin_reset_password, admin_revoke_key, admin_rotate_keys,
+    admin_set_roles, admin_unlock_user,
+};
+
 pub mod auth_response;
 pub use auth_response::{auth, get_jwks, register};
 
+pub mod authorize_response;
+pub use authorize_response::{authorize, authorize_form};
+
+pub mod discovery_response;
+pub use discovery_response::openid_configuration;
+
 pub mod error_response;
-pub use error_response::{method_not_allowed, not_found, not_found_to_method_not_allow};
+pub use error_response::{
+    forbidden, invalid_client, method_not_allowed, not_found, not_found_to_method_not_allow,
+    too_many_requests, unauthorized,
+};
+
+pub mod introspect_response;
+pub use introspect_response::introspect;
 
 pub mod index_response;
 pub use index_response::index;
+
+pub mod revoke_response;
+pub use revoke_response::{admin_revoke, revoke};
+
+pub mod token_response;
+pub use token_response::{token, TokenResponse};
+
+pub mod userinfo_response;
+pub use userinfo_response::{userinfo, userinfo_post};
+
+pub mod verify_response;
+pub use verify_response::verify;
@@ -0,0 +1,241 @@
+use crate::auth::refresh::revoke_refresh_token;
+use crate::auth::{Admin, ClientCredentials};
+use crate::crypto::key_pair::unix_timestamp;
+use crate::crypto::{Claims, KeyCipher, TokenError, TokenPolicy};
+use crate::revocation::{revoke_key, revoke_subject, revoke_token, validate_token};
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+/// An RFC 7009 revocation request.
+///
+/// Any `token_type_hint` is ignored, since refresh tokens and JWTs are easily told apart.
+#[derive(Debug, FromForm)]
+pub struct RevokeDTO {
+    /// The token to revoke.
+    pub token: String,
+}
+
+/// An administrative request to revoke every token for a subject or a signing key.
+///
+/// Exactly one of the fields must be set.
+#[derive(Debug, Deserialize)]
+pub struct AdminRevokeDTO {
+    /// Revokes every token issued to this subject so far.
+    pub sub: Option<String>,
+    /// Revokes every token signed by this key, and the key itself.
+    pub kid: Option<String>,
+}
+
+/// The client a token was issued to: the `client_id` claim of a `client_credentials`
+/// token, whose `aud` is the resource it was requested for, or else the `aud` of a
+/// user's token.
+fn token_client(claims: &Claims) -> Option<&str> {
+    claims
+        .extra
+        .get("client_id")
+        .and_then(|client_id| client_id.as_str())
+        .or(claims.aud.as_deref())
+}
+
+/// Revokes a token on behalf of the client it was issued to, as described in RFC 7009.
+///
+/// The caller must authenticate as a registered client with HTTP Basic. The token can
+/// be an access token or a refresh token; revoking a refresh token also revokes every
+/// other refresh token from the same login. Tokens issued to another client, or to no
+/// client at all, are left alone. As the RFC requires, the response is
+/// `200 OK` whether or not anything was revoked, so invalid, expired and already
+/// revoked tokens are not errors.
+///
+/// # Errors
+///
+/// Responds with `401 Unauthorized` if the client credentials are missing or wrong,
+/// and with `500 Internal Server Error` if the keys or revocation table cannot be read.
+#[post("/revoke", data = "<request>")]
+pub async fn revoke(
+    db_pool: &rocket::State<SqlitePool>,
+    key_cipher: &rocket::State<KeyCipher>,
+    token_policy: &rocket::State<TokenPolicy>,
+    client: ClientCredentials,
+    request: Form<RevokeDTO>,
+) -> Result<Status, TokenError> {
+    let client_id = &client.0.client_id;
+
+    if revoke_refresh_token(db_pool, &request.token, client_id).await? {
+        info!("Client '{}' revoked a refresh token", client_id);
+        return Ok(Status::Ok);
+    }
+
+    match validate_token(db_pool, key_cipher, token_policy, &request.token, None).await {
+        Ok(claims) if token_client(&claims) != Some(client_id.as_str()) => {
+            info!(
+                "Client '{}' tried to revoke another client's token",
+                client_id
+            );
+        }
+        Ok(claims) => revoke_token(db_pool, &claims.jti, claims.exp, token_policy.leeway).await?,
+        Err(TokenError::KeyError(err)) => return Err(err.into()),
+        Err(err) => info!("Client '{}' revoked an invalid token: {}", client_id, err),
+    }
+
+    Ok(Status::Ok)
+}
+
+/// Revokes every token issued to a subject, or signed by a key.
+///
+/// The caller must present `ADMIN_API_KEY`, or an access token granted the `admin`
+/// scope, as a bearer token; see [`Admin`]. Revoking a subject rejects every token
+/// issued to it up to now, while it can still log in again. Revoking a key withdraws
+/// it from the JWKS and rejects every token it signed.
+///
+/// # Errors
+///
+/// Responds with `401 Unauthorized` or `403 Forbidden` without admin access, with
+/// `400 Bad Request` unless exactly one of `sub` and `kid` is given, and with
+/// `404 Not Found` for an unknown `kid`.
+#[post("/admin/revoke", data = "<request>")]
+pub async fn admin_revoke(
+    db_pool: &rocket::State<SqlitePool>,
+    _admin: Admin,
+    request: Json<AdminRevokeDTO>,
+) -> Result<Status, TokenError> {
+    match (&request.sub, &request.kid) {
+        (Some(sub), None) => {
+            revoke_subject(db_pool, sub, unix_timestamp()).await?;
+            info!("Revoked every token for subject '{}'", sub);
+            Ok(Status::NoContent)
+        }
+        (None, Some(kid)) => {
+            if !revoke_key(db_pool, kid).await? {
+                return Ok(Status::NotFound);
+            }
+            info!("Revoked key '{}' and every token it signed", kid);
+            Ok(Status::NoContent)
+        }
+        _ => Ok(Status::BadRequest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::client::{create_client, ClientRegistration};
+    use crate::auth::{create_user, AdminApiKey, LockoutPolicy};
+    use crate::crypto::envelope::MasterKey;
+    use crate::crypto::key_pair::unix_timestamp;
+    use crate::crypto::{KeyCipher, SigningAlgorithm, TokenPolicy};
+    use crate::net::TrustedProxies;
+    use crate::rate_limit::RateLimits;
+    use crate::rotation::{rotate_keys, RotationPolicy};
+    use base64::engine::general_purpose;
+    use base64::Engine;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use serde_json::{json, Value};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[tokio::test]
+    async fn test_revoke_client_credentials_token() {
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        create_user(&db_pool, "alice", "alice@example.com", "password123")
+            .await
+            .unwrap();
+        for client_id in ["machine", "other"] {
+            create_client(
+                &db_pool,
+                &ClientRegistration {
+                    audiences: vec!["https://api.test".to_string()],
+                    ..ClientRegistration::new(client_id, "s3cret")
+                },
+            )
+            .await
+            .unwrap();
+        }
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        let rotation_policy = RotationPolicy {
+            algorithms: vec![SigningAlgorithm::ES256],
+            ..Default::default()
+        };
+        rotate_keys(&db_pool, &key_cipher, &rotation_policy, unix_timestamp())
+            .await
+            .unwrap();
+        let rocket = crate::build_rocket(
+            db_pool,
+            key_cipher,
+            rotation_policy,
+            TokenPolicy::default(),
+            LockoutPolicy::default(),
+            AdminApiKey::default(),
+            RateLimits::default(),
+            TrustedProxies::default(),
+        );
+        let client = Client::tracked(rocket).await.unwrap();
+        let remote = "127.0.0.1:8000".parse().unwrap();
+        let basic = |client_id: &str| {
+            let credentials = general_purpose::STANDARD.encode(format!("{client_id}:s3cret"));
+            Header::new("Authorization", format!("Basic {credentials}"))
+        };
+        let revoke = |client_id: &str, token: &str| {
+            client
+                .post("/revoke")
+                .remote(remote)
+                .header(ContentType::Form)
+                .header(basic(client_id))
+                .body(format!("token={token}"))
+                .dispatch()
+        };
+        let verify = |token: &str| {
+            client
+                .post("/verify")
+                .remote(remote)
+                .json(&json!({ "token": token }))
+                .dispatch()
+        };
+
+        let response = client
+            .post("/token")
+            .remote(remote)
+            .header(ContentType::Form)
+            .header(basic("machine"))
+            .body("grant_type=client_credentials")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let tokens: Value = response.into_json().await.unwrap();
+        let token = tokens["access_token"].as_str().unwrap();
+
+        assert_eq!(revoke("other", token).await.status(), Status::Ok);
+        assert_eq!(
+            verify(token).await.status(),
+            Status::Ok,
+            "Clients must not revoke each other's tokens."
+        );
+
+        let response = client
+            .post("/auth")
+            .remote(remote)
+            .header(ContentType::JSON)
+            .body(r#"{"username": "alice", "password": "password123"}"#)
+            .dispatch()
+            .await;
+        let user_token = response.into_string().await.unwrap();
+        assert_eq!(revoke("other", &user_token).await.status(), Status::Ok);
+        assert_eq!(
+            verify(&user_token).await.status(),
+            Status::Ok,
+            "Tokens issued to no client must not be revocable by any client."
+        );
+
+        assert_eq!(revoke("machine", token).await.status(), Status::Ok);
+        let response = verify(token).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let error: Value = response.into_json().await.unwrap();
+        assert_eq!(error["error"], "token has been revoked");
+    }
+}
@@ -0,0 +1,681 @@
+use crate::auth::client::{identify_client, parse_basic_credentials};
+use crate::auth::code::redeem_authorization_code;
+use crate::auth::refresh::{find_refresh_token, issue_refresh_token, rotate_refresh_token};
+use crate::auth::roles::grant_user_scopes;
+use crate::auth::{
+    find_user_by_id, has_scope, id_token_claims, log_token_request, sign_id_token, user_claims,
+    BasicCredentials, ClientIp, OAuthError, RequestingClient,
+};
+use crate::crypto::key_pair::unix_timestamp;
+use crate::crypto::{
+    Claims, ClaimsBuilder, CryptoError, Jwt, KeyCipher, KeyPair, SigningAlgorithm, TokenPolicy,
+};
+use crate::db::load_key_pairs;
+use crate::rate_limit::{RateLimitSubject, RateLimitedBody};
+use crate::rotation::RotationPolicy;
+use rocket::form::Form;
+use rocket::request::Request;
+use rocket::serde::json::Json;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// A request to the OAuth 2.0 token endpoint.
+#[derive(Debug, FromForm)]
+pub struct TokenRequest {
+    /// The grant being exchanged for a token, such as `client_credentials`.
+    pub grant_type: String,
+    /// The refresh token, for the `refresh_token` grant.
+    pub refresh_token: Option<String>,
+    /// The authorization code, for the `authorization_code` grant.
+    pub code: Option<String>,
+    /// The redirect URI the code was sent to, for the `authorization_code` grant.
+    pub redirect_uri: Option<String>,
+    /// The PKCE verifier, for the `authorization_code` grant.
+    pub code_verifier: Option<String>,
+    /// The space separated scopes requested, for the `client_credentials` grant.
+    pub scope: Option<String>,
+    /// The audience the token is requested for, for the `client_credentials` grant.
+    pub audience: Option<String>,
+    /// The client making the request, for clients that do not use HTTP Basic.
+    pub client_id: Option<String>,
+    /// The client's secret, for clients that use `client_secret_post`.
+    pub client_secret: Option<String>,
+}
+
+impl RateLimitSubject for TokenRequest {
+    /// The client named by HTTP Basic credentials, or else by `client_id`.
+    fn client_id(&self, request: &Request<'_>) -> Option<String> {
+        match request
+            .headers()
+            .get_one("Authorization")
+            .and_then(parse_basic_credentials)
+        {
+            Some((client_id, _)) => Some(client_id),
+            None => self.client_id.clone(),
+        }
+    }
+}
+
+/// A successful response from the token endpoint, as described in RFC 6749
+/// section 5.1.
+#[derive(Serialize, Debug)]
+pub struct TokenResponse {
+    /// The access token, a JWT.
+    pub access_token: String,
+    /// The type of the access token, which is always `Bearer`.
+    pub token_type: String,
+    /// How many seconds the access token is valid for.
+    pub expires_in: u64,
+    /// A refresh token that can be exchanged for a new access token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// The space separated scopes granted, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// An OpenID Connect ID token, for logins with the `openid` scope.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+impl TokenResponse {
+    /// The response for a bearer `access_token` with the given claims.
+    pub fn bearer(access_token: String, claims: &Claims, now: u64) -> Self {
+        Self {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: claims.exp.saturating_sub(now),
+            refresh_token: None,
+            id_token: None,
+            scope: claims
+                .extra
+                .get("scope")
+                .and_then(|scope| scope.as_str())
+                .map(str::to_string),
+        }
+    }
+
+    /// Adds a refresh token to the response.
+    pub fn with_refresh_token(mut self, refresh_token: String) -> Self {
+        self.refresh_token = Some(refresh_token);
+        self
+    }
+
+    /// Adds an ID token to the response.
+    pub fn with_id_token(mut self, id_token: Option<String>) -> Self {
+        self.id_token = id_token;
+        self
+    }
+}
+
+/// Finds a key that can sign new tokens with `algorithm`.
+fn signing_key(
+    key_pairs: &[KeyPair],
+    algorithm: SigningAlgorithm,
+) -> Result<&KeyPair, CryptoError> {
+    key_pairs
+        .iter()
+        .find(|kp| kp.algorithm == algorithm && kp.state.can_sign())
+        .ok_or(CryptoError::TokenCreationError)
+}
+
+/// Reads a parameter that the grant requires.
+fn required<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str, OAuthError> {
+    value
+        .as_deref()
+        .ok_or_else(|| OAuthError::InvalidRequest(format!("{name} is required")))
+}
+
+/// The OAuth 2.0 token endpoint.
+///
+/// Supports three grants:
+///
+/// * `authorization_code` exchanges a code from `/authorize` for an access token and
+///   a refresh token. The client must present the PKCE `code_verifier` for the
+///   code's challenge, and any `redirect_uri` it sends must be the one the code was
+///   sent to; it must send one if the authorization request did. Codes can only be
+///   exchanged once, by the client they were issued to. If the `openid` scope was granted, the response also carries an
+///   OpenID Connect `id_token`.
+/// * `client_credentials` issues a token to a registered client itself, for
+///   machine-to-machine calls. The token's `sub` and `client_id` claims are the
+///   client, `scope` is the requested scopes (by default all the client may request),
+///   and `aud` is the requested `audience` (by default the client's first). Its
+///   lifetime is the client's own, if it has one.
+/// * `refresh_token` exchanges a refresh token from `/auth` for a new access token
+///   and a new refresh token. Each refresh token can be used once; presenting one
+///   again revokes every refresh token descended from the same login. Tokens issued
+///   to a registered client can only be refreshed by that client. Logins with the
+///   `openid` scope get a new `id_token` too, with the original `auth_time`.
+///
+/// Access tokens for users carry the user's `roles`, and a `scope` limited to what
+/// those roles allow, which is checked again on every exchange.
+///
+/// Registered clients authenticate with HTTP Basic or with `client_id` and
+/// `client_secret` in the body; public clients send only their `client_id`. Every
+/// token issued is logged in `auth_logs`.
+///
+/// # Errors
+///
+/// Responds with `400 Bad Request` and a JSON body such as
+/// `{"error": "invalid_grant"}` as described in RFC 6749 section 5.2, or with
+/// `401 Unauthorized` and `{"error": "invalid_client"}` if client authentication
+/// fails. Responds with `429 Too Many Requests` if the `token` rate limit policy
+/// refuses the request.
+#[post("/token", data = "<request>")]
+pub async fn token(
+    db_pool: &rocket::State<SqlitePool>,
+    key_cipher: &rocket::State<KeyCipher>,
+    rotation_policy: &rocket::State<RotationPolicy>,
+    token_policy: &rocket::State<TokenPolicy>,
+    request_ip: ClientIp,
+    basic: BasicCredentials,
+    request: RateLimitedBody<Form<TokenRequest>>,
+) -> Result<Json<TokenResponse>, OAuthError> {
+    let client = identify_client(
+        db_pool,
+        &basic,
+        request.client_id.as_deref(),
+        request.client_secret.as_deref(),
+    )
+    .await?;
+    let now = unix_timestamp();
+
+    match request.grant_type.as_str() {
+        "client_credentials" => {
+            let client = match client {
+                RequestingClient::Confidential(client) => client,
+                RequestingClient::Public(_) => return Err(OAuthError::UnauthorizedClient),
+                RequestingClient::Anonymous => return Err(OAuthError::InvalidClient),
+            };
+            let scope = client.grant_scopes(request.scope.as_deref())?;
+            let audience = client.grant_audience(request.audience.as_deref())?;
+
+            let key_pairs = load_key_pairs(db_pool, key_cipher).await?;
+            let key_pair = signing_key(&key_pairs, rotation_policy.default_algorithm())?;
+
+            log_token_request(db_pool, &request_ip.0, None, Some(&client.client_id)).await?;
+            info!("Issuing token for client '{}'", client.client_id);
+
+            let mut claims = ClaimsBuilder::new(token_policy, &client.client_id)
+                .claim("client_id", client.client_id.as_str());
+            if let Some(audience) = &audience {
+                claims = claims.audience(audience);
+            }
+            if !scope.is_empty() {
+                claims = claims.claim("scope", scope);
+            }
+            if let Some(ttl) = client.token_ttl {
+                claims = claims.ttl(ttl as u64);
+            }
+            let claims = claims.build(key_pair, now);
+
+            Ok(Json(TokenResponse::bearer(
+                Jwt::from(key_pair, &claims)?,
+                &claims,
+                now,
+            )))
+        }
+        "authorization_code" => {
+            let client_id = client.client_id().ok_or(OAuthError::InvalidClient)?;
+            let code = redeem_authorization_code(
+                db_pool,
+                required(&request.code, "code")?,
+                client_id,
+                request.redirect_uri.as_deref(),
+                required(&request.code_verifier, "code_verifier")?,
+                now,
+            )
+            .await?;
+            let user = find_user_by_id(db_pool, code.user_id)
+                .await?
+                .filter(|user| !user.disabled)
+                .ok_or(OAuthError::InvalidGrant)?;
+
+            let key_pairs = load_key_pairs(db_pool, key_cipher).await?;
+            let key_pair = signing_key(&key_pairs, rotation_policy.default_algorithm())?;
+
+            log_token_request(db_pool, &request_ip.0, Some(code.user_id), Some(client_id)).await?;
+            info!(
+                "Issuing token for user '{}' ({}) to client '{}'",
+                user.username, code.user_id, client_id
+            );
+
+            // Roles are looked up afresh, and any scope the user has lost since logging
+            // in is dropped.
+            let grant = grant_user_scopes(
+                db_pool,
+                code.user_id,
+                Some(code.scope.as_deref().unwrap_or("")),
+            )
+            .await?;
+            let claims = user_claims(token_policy, code.user_id, &user, Some(client_id), &grant)
+                .build(key_pair, now);
+            let access_token = Jwt::from(key_pair, &claims)?;
+            let id_token = if has_scope(grant.scope(), "openid") {
+                let id_claims = id_token_claims(
+                    token_policy,
+                    code.user_id,
+                    &user,
+                    client_id,
+                    grant.scope(),
+                    code.auth_time as u64,
+                    code.nonce.as_deref(),
+                );
+                Some(sign_id_token(key_pair, id_claims, &access_token, now)?)
+            } else {
+                None
+            };
+            let refresh_token = issue_refresh_token(
+                db_pool,
+                code.user_id,
+                Some(client_id),
+                grant.scope(),
+                code.auth_time as u64,
+                None,
+                now.saturating_add(token_policy.refresh_ttl),
+            )
+            .await?;
+
+            Ok(Json(
+                TokenResponse::bearer(access_token, &claims, now)
+                    .with_refresh_token(refresh_token)
+                    .with_id_token(id_token),
+            ))
+        }
+        "refresh_token" => {
+            let refresh_token = required(&request.refresh_token, "refresh_token")?;
+
+            // Everything that can fail is done before the presented token is used up,
+            // so that a failure leaves the client with a token it can try again.
+            let key_pairs = load_key_pairs(db_pool, key_cipher).await?;
+            let key_pair = signing_key(&key_pairs, rotation_policy.default_algorithm())?;
+            let presented = find_refresh_token(db_pool, refresh_token)
+                .await?
+                .ok_or(OAuthError::InvalidGrant)?;
+            let user = find_user_by_id(db_pool, presented.user_id)
+                .await?
+                .filter(|user| !user.disabled)
+                .ok_or(OAuthError::InvalidGrant)?;
+            let grant = grant_user_scopes(
+                db_pool,
+                presented.user_id,
+                Some(presented.scope.as_deref().unwrap_or("")),
+            )
+            .await?;
+
+            let (record, refresh_token) = rotate_refresh_token(
+                db_pool,
+                refresh_token,
+                client.client_id(),
+                now,
+                now.saturating_add(token_policy.refresh_ttl),
+            )
+            .await?;
+
+            log_token_request(
+                db_pool,
+                &request_ip.0,
+                Some(record.user_id),
+                client.client_id(),
+            )
+            .await?;
+            info!(
+                "Refreshing token for user '{}' ({})",
+                user.username, record.user_id
+            );
+
+            let claims = user_claims(
+                token_policy,
+                record.user_id,
+                &user,
+                client.client_id(),
+                &grant,
+            )
+            .build(key_pair, now);
+            let access_token = Jwt::from(key_pair, &claims)?;
+            let id_token = match client.client_id() {
+                Some(client_id) if has_scope(grant.scope(), "openid") => {
+                    let id_claims = id_token_claims(
+                        token_policy,
+                        record.user_id,
+                        &user,
+                        client_id,
+                        grant.scope(),
+                        record.auth_time as u64,
+                        None,
+                    );
+                    Some(sign_id_token(key_pair, id_claims, &access_token, now)?)
+                }
+                _ => None,
+            };
+
+            Ok(Json(
+                TokenResponse::bearer(access_token, &claims, now)
+                    .with_refresh_token(refresh_token)
+                    .with_id_token(id_token),
+            ))
+        }
+        _ => Err(OAuthError::UnsupportedGrantType),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::client::{create_client, ClientRegistration};
+    use crate::auth::{create_user, AdminApiKey, LockoutPolicy};
+    use crate::crypto::envelope::MasterKey;
+    use crate::crypto::key_pair::unix_timestamp;
+    use crate::crypto::{KeyCipher, SigningAlgorithm, TokenPolicy};
+    use crate::net::TrustedProxies;
+    use crate::rate_limit::policy::{self, KeyPart};
+    use crate::rate_limit::{RateLimitPolicy, RateLimits};
+    use crate::revocation::revoke_key;
+    use crate::rotation::{rotate_keys, RotationPolicy};
+    use base64::engine::general_purpose;
+    use base64::Engine;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::{Client, LocalResponse};
+    use serde_json::{json, Value};
+    use sqlx::sqlite::SqlitePoolOptions;
+    use sqlx::SqlitePool;
+
+    const REMOTE: &str = "127.0.0.1:8000";
+
+    fn rotation_policy() -> RotationPolicy {
+        RotationPolicy {
+            algorithms: vec![SigningAlgorithm::ES256],
+            ..Default::default()
+        }
+    }
+
+    /// A server with the user `alice`, the confidential client `machine` and the
+    /// public client `spa`, and keys rotated in.
+    async fn setup_client() -> (Client, SqlitePool) {
+        setup_client_with(RateLimits::default()).await
+    }
+
+    async fn setup_client_with(rate_limits: RateLimits) -> (Client, SqlitePool) {
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        create_user(&db_pool, "alice", "alice@example.com", "password123")
+            .await
+            .unwrap();
+        create_client(
+            &db_pool,
+            &ClientRegistration {
+                scopes: vec!["read".to_string(), "write".to_string()],
+                audiences: vec![
+                    "https://api.test".to_string(),
+                    "https://other.test".to_string(),
+                ],
+                ..ClientRegistration::new("machine", "s3cret")
+            },
+        )
+        .await
+        .unwrap();
+        create_client(&db_pool, &ClientRegistration::new("spa", ""))
+            .await
+            .unwrap();
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        rotate_keys(&db_pool, &key_cipher, &rotation_policy(), unix_timestamp())
+            .await
+            .unwrap();
+        let rocket = crate::build_rocket(
+            db_pool.clone(),
+            key_cipher,
+            rotation_policy(),
+            TokenPolicy::default(),
+            LockoutPolicy::default(),
+            AdminApiKey::default(),
+            rate_limits,
+            TrustedProxies::default(),
+        );
+
+        (Client::tracked(rocket).await.unwrap(), db_pool)
+    }
+
+    async fn token_request<'c>(client: &'c Client, body: &str) -> LocalResponse<'c> {
+        client
+            .post("/token")
+            .remote(REMOTE.parse().unwrap())
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+            .await
+    }
+
+    async fn basic_token_request<'c>(
+        client: &'c Client,
+        credentials: &str,
+        body: &str,
+    ) -> LocalResponse<'c> {
+        let credentials = general_purpose::STANDARD.encode(credentials);
+        client
+            .post("/token")
+            .remote(REMOTE.parse().unwrap())
+            .header(ContentType::Form)
+            .header(Header::new("Authorization", format!("Basic {credentials}")))
+            .body(body)
+            .dispatch()
+            .await
+    }
+
+    /// The claims of the access token in a successful token response.
+    async fn access_claims(client: &Client, response: LocalResponse<'_>) -> Value {
+        assert_eq!(response.status(), Status::Ok);
+        let tokens: Value = response.into_json().await.unwrap();
+        let response = client
+            .post("/verify")
+            .remote(REMOTE.parse().unwrap())
+            .json(&json!({ "token": tokens["access_token"] }))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json().await.unwrap()
+    }
+
+    async fn error_code(response: LocalResponse<'_>) -> String {
+        let error: Value = response.into_json().await.unwrap();
+        error["error"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_client_credentials_authentication() {
+        let (client, _) = setup_client().await;
+
+        let response =
+            basic_token_request(&client, "machine:s3cret", "grant_type=client_credentials").await;
+        let claims = access_claims(&client, response).await;
+        assert_eq!(claims["sub"], "machine");
+        assert_eq!(claims["client_id"], "machine");
+
+        let response = token_request(
+            &client,
+            "grant_type=client_credentials&client_id=machine&client_secret=s3cret",
+        )
+        .await;
+        let claims = access_claims(&client, response).await;
+        assert_eq!(claims["client_id"], "machine");
+
+        let response = basic_token_request(
+            &client,
+            "machine:s3cret",
+            "grant_type=client_credentials&client_id=machine&client_secret=s3cret",
+        )
+        .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            error_code(response).await,
+            "invalid_request",
+            "A client must use only one way of authenticating."
+        );
+
+        let response =
+            basic_token_request(&client, "machine:wrong", "grant_type=client_credentials").await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(response.headers().get_one("WWW-Authenticate").is_some());
+        assert_eq!(error_code(response).await, "invalid_client");
+
+        let response = token_request(&client, "grant_type=client_credentials").await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(error_code(response).await, "invalid_client");
+
+        let response = token_request(&client, "grant_type=client_credentials&client_id=spa").await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(
+            error_code(response).await,
+            "unauthorized_client",
+            "A public client has no credentials of its own to exchange."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_credentials_scope_and_audience() {
+        let (client, _) = setup_client().await;
+
+        let response =
+            basic_token_request(&client, "machine:s3cret", "grant_type=client_credentials").await;
+        let claims = access_claims(&client, response).await;
+        assert_eq!(claims["scope"], "read write");
+        assert_eq!(claims["aud"], "https://api.test");
+
+        let response = basic_token_request(
+            &client,
+            "machine:s3cret",
+            "grant_type=client_credentials&scope=read&audience=https://other.test",
+        )
+        .await;
+        let claims = access_claims(&client, response).await;
+        assert_eq!(claims["scope"], "read");
+        assert_eq!(claims["aud"], "https://other.test");
+
+        let response = basic_token_request(
+            &client,
+            "machine:s3cret",
+            "grant_type=client_credentials&scope=read%20admin",
+        )
+        .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(error_code(response).await, "invalid_scope");
+
+        let response = basic_token_request(
+            &client,
+            "machine:s3cret",
+            "grant_type=client_credentials&audience=https://evil.test",
+        )
+        .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(error_code(response).await, "invalid_target");
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_grant_type() {
+        let (client, _) = setup_client().await;
+
+        let response = basic_token_request(
+            &client,
+            "machine:s3cret",
+            "grant_type=password&username=alice&password=password123",
+        )
+        .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(error_code(response).await, "unsupported_grant_type");
+    }
+
+    #[tokio::test]
+    async fn test_token_rate_limit() {
+        let (client, _) = setup_client_with(RateLimits::new(
+            [(
+                policy::TOKEN,
+                RateLimitPolicy::new(2, 60, &[KeyPart::Ip, KeyPart::ClientId]),
+            )],
+            Vec::new(),
+        ))
+        .await;
+        let request =
+            || basic_token_request(&client, "machine:s3cret", "grant_type=client_credentials");
+
+        let response = request().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("RateLimit-Limit"), Some("2"));
+        assert_eq!(
+            response.headers().get_one("RateLimit-Remaining"),
+            Some("1"),
+            "A request must be counted once."
+        );
+        assert!(response.headers().get_one("Retry-After").is_none());
+        assert_eq!(request().await.status(), Status::Ok);
+
+        let response = request().await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert_eq!(response.headers().get_one("RateLimit-Remaining"), Some("0"));
+        let retry_after: u64 = response
+            .headers()
+            .get_one("Retry-After")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(
+            (1..=30).contains(&retry_after),
+            "One request is refilled every 30 seconds."
+        );
+        assert!(response.headers().get_one("RateLimit-Reset").is_some());
+
+        let response = token_request(
+            &client,
+            "grant_type=client_credentials&client_id=machine&client_secret=s3cret",
+        )
+        .await;
+        assert_eq!(
+            response.status(),
+            Status::TooManyRequests,
+            "The client is the same however it authenticates."
+        );
+        let response = token_request(&client, "grant_type=client_credentials&client_id=spa").await;
+        assert_eq!(
+            response.status(),
+            Status::BadRequest,
+            "Other clients must be limited separately."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refresh_survives_server_errors() {
+        let (client, db_pool) = setup_client().await;
+        let response = client
+            .post("/auth?refresh=true")
+            .remote(REMOTE.parse().unwrap())
+            .header(ContentType::JSON)
+            .body(r#"{"username": "alice", "password": "password123", "client_id": "spa"}"#)
+            .dispatch()
+            .await;
+        let tokens: Value = response.into_json().await.unwrap();
+        let refresh = format!(
+            "grant_type=refresh_token&client_id=spa&refresh_token={}",
+            tokens["refresh_token"].as_str().unwrap()
+        );
+
+        // With no key to sign with, the refresh fails without using up the token.
+        let kid: (String,) = sqlx::query_as("SELECT kid FROM keys WHERE state = 'active'")
+            .fetch_one(&db_pool)
+            .await
+            .unwrap();
+        assert!(revoke_key(&db_pool, &kid.0).await.unwrap());
+        let response = token_request(&client, &refresh).await;
+        assert_eq!(response.status(), Status::InternalServerError);
+
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        rotate_keys(&db_pool, &key_cipher, &rotation_policy(), unix_timestamp())
+            .await
+            .unwrap();
+        let response = token_request(&client, &refresh).await;
+        assert_eq!(
+            response.status(),
+            Status::Ok,
+            "A refresh that failed on the server's side must be retryable."
+        );
+    }
+}
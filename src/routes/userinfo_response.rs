@@ -0,0 +1,216 @@
+use crate::auth::{find_user_by_id, has_scope, BearerError, OpenId, Scoped, User};
+use rocket::serde::json::Json;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// The claims returned by the UserInfo endpoint, as described in OpenID Connect Core
+/// 1.0 section 5.3.2.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct UserInfo {
+    /// The user's id, the same as the `sub` of their tokens.
+    pub sub: String,
+    /// The user's username, with the `profile` scope.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    /// When the user's profile last changed, as a timestamp, with the `profile` scope.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+    /// The user's email address, with the `email` scope.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// Whether the user's email address is verified, with the `email` scope.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+}
+
+impl UserInfo {
+    /// Describes `user` to a client that was granted `scope`.
+    pub fn new(user_id: i64, user: User, scope: Option<&str>) -> Self {
+        let mut info = Self {
+            sub: user_id.to_string(),
+            ..Default::default()
+        };
+        if has_scope(scope, "profile") {
+            info.preferred_username = Some(user.username);
+            info.updated_at = user.updated_at;
+        }
+        if has_scope(scope, "email") {
+            info.email_verified = user.email.as_ref().map(|_| user.email_verified);
+            info.email = user.email;
+        }
+        info
+    }
+}
+
+/// Looks up the user an access token was issued to.
+async fn find_userinfo(
+    db_pool: &SqlitePool,
+    scoped: Result<Scoped<OpenId>, BearerError>,
+) -> Result<Json<UserInfo>, BearerError> {
+    let claims = scoped?.claims;
+
+    // Tokens issued to clients themselves have a client id as their subject, and are
+    // refused even if an old client's id happens to be a number.
+    if claims.extra.contains_key("client_id") {
+        return Err(BearerError::InvalidToken);
+    }
+    let user_id = claims
+        .sub
+        .parse::<i64>()
+        .map_err(|_| BearerError::InvalidToken)?;
+    let user = find_user_by_id(db_pool, user_id)
+        .await?
+        .filter(|user| !user.disabled)
+        .ok_or(BearerError::InvalidToken)?;
+    let scope = claims.extra.get("scope").and_then(|scope| scope.as_str());
+
+    Ok(Json(UserInfo::new(user_id, user, scope)))
+}
+
+/// The OpenID Connect UserInfo endpoint.
+///
+/// Returns the profile of the user an access token was issued to, taken from the
+/// `users` table. The token must be sent as `Authorization: Bearer` and must have
+/// been granted the `openid` scope. `sub` is always returned; `preferred_username`
+/// and `updated_at` need the `profile` scope, and `email` and `email_verified` the
+/// `email` scope.
+///
+/// # Errors
+///
+/// Responds with `401 Unauthorized` if the token is missing, invalid, expired or
+/// revoked, and with `403 Forbidden` if it lacks the `openid` scope, each with a
+/// `WWW-Authenticate` challenge as described in RFC 6750 section 3.
+#[get("/userinfo")]
+pub async fn userinfo(
+    db_pool: &rocket::State<SqlitePool>,
+    scoped: Result<Scoped<OpenId>, BearerError>,
+) -> Result<Json<UserInfo>, BearerError> {
+    find_userinfo(db_pool, scoped).await
+}
+
+/// The OpenID Connect UserInfo endpoint, for clients that use `POST`; see [`userinfo`].
+#[post("/userinfo")]
+pub async fn userinfo_post(
+    db_pool: &rocket::State<SqlitePool>,
+    scoped: Result<Scoped<OpenId>, BearerError>,
+) -> Result<Json<UserInfo>, BearerError> {
+    find_userinfo(db_pool, scoped).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::client::{create_client, ClientRegistration};
+    use crate::auth::{create_user, AdminApiKey, LockoutPolicy};
+    use crate::crypto::envelope::MasterKey;
+    use crate::crypto::key_pair::unix_timestamp;
+    use crate::crypto::{KeyCipher, SigningAlgorithm, TokenPolicy};
+    use crate::net::TrustedProxies;
+    use crate::rate_limit::RateLimits;
+    use crate::rotation::{rotate_keys, RotationPolicy};
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use serde_json::Value;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[test]
+    fn test_userinfo_scopes() {
+        let user = || User {
+            id: Some(2),
+            username: "alice".to_string(),
+            email: Some("alice@example.com".to_string()),
+            email_verified: true,
+            updated_at: Some(1_700_000_000),
+            disabled: false,
+            failed_logins: 0,
+            locked_until: None,
+            password_hash: String::new(),
+        };
+
+        assert_eq!(
+            UserInfo::new(2, user(), Some("openid")),
+            UserInfo {
+                sub: "2".to_string(),
+                ..Default::default()
+            }
+        );
+
+        let profile = UserInfo::new(2, user(), Some("openid profile"));
+        assert_eq!(profile.preferred_username.as_deref(), Some("alice"));
+        assert_eq!(profile.updated_at, Some(1_700_000_000));
+        assert_eq!(profile.email, None);
+
+        let email = UserInfo::new(2, user(), Some("openid email"));
+        assert_eq!(email.email.as_deref(), Some("alice@example.com"));
+        assert_eq!(email.email_verified, Some(true));
+        assert_eq!(email.preferred_username, None);
+    }
+
+    #[tokio::test]
+    async fn test_userinfo_client_token() {
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        let alice = create_user(&db_pool, "alice", "alice@example.com", "password123")
+            .await
+            .unwrap();
+        // Registered directly, as a client could be before numeric ids were refused.
+        let client_id = alice.id.unwrap().to_string();
+        create_client(
+            &db_pool,
+            &ClientRegistration {
+                scopes: vec!["openid".to_string(), "profile".to_string()],
+                ..ClientRegistration::new(&client_id, "s3cret")
+            },
+        )
+        .await
+        .unwrap();
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        let rotation_policy = RotationPolicy {
+            algorithms: vec![SigningAlgorithm::ES256],
+            ..Default::default()
+        };
+        rotate_keys(&db_pool, &key_cipher, &rotation_policy, unix_timestamp())
+            .await
+            .unwrap();
+        let rocket = crate::build_rocket(
+            db_pool,
+            key_cipher,
+            rotation_policy,
+            TokenPolicy::default(),
+            LockoutPolicy::default(),
+            AdminApiKey::default(),
+            RateLimits::default(),
+            TrustedProxies::default(),
+        );
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let response = client
+            .post("/token")
+            .remote("127.0.0.1:8000".parse().unwrap())
+            .header(ContentType::Form)
+            .body(format!(
+                "grant_type=client_credentials&client_id={client_id}&client_secret=s3cret"
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let tokens: Value = response.into_json().await.unwrap();
+        let response = client
+            .get("/userinfo")
+            .header(Header::new(
+                "Authorization",
+                format!("Bearer {}", tokens["access_token"].as_str().unwrap()),
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(
+            response.status(),
+            Status::Unauthorized,
+            "A client's token must not read the profile of the user with the same id."
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use crate::crypto::{Claims, KeyCipher, TokenError, TokenPolicy};
+use crate::revocation::validate_token;
+use rocket::serde::json::Json;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+/// A request to verify a token issued by this server.
+#[derive(Debug, Deserialize)]
+pub struct VerifyDTO {
+    /// The encoded JWT.
+    pub token: String,
+    /// The audience the token must have been issued for, if the caller cares.
+    pub audience: Option<String>,
+}
+
+/// Verifies a JWT issued by this server and returns its claims.
+///
+/// This endpoint is for services that cannot verify tokens against the JWKS
+/// themselves. The token must be signed by a published key with that key's
+/// algorithm, must be within its `nbf`/`exp` window (allowing `TOKEN_LEEWAY_SECS` of
+/// clock skew), must have been issued by `ISSUER` for `audience`, if one is given,
+/// and must not have been revoked.
+///
+/// # Errors
+///
+/// Responds with `401 Unauthorized` and a JSON body such as
+/// `{"error": "token has expired"}` if the token fails any check.
+#[post("/verify", data = "<request>")]
+pub async fn verify(
+    db_pool: &rocket::State<SqlitePool>,
+    key_cipher: &rocket::State<KeyCipher>,
+    token_policy: &rocket::State<TokenPolicy>,
+    request: Json<VerifyDTO>,
+) -> Result<Json<Claims>, TokenError> {
+    let claims = validate_token(
+        db_pool,
+        key_cipher,
+        token_policy,
+        &request.token,
+        request.audience.as_deref(),
+    )
+    .await?;
+
+    Ok(Json(claims))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::client::{create_client, ClientRegistration};
+    use crate::auth::{AdminApiKey, LockoutPolicy};
+    use crate::crypto::envelope::MasterKey;
+    use crate::crypto::key_pair::unix_timestamp;
+    use crate::crypto::{KeyCipher, SigningAlgorithm, TokenPolicy};
+    use crate::net::TrustedProxies;
+    use crate::rate_limit::RateLimits;
+    use crate::revocation::{revoke_subject, revoke_token};
+    use crate::rotation::{rotate_keys, RotationPolicy};
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::{Client, LocalResponse};
+    use serde_json::{json, Value};
+    use sqlx::sqlite::SqlitePoolOptions;
+    use sqlx::SqlitePool;
+
+    const REMOTE: &str = "127.0.0.1:8000";
+
+    /// A server with the client `machine`, which gets tokens for `https://api.test`.
+    async fn setup_client() -> (Client, SqlitePool) {
+        let db_pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        create_client(
+            &db_pool,
+            &ClientRegistration {
+                audiences: vec!["https://api.test".to_string()],
+                ..ClientRegistration::new("machine", "s3cret")
+            },
+        )
+        .await
+        .unwrap();
+        let key_cipher = KeyCipher::new(MasterKey::from_secret("test-secret"));
+        let rotation_policy = RotationPolicy {
+            algorithms: vec![SigningAlgorithm::ES256],
+            ..Default::default()
+        };
+        rotate_keys(&db_pool, &key_cipher, &rotation_policy, unix_timestamp())
+            .await
+            .unwrap();
+        let rocket = crate::build_rocket(
+            db_pool.clone(),
+            key_cipher,
+            rotation_policy,
+            TokenPolicy::default(),
+            LockoutPolicy::default(),
+            AdminApiKey::default(),
+            RateLimits::default(),
+            TrustedProxies::default(),
+        );
+
+        (Client::tracked(rocket).await.unwrap(), db_pool)
+    }
+
+    async fn issue_token(client: &Client) -> String {
+        let response = client
+            .post("/token")
+            .remote(REMOTE.parse().unwrap())
+            .header(ContentType::Form)
+            .body("grant_type=client_credentials&client_id=machine&client_secret=s3cret")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let tokens: Value = response.into_json().await.unwrap();
+        tokens["access_token"].as_str().unwrap().to_string()
+    }
+
+    async fn verify(client: &Client, body: Value) -> LocalResponse<'_> {
+        client
+            .post("/verify")
+            .remote(REMOTE.parse().unwrap())
+            .json(&body)
+            .dispatch()
+            .await
+    }
+
+    async fn verify_error(response: LocalResponse<'_>) -> String {
+        assert_eq!(response.status(), Status::Unauthorized);
+        let error: Value = response.into_json().await.unwrap();
+        error["error"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_verify_audience() {
+        let (client, _) = setup_client().await;
+        let token = issue_token(&client).await;
+
+        let response = verify(&client, json!({ "token": token })).await;
+        assert_eq!(response.status(), Status::Ok);
+        let claims: Value = response.into_json().await.unwrap();
+        assert_eq!(claims["sub"], "machine");
+        assert_eq!(claims["aud"], "https://api.test");
+
+        let response = verify(
+            &client,
+            json!({ "token": token, "audience": "https://api.test" }),
+        )
+        .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = verify(
+            &client,
+            json!({ "token": token, "audience": "https://other.test" }),
+        )
+        .await;
+        assert_eq!(
+            verify_error(response).await,
+            "token was issued for another audience"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_malformed_token() {
+        let (client, _) = setup_client().await;
+
+        let response = verify(&client, json!({ "token": "not.a.jwt" })).await;
+        assert_eq!(verify_error(response).await, "token is malformed");
+
+        let token = issue_token(&client).await;
+        let (unsigned, _) = token.rsplit_once('.').unwrap();
+        let response = verify(&client, json!({ "token": format!("{unsigned}.AAAA") })).await;
+        assert_eq!(verify_error(response).await, "token signature is invalid");
+    }
+
+    #[tokio::test]
+    async fn test_verify_revoked_token() {
+        let (client, db_pool) = setup_client().await;
+
+        let token = issue_token(&client).await;
+        let response = verify(&client, json!({ "token": token })).await;
+        let claims: Value = response.into_json().await.unwrap();
+        revoke_token(
+            &db_pool,
+            claims["jti"].as_str().unwrap(),
+            claims["exp"].as_u64().unwrap(),
+            0,
+        )
+        .await
+        .unwrap();
+        let response = verify(&client, json!({ "token": token })).await;
+        assert_eq!(verify_error(response).await, "token has been revoked");
+
+        let token = issue_token(&client).await;
+        revoke_subject(&db_pool, "machine", unix_timestamp())
+            .await
+            .unwrap();
+        let response = verify(&client, json!({ "token": token })).await;
+        assert_eq!(
+            verify_error(response).await,
+            "token has been revoked",
+            "Revoking a subject revokes every token issued to it up to now."
+        );
+    }
+}